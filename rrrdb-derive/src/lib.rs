@@ -0,0 +1,115 @@
+// #[derive(FromRow)] and #[derive(ToRow)] for structs with named fields.
+//
+// a field maps to the column of its name, or of `#[rrrdb(rename = "column")]`.
+// `#[rrrdb(crate = "path")]` on the struct changes the path to rrrdb, which is `::rrrdb::rrrdb`.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitStr, Path};
+
+#[proc_macro_derive(FromRow, attributes(rrrdb))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_row(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(ToRow, attributes(rrrdb))]
+pub fn derive_to_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_to_row(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_from_row(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = crate_path(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let fields = columns(input)?.into_iter().map(|(field, column)| {
+        quote! { #field: record.try_get(#column)? }
+    });
+    Ok(quote! {
+        impl #impl_generics #krate::FromRow for #name #ty_generics #where_clause {
+            fn from_row(record: &#krate::Record) -> ::std::result::Result<Self, #krate::DBError> {
+                ::std::result::Result::Ok(Self { #(#fields),* })
+            }
+        }
+    })
+}
+
+fn expand_to_row(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = crate_path(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let values = columns(input)?.into_iter().map(|(field, column)| {
+        quote! {
+            (#column, #krate::FieldValue::from(::std::clone::Clone::clone(&self.#field)))
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics #krate::ToRow for #name #ty_generics #where_clause {
+            fn to_row(&self) -> ::std::vec::Vec<(&'static str, #krate::FieldValue)> {
+                ::std::vec![#(#values),*]
+            }
+        }
+    })
+}
+
+// the fields with the names of their columns
+fn columns(input: &DeriveInput) -> syn::Result<Vec<(Ident, String)>> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "only a struct with named fields can be mapped to a row",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "only a struct can be mapped to a row",
+            ))
+        }
+    };
+    let mut columns = vec![];
+    for field in fields {
+        // named fields always have an ident
+        let ident = match &field.ident {
+            Some(ident) => ident.clone(),
+            None => continue,
+        };
+        let mut column = ident.to_string().trim_start_matches("r#").to_string();
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("rrrdb")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    column = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown rrrdb attribute on a field"))
+                }
+            })?;
+        }
+        columns.push((ident, column));
+    }
+    Ok(columns)
+}
+
+fn crate_path(input: &DeriveInput) -> syn::Result<Path> {
+    let mut path: Path = syn::parse_quote!(::rrrdb::rrrdb);
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("rrrdb")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                path = meta.value()?.parse::<LitStr>()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unknown rrrdb attribute on a struct"))
+            }
+        })?;
+    }
+    Ok(path)
+}
@@ -0,0 +1,522 @@
+// an interactive SQL shell on a data directory, or a runner of a script file.
+//
+//   rrrdb [--mode table|csv|json] [--database name] <path> [script.sql]
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+    process,
+};
+
+use rrrdb::rrrdb::{split_script, DBError, FieldValue, OkDBResult, ResultSet, RrrDB, Session};
+
+const USAGE: &str = "usage: rrrdb [--mode table|csv|json] [--database name] <path> [script.sql]";
+const HELP: &str = "\
+statements end with ';' and may span lines.
+.tables            list the tables of the current database
+.schema [table]    show the CREATE TABLE statements
+.mode [mode]       show or set the output mode: table, csv or json
+.history           list the statements entered so far
+.help              show this message
+.quit              exit";
+const HISTORY_FILE: &str = ".rrrdb_history";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Table,
+    Csv,
+    Json,
+}
+
+impl Mode {
+    fn find(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_ref() {
+            "table" => Some(Mode::Table),
+            "csv" => Some(Mode::Csv),
+            "json" => Some(Mode::Json),
+            _ => None,
+        }
+    }
+}
+
+struct Options {
+    mode: Mode,
+    database_name: Option<String>,
+    path: String,
+    script: Option<String>,
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut mode = Mode::Table;
+    let mut database_name = None;
+    let mut positionals = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--mode" => {
+                let value = args.next().ok_or("--mode needs a value")?;
+                mode = Mode::find(&value).ok_or(format!("unknown mode {}", value))?;
+            }
+            "--database" => {
+                database_name = Some(args.next().ok_or("--database needs a value")?);
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positionals.push(arg),
+        }
+    }
+    let mut positionals = positionals.into_iter();
+    let path = positionals.next().ok_or(USAGE)?;
+    let script = positionals.next();
+    if positionals.next().is_some() {
+        return Err(USAGE.to_string());
+    }
+    Ok(Options {
+        mode,
+        database_name,
+        path,
+        script,
+    })
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+    let rrrdb = match RrrDB::open(&options.path) {
+        Ok(rrrdb) => rrrdb,
+        Err(err) => {
+            eprintln!("Error: can't open {}. err: {}", options.path, err);
+            process::exit(1);
+        }
+    };
+    for recovery in rrrdb.recovery() {
+        eprintln!("Warning: {}", recovery);
+    }
+    let mut session = rrrdb.session();
+    if let Some(database_name) = &options.database_name {
+        if let Err(err) = session.execute(&format!("USE {}", database_name)) {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+    let code = match &options.script {
+        Some(script) => run_script(&mut session, options.mode, script),
+        None => run_repl(&mut session, options.mode),
+    };
+    process::exit(code);
+}
+
+// stops at the first failing statement
+fn run_script(session: &mut Session, mode: Mode, path: &str) -> i32 {
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(err) => {
+            eprintln!("Error: can't read {}. err: {}", path, err);
+            return 1;
+        }
+    };
+    for (index, statement) in split_script(&script).iter().enumerate() {
+        match session.execute(&statement.text) {
+            Ok(result) => print_result(&result, mode),
+            Err(err) => {
+                eprintln!(
+                    "Error in statement {} at line {}: {}",
+                    index + 1,
+                    statement.location.line,
+                    err
+                );
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+fn run_repl(session: &mut Session, mut mode: Mode) -> i32 {
+    let mut history = History::load();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut buffer = String::new();
+    loop {
+        let prompt = match (buffer.trim().is_empty(), session.database_name()) {
+            (true, Some(database_name)) => format!("rrrdb({})> ", database_name),
+            (true, None) => "rrrdb> ".to_string(),
+            (false, _) => "   ...> ".to_string(),
+        };
+        print!("{}", prompt);
+        io::stdout().flush().ok();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(err)) => {
+                eprintln!("Error: {}", err);
+                return 1;
+            }
+            None => break,
+        };
+        if buffer.trim().is_empty() && line.trim_start().starts_with('.') {
+            history.push(line.trim());
+            match run_meta_command(session, &mut mode, &history, line.trim()) {
+                Some(()) => continue,
+                None => return 0,
+            }
+        }
+        buffer.push_str(&line);
+        buffer.push('\n');
+        let statements = split_script(&buffer);
+        buffer.clear();
+        for statement in statements {
+            if statement.terminated {
+                history.push(&format!("{};", statement.text));
+                execute(session, mode, &statement.text);
+            } else {
+                // goes on in the next lines
+                buffer = statement.text + "\n";
+            }
+        }
+    }
+    // the last statement may miss its ';'
+    if !buffer.trim().is_empty() {
+        history.push(buffer.trim());
+        execute(session, mode, &buffer);
+    }
+    println!();
+    0
+}
+
+fn execute(session: &mut Session, mode: Mode, statement: &str) {
+    match session.execute(statement) {
+        Ok(result) => print_result(&result, mode),
+        Err(err) => eprintln!("Error: {}", err),
+    }
+}
+
+// None to quit
+fn run_meta_command(
+    session: &mut Session,
+    mode: &mut Mode,
+    history: &History,
+    line: &str,
+) -> Option<()> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let result: Result<(), DBError> = match &words[..] {
+        [".quit"] | [".exit"] => return None,
+        [".help"] => {
+            println!("{}", HELP);
+            Ok(())
+        }
+        [".tables"] => session.table_names().map(|names| {
+            for name in names {
+                println!("{}", name);
+            }
+        }),
+        [".schema"] => session.table_names().and_then(|names| {
+            for name in names {
+                println!("{};", session.table_definition(&name)?);
+            }
+            Ok(())
+        }),
+        [".schema", table_name] => session
+            .table_definition(table_name)
+            .map(|definition| println!("{};", definition)),
+        [".mode"] => {
+            println!("{:?}", mode);
+            Ok(())
+        }
+        [".mode", name] => {
+            match Mode::find(name) {
+                Some(new_mode) => *mode = new_mode,
+                None => eprintln!("unknown mode {}, which is one of table, csv or json", name),
+            }
+            Ok(())
+        }
+        [".history"] => {
+            for (i, entry) in history.entries.iter().enumerate() {
+                println!("{:>5}  {}", i + 1, entry);
+            }
+            Ok(())
+        }
+        _ => {
+            eprintln!("unknown command {}, see .help", line);
+            Ok(())
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+    }
+    Some(())
+}
+
+// kept in ~/.rrrdb_history across runs
+struct History {
+    entries: Vec<String>,
+    file: Option<fs::File>,
+}
+
+impl History {
+    const LIMIT: usize = 1000;
+
+    fn load() -> Self {
+        let path = match env::var("HOME") {
+            Ok(home) => std::path::Path::new(&home).join(HISTORY_FILE),
+            Err(_) => {
+                return Self {
+                    entries: vec![],
+                    file: None,
+                }
+            }
+        };
+        let mut entries: Vec<String> = fs::read_to_string(&path)
+            .map(|history| history.lines().map(|line| line.to_string()).collect())
+            .unwrap_or_default();
+        let skip = entries.len().saturating_sub(Self::LIMIT);
+        entries.drain(..skip);
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .ok();
+        Self { entries, file }
+    }
+
+    // a statement spanning lines is kept in one line
+    fn push(&mut self, entry: &str) {
+        let entry = entry.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", entry).ok();
+        }
+        self.entries.push(entry);
+    }
+}
+
+fn print_result(result: &OkDBResult, mode: Mode) {
+    match result {
+        OkDBResult::SelectResult(result_set) => print_result_set(result_set, mode),
+        // keys generated by sequences
+        OkDBResult::InsertResult(result_set) if !result_set.is_empty() => {
+            print_result_set(result_set, mode)
+        }
+        OkDBResult::InsertResult(_) | OkDBResult::ExecutionResult => {
+            if mode == Mode::Table {
+                println!("OK");
+            }
+        }
+    }
+}
+
+fn print_result_set(result_set: &ResultSet, mode: Mode) {
+    let output = match mode {
+        Mode::Table => format_table(result_set),
+        Mode::Csv => format_csv(result_set),
+        Mode::Json => format_json(result_set),
+    };
+    print!("{}", output);
+}
+
+fn format_table(result_set: &ResultSet) -> String {
+    let columns = result_set.columns();
+    let rows: Vec<Vec<String>> = result_set
+        .iter()
+        .map(|record| {
+            record
+                .values()
+                .iter()
+                .map(|value| text(value).unwrap_or("NULL".to_string()))
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(|value| value.chars().count())
+                .chain(std::iter::once(column.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let border = format!(
+        "+{}+\n",
+        widths
+            .iter()
+            .map(|width| "-".repeat(width + 2))
+            .collect::<Vec<_>>()
+            .join("+")
+    );
+    let format_row = |values: Vec<&str>| {
+        let cells: Vec<String> = values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| {
+                format!(" {}{} ", value, " ".repeat(width - value.chars().count()))
+            })
+            .collect();
+        format!("|{}|\n", cells.join("|"))
+    };
+    let mut output = border.clone();
+    output.push_str(&format_row(columns));
+    output.push_str(&border);
+    for row in &rows {
+        output.push_str(&format_row(
+            row.iter().map(|value| value.as_str()).collect(),
+        ));
+    }
+    output.push_str(&border);
+    output.push_str(&format!(
+        "({} row{})\n",
+        rows.len(),
+        if rows.len() == 1 { "" } else { "s" }
+    ));
+    output
+}
+
+// NULL is an empty field
+fn format_csv(result_set: &ResultSet) -> String {
+    let quote = |value: &str| {
+        if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    };
+    let mut output = String::new();
+    let header: Vec<String> = result_set
+        .columns()
+        .into_iter()
+        .map(|column| quote(column))
+        .collect();
+    output.push_str(&header.join(","));
+    output.push('\n');
+    for record in result_set {
+        let fields: Vec<String> = record
+            .values()
+            .iter()
+            .map(|value| text(value).map_or(String::new(), |value| quote(&value)))
+            .collect();
+        output.push_str(&fields.join(","));
+        output.push('\n');
+    }
+    output
+}
+
+// an array of objects by column name, with JSON columns embedded as they are.
+// keys are in the order of the columns
+fn format_json(result_set: &ResultSet) -> String {
+    let columns = result_set.columns();
+    let rows: Vec<String> = result_set
+        .iter()
+        .map(|record| {
+            let fields: Vec<String> = columns
+                .iter()
+                .zip(record.values())
+                .map(|(column, value)| {
+                    let value = match value {
+                        FieldValue::Int(i) => serde_json::Value::from(*i),
+                        FieldValue::Json(doc) => serde_json::from_str(doc)
+                            .unwrap_or_else(|_| serde_json::Value::from(doc.as_str())),
+                        FieldValue::Null => serde_json::Value::Null,
+                        other => serde_json::Value::from(text(other).unwrap_or_default()),
+                    };
+                    format!("{}:{}", serde_json::Value::from(*column), value)
+                })
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        })
+        .collect();
+    format!("[{}]\n", rows.join(","))
+}
+
+// None for NULL
+fn text(value: &FieldValue) -> Option<String> {
+    match value {
+        FieldValue::Int(i) => Some(i.to_string()),
+        FieldValue::Text(s) | FieldValue::Json(s) => Some(s.to_owned()),
+        FieldValue::Bytes(bytes) => Some(
+            bytes
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>(),
+        ),
+        FieldValue::Null => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rrrdb::rrrdb::{FieldMetadata, Record, ResultMetadata};
+
+    use super::*;
+
+    #[test]
+    fn script() {
+        let dir = "./test_tmp_shell_script";
+        if std::path::Path::new(dir).exists() {
+            fs::remove_dir_all(dir).unwrap();
+        }
+        fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/001_users.sql", dir);
+        fs::write(
+            &path,
+            "/*\n * users; and their names\n */\n\
+             CREATE TABLE users (id integer, name varchar); -- the table\n\
+             -- Alice; the first user\n\
+             INSERT INTO users VALUES (1, 'a;b'); /* ; */\n",
+        )
+        .unwrap();
+        let rrrdb = RrrDB::new(&format!("{}/data", dir));
+        let mut session = rrrdb.session();
+        session.execute("USE test_db").unwrap();
+        assert_eq!(run_script(&mut session, Mode::Csv, &path), 0);
+        assert_eq!(session.table_names().unwrap(), vec!["users".to_string()]);
+
+        // stops at the first failing statement
+        fs::write(
+            &path,
+            "-- broken\nINSERT INTO teams VALUES (1);\nCREATE TABLE teams (id integer);",
+        )
+        .unwrap();
+        assert_eq!(run_script(&mut session, Mode::Csv, &path), 1);
+        assert_eq!(session.table_names().unwrap(), vec!["users".to_string()]);
+    }
+
+    #[test]
+    fn format() {
+        let result_set = ResultSet::new(
+            vec![
+                Record::new(vec![FieldValue::Int(1), "a,\"b\"".into(), FieldValue::Null]),
+                Record::new(vec![
+                    FieldValue::Int(22),
+                    FieldValue::Null,
+                    FieldValue::Json("{\"x\":1}".to_string()),
+                ]),
+            ],
+            ResultMetadata::new(vec![
+                FieldMetadata::new("id", "integer"),
+                FieldMetadata::new("name", "varchar"),
+                FieldMetadata::new("attrs", "json"),
+            ]),
+        );
+        assert_eq!(
+            format_table(&result_set),
+            "+----+-------+---------+\n\
+             | id | name  | attrs   |\n\
+             +----+-------+---------+\n\
+             | 1  | a,\"b\" | NULL    |\n\
+             | 22 | NULL  | {\"x\":1} |\n\
+             +----+-------+---------+\n\
+             (2 rows)\n"
+        );
+        assert_eq!(
+            format_csv(&result_set),
+            "id,name,attrs\n1,\"a,\"\"b\"\"\",\n22,,\"{\"\"x\"\":1}\"\n"
+        );
+        assert_eq!(
+            format_json(&result_set),
+            "[{\"id\":1,\"name\":\"a,\\\"b\\\"\",\"attrs\":null},{\"id\":22,\"name\":null,\"attrs\":{\"x\":1}}]\n"
+        );
+    }
+}
@@ -1,35 +1,191 @@
-use parser::Parser;
+use std::{
+    convert::TryFrom,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use parser::{Parser, Statement};
 
 use crate::rrrdb::schema::ColumnType;
-use crate::rrrdb::storage::Storage;
+use crate::rrrdb::storage::SharedStorage;
 
-use self::{parser::ParserError, sql::executor::Executor, sql::planner::Planner};
+use self::parser::ParserError;
+pub use self::parser::{Location, ScriptStatement};
+pub use self::prepared::{Params, PreparedStatement};
+pub use self::row::{FromField, FromRow, ToRow};
+pub use self::session::Session;
+pub use self::sql::executor::Rows;
+pub use self::storage::Recovery;
+#[cfg(feature = "derive")]
+pub use rrrdb_derive::{FromRow, ToRow};
 
 mod parser;
+mod prepared;
+mod row;
 mod schema;
+mod session;
 mod sql;
 mod storage;
 
+// a handle to a database, which can be shared or cloned across threads.
+// statements run concurrently, except CREATE statements, which run one at a time.
 pub struct RrrDB {
-    pub(crate) underlying: Storage,
+    pub(crate) underlying: Arc<SharedStorage>,
+    // holds the transaction of this handle. a clone starts with none
+    session: Mutex<Session>,
 }
 
 impl RrrDB {
+    // panics if the database can't be opened, which `open` returns instead
     pub fn new(path: &str) -> Self {
-        Self {
-            underlying: Storage::new(path),
+        Self::open(path).unwrap()
+    }
+
+    // opens the database at `path`, creating it if missing, and recovers from a crash in
+    // CREATE TABLE or upgrades what earlier versions left
+    pub fn open(path: &str) -> Result<Self, DBError> {
+        let underlying = SharedStorage::open(path)?;
+        Ok(Self {
+            session: Mutex::new(Session::new(&underlying)),
+            underlying,
+        })
+    }
+
+    // what was done on opening to recover from a crash in CREATE TABLE
+    pub fn recovery(&self) -> Vec<Recovery> {
+        self.underlying.recovery()
+    }
+
+    // opens a connection, which selects its database with USE
+    pub fn session(&self) -> Session {
+        Session::new(&self.underlying)
+    }
+
+    // CREATE statements wait for the statements of every other session to end, and fail with
+    // ObjectInUse at once while any session, this one or another, has rows being streamed by
+    // `query` or a REPEATABLE READ transaction in progress
+    pub fn execute(&self, database_name: &str, query: &str) -> DBResult {
+        let statement = Parser::parse_sql(Some(database_name.to_string()), query)?;
+        self.execute_statement(database_name, statement)
+            .map_err(|e| e.with_source(query))
+    }
+
+    // runs the statements of a script separated by `;` in order, like a migration file.
+    // the whole script is parsed first, and it stops at the first failing statement
+    pub fn execute_batch(
+        &self,
+        database_name: &str,
+        script: &str,
+    ) -> Result<Vec<OkDBResult>, BatchError> {
+        let statements = Parser::parse_script(Some(database_name.to_string()), script).map_err(
+            |(index, location, e)| BatchError {
+                index,
+                location,
+                error: e.into(),
+            },
+        )?;
+        statements
+            .into_iter()
+            .enumerate()
+            .map(|(index, (location, statement))| {
+                self.execute_statement(database_name, statement)
+                    .map_err(|error| BatchError {
+                        index,
+                        location,
+                        error: error.with_source(script),
+                    })
+            })
+            .collect()
+    }
+
+    fn execute_statement(&self, database_name: &str, statement: Statement) -> DBResult {
+        let database_name = Some(database_name.to_string());
+        let exclusive = matches!(statement, Statement::Transaction(_) | Statement::Session(_));
+        self.with_session(exclusive, |session| {
+            session.execute_statement(database_name, statement)
+        })
+    }
+
+    // streams the rows of a SELECT, which are read as they are iterated
+    pub fn query(&self, database_name: &str, query: &str) -> Result<Rows<'_>, DBError> {
+        self.with_session(false, |session| {
+            session.query_on(Some(database_name.to_string()), query)
+        })
+    }
+
+    // the rows of a SELECT, each built into a T
+    pub fn query_as<T: FromRow>(
+        &self,
+        database_name: &str,
+        query: &str,
+    ) -> Result<Vec<T>, DBError> {
+        self.with_session(false, |session| {
+            session.query_as_on(Some(database_name.to_string()), query)
+        })
+    }
+
+    // inserts the columns of the row into the table
+    pub fn insert<T: ToRow>(&self, database_name: &str, table_name: &str, row: &T) -> DBResult {
+        self.with_session(false, |session| {
+            session.insert_on(Some(database_name.to_string()), table_name, row)
+        })
+    }
+
+    // the names of the tables in the database, in the order they were created
+    pub fn table_names(&self, database_name: &str) -> Result<Vec<String>, DBError> {
+        self.with_session(false, |session| {
+            session.table_names_on(Some(database_name.to_string()))
+        })
+    }
+
+    // the CREATE TABLE statement of the table
+    pub fn table_definition(
+        &self,
+        database_name: &str,
+        table_name: &str,
+    ) -> Result<String, DBError> {
+        self.with_session(false, |session| {
+            session.table_definition_on(Some(database_name.to_string()), table_name)
+        })
+    }
+
+    pub fn prepare(&self, database_name: &str, query: &str) -> Result<PreparedStatement, DBError> {
+        self.with_session(true, |session| {
+            session.prepare_on(Some(database_name.to_string()), query)
+        })
+    }
+
+    pub fn execute_prepared<P: Into<Params>>(
+        &self,
+        prepared: &PreparedStatement,
+        params: P,
+    ) -> DBResult {
+        self.with_session(false, |session| session.execute_prepared(prepared, params))
+    }
+
+    // the session of this handle while it's in a transaction. otherwise a new one,
+    // which doesn't block the other statements of this handle
+    fn with_session<T, F: FnOnce(&mut Session) -> T>(&self, exclusive: bool, f: F) -> T {
+        let mut session = self.session.lock().unwrap_or_else(PoisonError::into_inner);
+        if exclusive || session.in_transaction() {
+            return f(&mut session);
         }
+        drop(session);
+        f(&mut Session::new(&self.underlying))
     }
+}
 
-    pub fn execute(&mut self, database_name: &str, query: &str) -> DBResult {
-        let plan = {
-            let statement = Parser::parse_sql(Some(database_name.to_string()), query)
-                .map_err(|pe: ParserError| pe.to_string())?;
-            let mut planner: Planner = Planner::new(database_name, &mut self.underlying, statement);
-            planner.plan()
-        };
-        let mut executor = Executor::new(&mut self.underlying, plan);
-        executor.execute()
+// the statements of a script separated by `;` as they are written, skipping comments and `;` in
+// quotes as `execute_batch` does, e.g. to run them one at a time
+pub fn split_script(script: &str) -> Vec<ScriptStatement> {
+    Parser::split_script(script)
+}
+
+impl Clone for RrrDB {
+    fn clone(&self) -> Self {
+        Self {
+            underlying: self.underlying.clone(),
+            session: Mutex::new(Session::new(&self.underlying)),
+        }
     }
 }
 
@@ -38,36 +194,262 @@ pub type DBResult = Result<OkDBResult, DBError>;
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum OkDBResult {
     SelectResult(ResultSet),
+    // keys generated by sequences for the inserted row, if any
+    InsertResult(ResultSet),
     ExecutionResult,
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct DBError {
-    pub(crate) message: String,
+pub enum DBError {
+    // the query can't be tokenized or parsed, at the location in the source line
+    Syntax {
+        message: String,
+        location: Option<Location>,
+        source_line: String,
+    },
+    NoDatabaseSelected,
+    UnknownTable(String),
+    UnknownColumn(String),
+    UnknownSequence(String),
+    UnknownFunction(String),
+    // e.g. "table users"
+    AlreadyExists(String),
+    // CREATE statements contradicting themselves or the schema
+    InvalidDefinition(String),
+    // statements which can't be applied to the schema, e.g. too many values
+    InvalidStatement(String),
+    TypeMismatch {
+        column: String,
+        column_type: String,
+        value: String,
+    },
+    ConstraintViolation {
+        constraint: String,
+        detail: String,
+    },
+    // parameters not matching the placeholders of a prepared statement
+    InvalidParameter(String),
+    // e.g. COMMIT without a transaction
+    InvalidTransactionState(String),
+    // someone else has changed what the statement or the transaction wrote. may be retried
+    SerializationFailure(String),
+    // e.g. a ColumnFamily to create while snapshots are read. may be retried
+    ObjectInUse(String),
+    Unsupported(String),
+    // failures of RocksDB
+    Storage(String),
+    // stored data which can't be read
+    Corruption(String),
+    // bugs
+    Internal(String),
+    // an error about what's written at the location, e.g. an unknown column. the source line
+    // is filled in by the entry point which has the query
+    Located {
+        error: Box<DBError>,
+        location: Location,
+        source_line: String,
+    },
 }
 impl DBError {
-    pub(crate) fn new(message: String) -> Self {
-        Self { message }
+    // nodes built by hand have no location, and their errors are left as they are
+    pub(crate) fn at(self, location: Option<Location>) -> Self {
+        match (self, location) {
+            (error @ DBError::Located { .. }, _) | (error, None) => error,
+            (error, Some(location)) => DBError::Located {
+                error: Box::new(error),
+                location,
+                source_line: String::new(),
+            },
+        }
+    }
+    pub(crate) fn with_source(self, query: &str) -> Self {
+        match self {
+            DBError::Located {
+                error,
+                location,
+                source_line,
+            } if source_line.is_empty() => DBError::Located {
+                error,
+                location,
+                source_line: query
+                    .lines()
+                    .nth(location.line - 1)
+                    .unwrap_or_default()
+                    .to_string(),
+            },
+            error => error,
+        }
+    }
+    // where the error is in the query, if it's known
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            DBError::Syntax { location, .. } => *location,
+            DBError::Located { location, .. } => Some(*location),
+            _ => None,
+        }
+    }
+    // the error itself, for matching on its kind
+    pub fn without_location(&self) -> &DBError {
+        match self {
+            DBError::Located { error, .. } => error,
+            error => error,
+        }
     }
     pub(crate) fn namespace_not_found(namespace: &storage::Namespace) -> Self {
-        Self {
-            message: format!("ColumnFamily({}) not found", namespace.cf_name()),
+        DBError::Storage(format!("ColumnFamily({}) not found", namespace.cf_name()))
+    }
+    pub(crate) fn constraint_violation(constraint_name: &str, detail: String) -> Self {
+        DBError::ConstraintViolation {
+            constraint: constraint_name.to_string(),
+            detail,
+        }
+    }
+    pub(crate) fn type_mismatch(column: &schema::Column, value: &parser::Value) -> Self {
+        DBError::TypeMismatch {
+            column: column.name.to_string(),
+            column_type: column.column_type.to_string(),
+            value: format!("{:?}", value),
+        }
+    }
+}
+
+impl std::fmt::Display for DBError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DBError::Syntax {
+                message,
+                location: Some(location),
+                source_line,
+            } => {
+                write!(
+                    f,
+                    "syntax error at line {}, column {}: {}",
+                    location.line, location.column, message
+                )?;
+                write_caret(f, source_line, location)
+            }
+            DBError::Syntax {
+                message,
+                location: None,
+                ..
+            } => write!(f, "syntax error: {}", message),
+            DBError::NoDatabaseSelected => write!(f, "no database is selected"),
+            DBError::UnknownTable(name) => write!(f, "table {} not found", name),
+            DBError::UnknownColumn(name) => write!(f, "column {} not found", name),
+            DBError::UnknownSequence(name) => write!(f, "sequence {} not found", name),
+            DBError::UnknownFunction(name) => write!(f, "function {} not found", name),
+            DBError::AlreadyExists(name) => write!(f, "{} already exists", name),
+            DBError::TypeMismatch {
+                column,
+                column_type,
+                value,
+            } => write!(
+                f,
+                "type mismatch for column {}({}). value: {}",
+                column, column_type, value
+            ),
+            DBError::ConstraintViolation { constraint, detail } => {
+                write!(f, "constraint violation: {}. {}", constraint, detail)
+            }
+            DBError::SerializationFailure(detail) => {
+                write!(f, "could not serialize access due to {}", detail)
+            }
+            DBError::Unsupported(message) => write!(f, "not supported: {}", message),
+            DBError::Corruption(message) => write!(f, "corrupted data: {}", message),
+            DBError::Internal(message) => write!(f, "internal error: {}", message),
+            DBError::InvalidDefinition(message)
+            | DBError::InvalidStatement(message)
+            | DBError::InvalidParameter(message)
+            | DBError::InvalidTransactionState(message)
+            | DBError::ObjectInUse(message)
+            | DBError::Storage(message) => write!(f, "{}", message),
+            DBError::Located {
+                error,
+                location,
+                source_line,
+            } => {
+                write!(
+                    f,
+                    "{} at line {}, column {}",
+                    error, location.line, location.column
+                )?;
+                if source_line.is_empty() {
+                    return Ok(());
+                }
+                write_caret(f, source_line, location)
+            }
         }
     }
 }
 
+// the source line, and a caret under the location in it
+fn write_caret(
+    f: &mut std::fmt::Formatter<'_>,
+    source_line: &str,
+    location: &Location,
+) -> std::fmt::Result {
+    // tabs are kept so that the caret lines up
+    let indent: String = source_line
+        .chars()
+        .take(location.column - 1)
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+    write!(f, "\n{}\n{}^", source_line, indent)
+}
+
+impl std::error::Error for DBError {}
+
 impl From<rocksdb::Error> for DBError {
     fn from(e: rocksdb::Error) -> Self {
-        Self {
-            message: e.into_string(),
+        DBError::Storage(e.into_string())
+    }
+}
+impl From<ParserError> for DBError {
+    fn from(e: ParserError) -> Self {
+        match e {
+            ParserError::TokenizeError(message) | ParserError::ParseError(message) => {
+                DBError::Syntax {
+                    message,
+                    location: None,
+                    source_line: String::new(),
+                }
+            }
+            ParserError::SyntaxError {
+                message,
+                location,
+                source_line,
+            } => DBError::Syntax {
+                message,
+                location: Some(location),
+                source_line,
+            },
         }
     }
 }
-impl From<String> for DBError {
-    fn from(e: String) -> Self {
-        Self { message: e }
+
+// the statement of a batch which failed. the statements before it have been run
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BatchError {
+    // counts from 0, skipping empty statements
+    pub index: usize,
+    // where the statement starts in the script
+    pub location: Location,
+    pub error: DBError,
+}
+
+impl std::fmt::Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "statement {} at line {} failed: {}",
+            self.index + 1,
+            self.location.line,
+            self.error
+        )
     }
 }
 
+impl std::error::Error for BatchError {}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ResultSet {
     records: Vec<Record>,
@@ -75,30 +457,186 @@ pub struct ResultSet {
 }
 impl ResultSet {
     pub fn new(records: Vec<Record>, metadata: ResultMetadata) -> Self {
+        let shared = Arc::new(metadata.clone());
+        let records = records
+            .into_iter()
+            .map(|record| record.with_metadata(&shared))
+            .collect();
         Self { records, metadata }
     }
     pub fn get(&self, index: usize) -> Option<&Record> {
         self.records.get(index)
     }
+    pub fn iter(&self) -> std::slice::Iter<'_, Record> {
+        self.records.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    pub fn metadata(&self) -> &ResultMetadata {
+        &self.metadata
+    }
+    // the names of the columns, in the order of the values of a record
+    pub fn columns(&self) -> Vec<&str> {
+        self.metadata.columns()
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+impl IntoIterator for ResultSet {
+    type Item = Record;
+    type IntoIter = std::vec::IntoIter<Record>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.records.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ResultSet {
+    type Item = &'a Record;
+    type IntoIter = std::slice::Iter<'a, Record>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.records.iter()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Record {
     values: Vec<FieldValue>,
+    // the columns of the result the record is part of, which names its values
+    metadata: Option<Arc<ResultMetadata>>,
 }
 impl Record {
     pub fn new(values: Vec<FieldValue>) -> Self {
-        Self { values }
+        Self {
+            values,
+            metadata: None,
+        }
+    }
+    pub(crate) fn with_metadata(mut self, metadata: &Arc<ResultMetadata>) -> Self {
+        self.metadata = Some(metadata.clone());
+        self
     }
     pub fn get(&self, index: usize) -> Option<&FieldValue> {
         self.values.get(index)
     }
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    pub fn values(&self) -> &[FieldValue] {
+        &self.values
+    }
+    // the value of the column of the name as a T, like `try_get::<Option<String>>("name")`
+    pub fn try_get<T: FromField>(&self, name: &str) -> Result<T, DBError> {
+        T::from_field(name, self.get_named(name)?)
+    }
+    // the value of the first column of the name
+    pub fn get_by_name(&self, name: &str) -> Option<&FieldValue> {
+        let index = self
+            .metadata
+            .as_ref()?
+            .fields
+            .iter()
+            .position(|field| field.field_name == name)?;
+        self.values.get(index)
+    }
+    // None for NULL
+    pub fn get_i64(&self, name: &str) -> Result<Option<i64>, DBError> {
+        match self.get_named(name)? {
+            FieldValue::Int(i) => Ok(Some(*i)),
+            FieldValue::Null => Ok(None),
+            other => Err(Self::type_mismatch(name, ColumnType::Integer, other)),
+        }
+    }
+    // None for NULL
+    pub fn get_str(&self, name: &str) -> Result<Option<&str>, DBError> {
+        match self.get_named(name)? {
+            FieldValue::Text(s) => Ok(Some(s)),
+            FieldValue::Null => Ok(None),
+            other => Err(Self::type_mismatch(name, ColumnType::Varchar, other)),
+        }
+    }
+    // the document of a JSON column, or None for NULL
+    pub fn get_json(&self, name: &str) -> Result<Option<serde_json::Value>, DBError> {
+        match self.get_named(name)? {
+            FieldValue::Json(doc) => serde_json::from_str(doc).map(Some).map_err(|err| {
+                DBError::Corruption(format!("broken JSON in column {}. err: {}", name, err))
+            }),
+            FieldValue::Null => Ok(None),
+            other => Err(Self::type_mismatch(name, ColumnType::Json, other)),
+        }
+    }
+    fn get_named(&self, name: &str) -> Result<&FieldValue, DBError> {
+        self.get_by_name(name)
+            .ok_or(DBError::UnknownColumn(name.to_string()))
+    }
+    fn type_mismatch(name: &str, column_type: ColumnType, value: &FieldValue) -> DBError {
+        DBError::TypeMismatch {
+            column: name.to_string(),
+            column_type: column_type.to_string(),
+            value: format!("{:?}", value),
+        }
+    }
+}
+
+// records are equal by their values, whichever result they come from
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+impl Eq for Record {}
+impl std::hash::Hash for Record {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.values.hash(state)
+    }
 }
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum FieldValue {
     Bytes(Vec<u8>),
     Int(i64),
     Text(String),
+    // serialized JSON document
+    Json(String),
+    Null,
+}
+
+impl From<i64> for FieldValue {
+    fn from(i: i64) -> Self {
+        FieldValue::Int(i)
+    }
+}
+impl From<i32> for FieldValue {
+    fn from(i: i32) -> Self {
+        FieldValue::Int(i as i64)
+    }
+}
+impl From<&str> for FieldValue {
+    fn from(s: &str) -> Self {
+        FieldValue::Text(s.to_string())
+    }
+}
+impl From<String> for FieldValue {
+    fn from(s: String) -> Self {
+        FieldValue::Text(s)
+    }
+}
+impl From<serde_json::Value> for FieldValue {
+    fn from(json: serde_json::Value) -> Self {
+        FieldValue::Json(json.to_string())
+    }
+}
+impl<T: Into<FieldValue>> From<Option<T>> for FieldValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(FieldValue::Null, Into::into)
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
@@ -111,6 +649,15 @@ impl ResultMetadata {
             fields: field_metadatas,
         }
     }
+    pub fn fields(&self) -> &[FieldMetadata] {
+        &self.fields
+    }
+    pub fn columns(&self) -> Vec<&str> {
+        (&self.fields)
+            .into_iter()
+            .map(|field| field.name())
+            .collect()
+    }
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FieldMetadata {
@@ -125,8 +672,15 @@ impl FieldMetadata {
             field_type: _type.to_string(),
         }
     }
-    pub(crate) fn field_type(&self) -> ColumnType {
-        ColumnType::from(self.field_type.to_owned())
+    pub fn name(&self) -> &str {
+        &self.field_name
+    }
+    // like "integer" or "varchar"
+    pub fn type_name(&self) -> &str {
+        &self.field_type
+    }
+    pub(crate) fn field_type(&self) -> Result<ColumnType, DBError> {
+        ColumnType::try_from(self.field_type.to_owned())
     }
 }
 
@@ -145,7 +699,7 @@ mod tests {
 
     #[test]
     fn run() {
-        let mut rrrdb = build_crean_database();
+        let rrrdb = build_crean_database("./test_tmp_database");
         rrrdb
             .execute("test_db", "CREATE TABLE users (id integer, name varchar)")
             .unwrap();
@@ -204,8 +758,2039 @@ mod tests {
         println!("OK - SELECT id FROM users WHERE name = 'Alice'");
     }
 
-    fn build_crean_database() -> RrrDB {
-        let path = "./test_tmp_database";
+    #[test]
+    fn json_column() {
+        let rrrdb = build_crean_database("./test_tmp_database_json");
+        rrrdb
+            .execute("test_db", "CREATE TABLE users (id integer, attrs json)")
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                r#"INSERT INTO users VALUES (1, '{"role": "admin", "address": {"city": "Tokyo"}}')"#,
+            )
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                r#"INSERT INTO users VALUES (2, '{"role": "guest", "tags": [1, 2]}')"#,
+            )
+            .unwrap();
+        let invalid = rrrdb.execute("test_db", "INSERT INTO users VALUES (3, '{role}')");
+        assert!(invalid.is_err(), "result: {:?}", invalid);
+
+        let result = rrrdb
+            .execute(
+                "test_db",
+                "SELECT id, attrs->'address'->>'city' FROM users WHERE attrs->>'role' = 'admin'",
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            OkDBResult::SelectResult(ResultSet::new(
+                vec![Record::new(vec![
+                    FieldValue::Int(1),
+                    FieldValue::Text("Tokyo".to_string()),
+                ])],
+                ResultMetadata::new(vec![
+                    FieldMetadata::new("id", "integer"),
+                    FieldMetadata::new("attrs->>'$.address.city'", "varchar"),
+                ])
+            ))
+        );
+
+        let result = rrrdb
+            .execute("test_db", "SELECT attrs->'$.tags' FROM users WHERE id = 2")
+            .unwrap();
+        assert_eq!(
+            result,
+            OkDBResult::SelectResult(ResultSet::new(
+                vec![Record::new(vec![FieldValue::Json("[1,2]".to_string())])],
+                ResultMetadata::new(vec![FieldMetadata::new("attrs->'$.tags'", "json")])
+            ))
+        );
+    }
+
+    #[test]
+    fn insert_type_check() {
+        let rrrdb = build_crean_database("./test_tmp_database_type_check");
+        rrrdb
+            .execute("test_db", "CREATE TABLE users (id integer, name varchar)")
+            .unwrap();
+
+        let result = rrrdb.execute("test_db", "INSERT INTO users VALUES ('abc', 1)");
+        let err = result.unwrap_err();
+        assert!(
+            matches!(err.without_location(), DBError::TypeMismatch { .. }),
+            "err: {:?}",
+            err
+        );
+        assert!(err.to_string().contains("column id"), "err: {:?}", err);
+        assert!(err.to_string().contains("abc"), "err: {:?}", err);
+
+        let result = rrrdb.execute("test_db", "INSERT INTO users VALUES (1)");
+        assert!(result.is_err(), "result: {:?}", result);
+        let result = rrrdb.execute("test_db", "INSERT INTO users VALUES (1, 'Alice', 2)");
+        assert!(result.is_err(), "result: {:?}", result);
+
+        // numeric strings are coerced into integer, numbers into text
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES ('2', 100)")
+            .unwrap();
+        let result = rrrdb.execute("test_db", "SELECT * FROM users").unwrap();
+        assert_eq!(
+            result,
+            OkDBResult::SelectResult(ResultSet::new(
+                vec![Record::new(vec![
+                    FieldValue::Int(2),
+                    FieldValue::Text("100".to_string()),
+                ])],
+                ResultMetadata::new(vec![
+                    FieldMetadata::new("id", "integer"),
+                    FieldMetadata::new("name", "varchar")
+                ])
+            ))
+        );
+    }
+
+    #[test]
+    fn column_constraints() {
+        let rrrdb = build_crean_database("./test_tmp_database_constraints");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer NOT NULL, name varchar NOT NULL DEFAULT 'anonymous', age integer CHECK (age >= 0))",
+            )
+            .unwrap();
+
+        let err = rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (1, NULL, 20)")
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("users_name_not_null"),
+            "err: {:?}",
+            err
+        );
+        let err = rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (1, 'Alice', -1)")
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("users_age_check"),
+            "err: {:?}",
+            err
+        );
+
+        rrrdb
+            .execute("test_db", "INSERT INTO users (id, age) VALUES (1, 20)")
+            .unwrap();
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (2, 'Bob', NULL)")
+            .unwrap();
+        let result = rrrdb.execute("test_db", "SELECT * FROM users").unwrap();
+        assert_eq!(
+            result,
+            OkDBResult::SelectResult(ResultSet::new(
+                vec![
+                    Record::new(vec![
+                        FieldValue::Int(1),
+                        FieldValue::Text("anonymous".to_string()),
+                        FieldValue::Int(20),
+                    ]),
+                    Record::new(vec![
+                        FieldValue::Int(2),
+                        FieldValue::Text("Bob".to_string()),
+                        FieldValue::Null,
+                    ]),
+                ],
+                ResultMetadata::new(vec![
+                    FieldMetadata::new("id", "integer"),
+                    FieldMetadata::new("name", "varchar"),
+                    FieldMetadata::new("age", "integer"),
+                ])
+            ))
+        );
+    }
+
+    #[test]
+    fn unique_constraints() {
+        let rrrdb = build_crean_database("./test_tmp_database_unique");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, email varchar UNIQUE, name varchar, team varchar, UNIQUE (name, team))",
+            )
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (1, 'alice@example.com', 'Alice', 'a')",
+            )
+            .unwrap();
+
+        let err = rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (2, 'alice@example.com', 'Bob', 'a')",
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("users_email_key"),
+            "err: {:?}",
+            err
+        );
+        let err = rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (2, NULL, 'Alice', 'a')",
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("users_name_team_key"),
+            "err: {:?}",
+            err
+        );
+
+        // NULLs never conflict
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (2, NULL, 'Bob', 'a')")
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (3, NULL, 'Carol', 'a')",
+            )
+            .unwrap();
+
+        // replacing a row releases its previous values
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (1, 'alice@example.org', 'Alice', 'a')",
+            )
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (4, 'alice@example.com', 'Dave', 'a')",
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn foreign_keys() {
+        let rrrdb = build_crean_database("./test_tmp_database_foreign_key");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, email varchar UNIQUE)",
+            )
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE posts (id integer, user_id integer REFERENCES users(id) ON DELETE CASCADE, editor varchar, parent_id integer REFERENCES posts(id), CONSTRAINT posts_editor_fk FOREIGN KEY (editor) REFERENCES users(email))",
+            )
+            .unwrap();
+        let err = rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE comments (id integer, user_id integer REFERENCES users(name))",
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("comments_user_id_fkey"),
+            "err: {:?}",
+            err
+        );
+
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (1, 'alice@example.com')",
+            )
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO posts VALUES (1, 1, 'alice@example.com', 1)",
+            )
+            .unwrap();
+        // NULL references nothing
+        rrrdb
+            .execute("test_db", "INSERT INTO posts VALUES (2, NULL, NULL, 1)")
+            .unwrap();
+
+        let err = rrrdb
+            .execute("test_db", "INSERT INTO posts VALUES (3, 2, NULL, NULL)")
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("posts_user_id_fkey"),
+            "err: {:?}",
+            err
+        );
+        let err = rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO posts VALUES (3, 1, 'bob@example.com', NULL)",
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("posts_editor_fk"),
+            "err: {:?}",
+            err
+        );
+        let err = rrrdb
+            .execute("test_db", "INSERT INTO posts VALUES (3, 1, NULL, 4)")
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("posts_parent_id_fkey"),
+            "err: {:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn sequences() {
+        let path = "./test_tmp_database_sequence";
+        let rrrdb = build_crean_database(path);
+        rrrdb
+            .execute("test_db", "CREATE TABLE users (id serial, name varchar)")
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE SEQUENCE ticket START WITH 100 INCREMENT BY 10",
+            )
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE tickets (id integer, no integer, user_id integer AUTO_INCREMENT)",
+            )
+            .unwrap();
+
+        let generated_key = |result: DBResult| match result {
+            Ok(OkDBResult::InsertResult(keys)) => keys.get(0).and_then(|r| r.get(0)).cloned(),
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(
+            generated_key(rrrdb.execute("test_db", "INSERT INTO users (name) VALUES ('Alice')")),
+            Some(FieldValue::Int(1))
+        );
+        assert_eq!(
+            generated_key(rrrdb.execute("test_db", "INSERT INTO users VALUES (NULL, 'Bob')")),
+            Some(FieldValue::Int(2))
+        );
+        // an explicit value is kept as is
+        assert_eq!(
+            generated_key(rrrdb.execute("test_db", "INSERT INTO users VALUES (10, 'Carol')")),
+            None
+        );
+        assert_eq!(
+            generated_key(rrrdb.execute(
+                "test_db",
+                "INSERT INTO tickets (id, no, user_id) VALUES (1, nextval('ticket'), 1)",
+            )),
+            Some(FieldValue::Int(100))
+        );
+        assert_eq!(
+            generated_key(rrrdb.execute(
+                "test_db",
+                "INSERT INTO tickets (id, no) VALUES (2, nextval('ticket'))",
+            )),
+            Some(FieldValue::Int(110))
+        );
+        let err = rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO tickets VALUES (3, nextval('unknown'), 1)",
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown"), "err: {:?}", err);
+
+        // counters survive a restart
+        drop(rrrdb);
+        let rrrdb = RrrDB::new(path);
+        assert_eq!(
+            generated_key(rrrdb.execute("test_db", "INSERT INTO users (name) VALUES ('Dave')")),
+            Some(FieldValue::Int(3))
+        );
+        assert_eq!(
+            generated_key(rrrdb.execute(
+                "test_db",
+                "INSERT INTO tickets (id, no) VALUES (3, nextval('ticket'))",
+            )),
+            Some(FieldValue::Int(120))
+        );
+
+        // concurrent sessions never draw the same value
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let rrrdb = rrrdb.clone();
+                thread::spawn(move || {
+                    (0..20)
+                        .map(|_| {
+                            generated_key(
+                                rrrdb.execute("test_db", "INSERT INTO users (name) VALUES ('x')"),
+                            )
+                            .unwrap()
+                        })
+                        .collect::<Vec<FieldValue>>()
+                })
+            })
+            .collect();
+        let mut ids = std::collections::HashSet::new();
+        for handle in handles {
+            for id in handle.join().unwrap() {
+                assert!(ids.insert(id.clone()), "{:?} is drawn twice", id);
+            }
+        }
+        assert_eq!(ids.len(), 80);
+    }
+
+    #[test]
+    fn transactions() {
+        let path = "./test_tmp_database_transaction";
+        let rrrdb = build_crean_database(path);
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, email varchar UNIQUE)",
+            )
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (1, 'alice@example.com')",
+            )
+            .unwrap();
+        let ids = |rrrdb: &RrrDB| match rrrdb.execute("test_db", "SELECT id FROM users") {
+            Ok(OkDBResult::SelectResult(result_set)) => (0..)
+                .map(|i| result_set.get(i).and_then(|r| r.get(0)).cloned())
+                .take_while(Option::is_some)
+                .flatten()
+                .collect::<Vec<FieldValue>>(),
+            other => panic!("unexpected result: {:?}", other),
+        };
+
+        // a rollback leaves no trace, including the unique index
+        rrrdb.execute("test_db", "BEGIN").unwrap();
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (2, 'bob@example.com')")
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (1, 'carol@example.com')",
+            )
+            .unwrap();
+        assert_eq!(ids(&rrrdb), vec![FieldValue::Int(1), FieldValue::Int(2)]);
+        let err = rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (3, 'bob@example.com')")
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("users_email_key"),
+            "err: {:?}",
+            err
+        );
+        let err = rrrdb
+            .execute("test_db", "CREATE TABLE teams (id integer)")
+            .unwrap_err();
+        assert!(matches!(err, DBError::InvalidTransactionState(_)));
+        assert!(err.to_string().contains("transaction"), "err: {:?}", err);
+        rrrdb.execute("test_db", "ROLLBACK").unwrap();
+        assert_eq!(ids(&rrrdb), vec![FieldValue::Int(1)]);
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (2, 'alice@example.com')",
+            )
+            .unwrap_err();
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (2, 'carol@example.com')",
+            )
+            .unwrap();
+
+        rrrdb.execute("test_db", "BEGIN TRANSACTION").unwrap();
+        assert!(rrrdb.execute("test_db", "BEGIN").is_err());
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (3, 'dave@example.com')",
+            )
+            .unwrap();
+        rrrdb.execute("test_db", "COMMIT").unwrap();
+        assert!(rrrdb.execute("test_db", "COMMIT").is_err());
+        assert!(rrrdb.execute("test_db", "ROLLBACK").is_err());
+
+        drop(rrrdb);
+        let rrrdb = RrrDB::new(path);
+        assert_eq!(
+            ids(&rrrdb),
+            vec![FieldValue::Int(1), FieldValue::Int(2), FieldValue::Int(3)]
+        );
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (4, 'dave@example.com')",
+            )
+            .unwrap_err();
+    }
+
+    #[test]
+    fn failed_create_table() {
+        let rrrdb = build_crean_database("./test_tmp_database_failed_create_table");
+        rrrdb
+            .execute("test_db", "CREATE SEQUENCE users_id_seq")
+            .unwrap();
+        // the sequence for `id` clashes after the column families are created
+        let err = rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id serial, email varchar UNIQUE)",
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("users_id_seq"), "err: {:?}", err);
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, email varchar UNIQUE)",
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn open_error() {
+        let path = "./test_tmp_database_open_error";
+        if std::path::Path::new(path).exists() {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+        std::fs::create_dir_all(path).unwrap();
+        // a file where the directory should be
+        let file = format!("{}/file", path);
+        std::fs::write(&file, "").unwrap();
+        match RrrDB::open(&file) {
+            Err(DBError::Storage(_)) => {}
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("opened {}", file),
+        }
+
+        let rrrdb = RrrDB::open(&format!("{}/data", path)).unwrap();
+        assert_eq!(rrrdb.recovery(), vec![]);
+    }
+
+    #[test]
+    fn recover_create_table() {
+        let path = "./test_tmp_database_recover_create_table";
+        let cf_name = |table_name: &str| storage::Namespace::table("test_db", table_name).cf_name();
+        let rrrdb = build_crean_database(path);
+        rrrdb
+            .execute("test_db", "CREATE TABLE users (id integer)")
+            .unwrap();
+        let err = rrrdb
+            .execute("test_db", "CREATE TABLE users (id integer, name varchar)")
+            .unwrap_err();
+        assert!(err.to_string().contains("already exist"), "err: {:?}", err);
+        assert!(rrrdb.underlying.has_column_family(&cf_name("users")));
+
+        // left by CREATE TABLEs crashed before saving the schema, which may hold data
+        let mut storage = storage::Storage::session(&rrrdb.underlying);
+        storage.create_column_family(&cf_name("teams")).unwrap();
+        storage.create_column_family(&cf_name("posts")).unwrap();
+        // not named like a table's
+        storage.create_column_family("legacy").unwrap();
+        drop(storage);
+        assert!(matches!(
+            rrrdb.execute("test_db", "CREATE TABLE teams (id integer)"),
+            Err(DBError::AlreadyExists(_))
+        ));
+        assert!(rrrdb.underlying.has_column_family(&cf_name("teams")));
+
+        drop(rrrdb);
+        let rrrdb = RrrDB::new(path);
+        let mut recovery = rrrdb.recovery();
+        recovery.sort_by_key(|recovery| recovery.to_string());
+        assert_eq!(
+            recovery,
+            vec![
+                Recovery::DroppedOrphan(cf_name("posts")),
+                Recovery::DroppedOrphan(cf_name("teams")),
+            ]
+        );
+        assert!(rrrdb.underlying.has_column_family(&cf_name("users")));
+        assert!(!rrrdb.underlying.has_column_family(&cf_name("teams")));
+        assert!(rrrdb.underlying.has_column_family("legacy"));
+        for table_name in &["teams", "posts"] {
+            rrrdb
+                .execute(
+                    "test_db",
+                    &format!("CREATE TABLE {} (id integer)", table_name),
+                )
+                .unwrap();
+        }
+        rrrdb
+            .execute("test_db", "INSERT INTO posts VALUES (1)")
+            .unwrap();
+
+        // nothing is dropped unless every schema can be read
+        let mut storage = storage::Storage::session(&rrrdb.underlying);
+        storage.create_column_family(&cf_name("tags")).unwrap();
+        storage
+            .put(
+                &storage::Namespace::Metadata,
+                &SchemaStore::schema_key("broken_db"),
+                b"{".to_vec(),
+            )
+            .unwrap();
+        drop(storage);
+        drop(rrrdb);
+        let rrrdb = RrrDB::new(path);
+        assert!(matches!(&rrrdb.recovery()[..], [Recovery::Skipped(_)]));
+        assert!(rrrdb.underlying.has_column_family(&cf_name("tags")));
+    }
+
+    #[test]
+    fn migrate_legacy_column_families() {
+        let path = "./test_tmp_database_legacy_column_families";
+        if Path::new(path).exists() {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+        // as written before table names were length-prefixed
+        let mut options = rocksdb::Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let db = rocksdb::DB::open_cf(&options, path, &["metadata", "test_db_users"]).unwrap();
+        let schema = r#"{"name":"test_db","tables":[{"name":"users","columns":[
+            {"name":"id","column_type":"Integer"},{"name":"name","column_type":"Varchar"}]}]}"#;
+        db.put_cf(db.cf_handle("metadata").unwrap(), "test_db_schema", schema)
+            .unwrap();
+        let users = db.cf_handle("test_db_users").unwrap();
+        db.put_cf(users, "1", r#"{"id":"1","name":"Alice"}"#)
+            .unwrap();
+        db.put_cf(users, "2", r#"{"id":"2","name":"Bob"}"#).unwrap();
+        drop(db);
+
+        let cf_name = storage::Namespace::table("test_db", "users").cf_name();
+        let rrrdb = RrrDB::new(path);
+        assert_eq!(
+            rrrdb.recovery(),
+            vec![
+                Recovery::MigratedSchema {
+                    from: "test_db_schema".to_string(),
+                    to: SchemaStore::schema_key("test_db"),
+                },
+                Recovery::Migrated {
+                    from: "test_db_users".to_string(),
+                    to: cf_name.clone(),
+                },
+            ]
+        );
+        assert!(!rrrdb.underlying.has_column_family("test_db_users"));
+        let names =
+            |rrrdb: &RrrDB| match rrrdb.execute("test_db", "SELECT name FROM users").unwrap() {
+                OkDBResult::SelectResult(result_set) => result_set.records,
+                other => panic!("unexpected result: {:?}", other),
+            };
+        let alice_and_bob = vec![
+            Record::new(vec![FieldValue::Text("Alice".to_string())]),
+            Record::new(vec![FieldValue::Text("Bob".to_string())]),
+        ];
+        assert_eq!(names(&rrrdb), alice_and_bob);
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (3, 'Carol')")
+            .unwrap();
+
+        // left by a crash after the copy, and dropped without copying it again
+        let mut storage = storage::Storage::session(&rrrdb.underlying);
+        storage.create_column_family("test_db_users").unwrap();
+        drop(storage);
+        drop(rrrdb);
+        let rrrdb = RrrDB::new(path);
+        assert!(!rrrdb.underlying.has_column_family("test_db_users"));
+        assert_eq!(names(&rrrdb).len(), 3);
+        drop(rrrdb);
+        let rrrdb = RrrDB::new(path);
+        assert_eq!(rrrdb.recovery(), vec![]);
+        assert_eq!(names(&rrrdb).len(), 3);
+    }
+
+    #[test]
+    fn metadata_keys() {
+        let rrrdb = build_crean_database("./test_tmp_database_metadata_keys");
+        // statistics of `a` + `x_schema` were saved where the schema of `a_statistics_x` was
+        rrrdb
+            .execute("a_statistics_x", "CREATE TABLE t (id integer)")
+            .unwrap();
+        rrrdb
+            .execute("a", "CREATE TABLE x_schema (id integer)")
+            .unwrap();
+        rrrdb
+            .execute("a", "INSERT INTO x_schema VALUES (1)")
+            .unwrap();
+        rrrdb.execute("a", "ANALYZE x_schema").unwrap();
+        rrrdb
+            .execute("a_statistics_x", "INSERT INTO t VALUES (1)")
+            .unwrap();
+        assert_eq!(rrrdb.table_names("a_statistics_x").unwrap(), vec!["t"]);
+        assert_eq!(rrrdb.table_names("a").unwrap(), vec!["x_schema"]);
+
+        // and the counter of `a` + `b_schema` where the schema of `a_sequence_b` was
+        rrrdb
+            .execute("a_sequence_b", "CREATE TABLE t (id integer)")
+            .unwrap();
+        rrrdb.execute("a", "CREATE SEQUENCE b_schema").unwrap();
+        rrrdb.execute("a", "CREATE TABLE c (id integer)").unwrap();
+        rrrdb
+            .execute("a", "INSERT INTO c VALUES (nextval('b_schema'))")
+            .unwrap();
+        rrrdb
+            .execute("a_sequence_b", "INSERT INTO t VALUES (1)")
+            .unwrap();
+        assert_eq!(rrrdb.table_names("a_sequence_b").unwrap(), vec!["t"]);
+
+        drop(rrrdb);
+        let rrrdb = RrrDB::new("./test_tmp_database_metadata_keys");
+        assert_eq!(rrrdb.recovery(), vec![]);
+        assert_eq!(rrrdb.table_names("a_statistics_x").unwrap(), vec!["t"]);
+    }
+
+    #[test]
+    fn column_family_names() {
+        let rrrdb = build_crean_database("./test_tmp_database_column_family_names");
+        // `a` + `b_c` and `a_b` + `c` are told apart
+        rrrdb
+            .execute("a", "CREATE TABLE b_c (id integer, name varchar)")
+            .unwrap();
+        rrrdb
+            .execute("a_b", "CREATE TABLE c (id integer, name varchar)")
+            .unwrap();
+        rrrdb
+            .execute("a", "INSERT INTO b_c VALUES (1, 'x')")
+            .unwrap();
+        rrrdb
+            .execute("a_b", "INSERT INTO c VALUES (2, 'y')")
+            .unwrap();
+        for (database_name, table_name, id) in &[("a", "b_c", 1), ("a_b", "c", 2)] {
+            match rrrdb
+                .execute(database_name, &format!("SELECT id FROM {}", table_name))
+                .unwrap()
+            {
+                OkDBResult::SelectResult(result_set) => assert_eq!(
+                    result_set.records,
+                    vec![Record::new(vec![FieldValue::Int(*id)])]
+                ),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+
+        // a quoted table name doesn't clash with the index of another table
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, email varchar UNIQUE)",
+            )
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE \"users.users_email_key\" (id integer, email varchar)",
+            )
+            .unwrap();
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (1, 'a@example.com')")
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO \"users.users_email_key\" VALUES (2, 'a@example.com')",
+            )
+            .unwrap();
+        assert!(matches!(
+            rrrdb.execute("test_db", "INSERT INTO users VALUES (3, 'a@example.com')"),
+            Err(DBError::ConstraintViolation { .. })
+        ));
+        for (table_name, id) in &[("users", 1), ("\"users.users_email_key\"", 2)] {
+            match rrrdb
+                .execute("test_db", &format!("SELECT id FROM {}", table_name))
+                .unwrap()
+            {
+                OkDBResult::SelectResult(result_set) => assert_eq!(
+                    result_set.records,
+                    vec![Record::new(vec![FieldValue::Int(*id)])]
+                ),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn concurrent_sessions() {
+        fn assert_shareable<T: Send + Sync + Clone>() {}
+        assert_shareable::<RrrDB>();
+
+        let rrrdb = build_crean_database("./test_tmp_database_concurrent_sessions");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, email varchar UNIQUE)",
+            )
+            .unwrap();
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let rrrdb = rrrdb.clone();
+                thread::spawn(move || {
+                    for i in 0..10 {
+                        let id = t * 10 + i;
+                        rrrdb
+                            .execute(
+                                "test_db",
+                                &format!(
+                                    "INSERT INTO users VALUES ({}, 'user{}@example.com')",
+                                    id, id
+                                ),
+                            )
+                            .unwrap();
+                        rrrdb.execute("test_db", "SELECT * FROM users").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        match rrrdb.execute("test_db", "SELECT id FROM users").unwrap() {
+            OkDBResult::SelectResult(result_set) => {
+                assert!(result_set.get(39).is_some());
+                assert!(result_set.get(40).is_none());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // a clone has its own transaction, and the first committer wins
+        let other = rrrdb.clone();
+        rrrdb.execute("test_db", "BEGIN").unwrap();
+        other.execute("test_db", "BEGIN").unwrap();
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (100, 'x@example.com')")
+            .unwrap();
+        other
+            .execute("test_db", "INSERT INTO users VALUES (101, 'x@example.com')")
+            .unwrap();
+        rrrdb.execute("test_db", "COMMIT").unwrap();
+        let err = other.execute("test_db", "COMMIT").unwrap_err();
+        assert!(matches!(err, DBError::SerializationFailure(_)));
+        assert!(
+            err.to_string().contains("concurrent update"),
+            "err: {:?}",
+            err
+        );
+
+        // tables created concurrently are all kept
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let rrrdb = rrrdb.clone();
+                thread::spawn(move || {
+                    rrrdb
+                        .execute("test_db", &format!("CREATE TABLE t{} (id integer)", t))
+                        .unwrap();
+                    rrrdb
+                        .execute("test_db", &format!("INSERT INTO t{} VALUES (1)", t))
+                        .unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        for t in 0..4 {
+            rrrdb
+                .execute("test_db", &format!("SELECT * FROM t{}", t))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn sessions() {
+        let rrrdb = build_crean_database("./test_tmp_database_sessions");
+        let mut session = rrrdb.session();
+        let err = session.execute("SELECT * FROM users").unwrap_err();
+        assert_eq!(err, DBError::NoDatabaseSelected);
+        assert!(err.to_string().contains("no database"), "err: {:?}", err);
+
+        session.execute("USE test_db").unwrap();
+        assert_eq!(session.database_name(), Some("test_db"));
+        session
+            .execute("CREATE TABLE users (id integer, name varchar)")
+            .unwrap();
+        session.execute("SET application_name = 'batch'").unwrap();
+        assert_eq!(session.variable("APPLICATION_NAME"), Some("batch"));
+
+        // the transaction belongs to the session
+        let mut other = rrrdb.session();
+        other.execute("USE test_db").unwrap();
+        session.execute("BEGIN").unwrap();
+        session
+            .execute("INSERT INTO users VALUES (1, 'Alice')")
+            .unwrap();
+        assert!(session.in_transaction());
+        assert!(!other.in_transaction());
+        let count = |session: &mut Session| match session.execute("SELECT * FROM users") {
+            Ok(OkDBResult::SelectResult(result_set)) => {
+                (0..).take_while(|i| result_set.get(*i).is_some()).count()
+            }
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(count(&mut session), 1);
+        assert_eq!(count(&mut other), 0);
+        session.execute("COMMIT").unwrap();
+        assert_eq!(count(&mut other), 1);
+        assert_eq!(other.variable("application_name"), None);
+    }
+
+    #[test]
+    fn prepared_statements() {
+        let rrrdb = build_crean_database("./test_tmp_database_prepared_statements");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, name varchar, age integer)",
+            )
+            .unwrap();
+        let insert = rrrdb
+            .prepare("test_db", "INSERT INTO users VALUES (?, ?, ?)")
+            .unwrap();
+        assert_eq!(insert.parameter_count(), 3);
+        rrrdb
+            .execute_prepared(
+                &insert,
+                vec![FieldValue::from(1), "Alice".into(), 20.into()],
+            )
+            .unwrap();
+        // never parsed as SQL
+        rrrdb
+            .execute_prepared(
+                &insert,
+                vec![
+                    FieldValue::from(2),
+                    "Bob'), (3, 'Mallory".into(),
+                    None::<i64>.into(),
+                ],
+            )
+            .unwrap();
+        let err = rrrdb
+            .execute_prepared(&insert, vec![FieldValue::from(3), "Carol".into()])
+            .unwrap_err();
+        assert!(err.to_string().contains("parameters"), "err: {:?}", err);
+        let err = rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (?, 'Carol', 30)")
+            .unwrap_err();
+        assert!(err.to_string().contains("prepared"), "err: {:?}", err);
+
+        let mut session = rrrdb.session();
+        session.execute("USE test_db").unwrap();
+        let select = session
+            .prepare("SELECT name FROM users WHERE id = :id")
+            .unwrap();
+        assert_eq!(
+            session.prepare("SELECT name FROM users WHERE id = :id"),
+            Ok(select.clone())
+        );
+        let name = |session: &mut Session, id: i64| match session
+            .execute_prepared(&select, vec![("id", id.into())])
+        {
+            Ok(OkDBResult::SelectResult(result_set)) => {
+                result_set.get(0).and_then(|r| r.get(0)).cloned()
+            }
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(
+            name(&mut session, 1),
+            Some(FieldValue::Text("Alice".to_string()))
+        );
+        assert_eq!(
+            name(&mut session, 2),
+            Some(FieldValue::Text("Bob'), (3, 'Mallory".to_string()))
+        );
+        assert_eq!(name(&mut session, 3), None);
+    }
+
+    #[test]
+    fn query() {
+        let rrrdb = build_crean_database("./test_tmp_database_query");
+        rrrdb
+            .execute("test_db", "CREATE TABLE users (id integer, name varchar)")
+            .unwrap();
+        for (id, name) in &[(1, "Alice"), (2, "Bob"), (3, "Carol")] {
+            rrrdb
+                .execute(
+                    "test_db",
+                    &format!("INSERT INTO users VALUES ({}, '{}')", id, name),
+                )
+                .unwrap();
+        }
+
+        let mut rows = rrrdb.query("test_db", "SELECT name FROM users").unwrap();
+        assert_eq!(
+            rows.metadata(),
+            &ResultMetadata::new(vec![FieldMetadata::new("name", "varchar")])
+        );
+        assert_eq!(
+            rows.next().unwrap().unwrap(),
+            Record::new(vec![FieldValue::Text("Alice".to_string())])
+        );
+        // rows committed after the query started are not seen
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (4, 'Dave')")
+            .unwrap();
+        let names: Vec<Record> = rows.map(|row| row.unwrap()).collect();
+        assert_eq!(
+            names,
+            vec![
+                Record::new(vec![FieldValue::Text("Bob".to_string())]),
+                Record::new(vec![FieldValue::Text("Carol".to_string())]),
+            ]
+        );
+
+        // stops early, and filters
+        let first = rrrdb
+            .query("test_db", "SELECT id FROM users WHERE name = 'Dave'")
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first, Record::new(vec![FieldValue::Int(4)]));
+
+        // sees the uncommitted rows of the session's transaction, and outlives it
+        let mut session = rrrdb.session();
+        session.execute("USE test_db").unwrap();
+        session.execute("BEGIN").unwrap();
+        session
+            .execute("INSERT INTO users VALUES (5, 'Eve')")
+            .unwrap();
+        let rows = session.query("SELECT id FROM users").unwrap();
+        session.execute("ROLLBACK").unwrap();
+        assert_eq!(rows.count(), 5);
+
+        let err = rrrdb
+            .query("test_db", "INSERT INTO users VALUES (6, 'Frank')")
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, DBError::InvalidStatement(_)),
+            "err: {:?}",
+            err
+        );
+        assert!(matches!(
+            rrrdb
+                .query("test_db", "SELECT * FROM teams")
+                .err()
+                .as_ref()
+                .map(DBError::without_location),
+            Some(DBError::UnknownTable(_))
+        ));
+
+        // CREATE fails instead of waiting for the rows streamed on the same thread
+        let rows = rrrdb.query("test_db", "SELECT id FROM users").unwrap();
+        assert!(matches!(
+            rrrdb.execute("test_db", "CREATE TABLE teams (id integer)"),
+            Err(DBError::ObjectInUse(_))
+        ));
+        assert!(matches!(
+            rrrdb.execute_batch("test_db", "CREATE TABLE teams (id integer);"),
+            Err(BatchError {
+                index: 0,
+                error: DBError::ObjectInUse(_),
+                ..
+            })
+        ));
+        drop(rows);
+        rrrdb
+            .execute("test_db", "CREATE TABLE teams (id integer)")
+            .unwrap();
+    }
+
+    #[test]
+    fn create_while_reading_snapshot() {
+        let rrrdb = build_crean_database("./test_tmp_database_create_while_reading_snapshot");
+        rrrdb
+            .execute("test_db", "CREATE TABLE users (id integer, name varchar)")
+            .unwrap();
+        let mut session = rrrdb.session();
+        session.execute("USE test_db").unwrap();
+        session
+            .execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+            .unwrap();
+        session.execute("BEGIN").unwrap();
+
+        // another session's CREATE fails at once instead of waiting for the transaction
+        let mut other = rrrdb.session();
+        other.execute("USE test_db").unwrap();
+        let err = other
+            .execute("CREATE TABLE teams (id integer)")
+            .unwrap_err();
+        assert!(matches!(err, DBError::ObjectInUse(_)), "err: {:?}", err);
+        assert_eq!(other.table_names().unwrap(), vec!["users".to_string()]);
+
+        session.execute("COMMIT").unwrap();
+        other.execute("CREATE TABLE teams (id integer)").unwrap();
+    }
+
+    #[test]
+    fn index_scan() {
+        use sql::planner::{IndexPlan, Plan, PlanNode, Planner};
+
+        let rrrdb = build_crean_database("./test_tmp_database_index_scan");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, name varchar UNIQUE, age integer)",
+            )
+            .unwrap();
+        for (id, name, age) in &[(1, "Alice", 20), (2, "Bob", 30), (3, "Carol", 30)] {
+            rrrdb
+                .execute(
+                    "test_db",
+                    &format!("INSERT INTO users VALUES ({}, '{}', {})", id, name, age),
+                )
+                .unwrap();
+        }
+
+        let access_path = |query: &str| {
+            let mut storage = storage::Storage::session(&rrrdb.underlying);
+            let statement = parser::Parser::parse_sql(Some("test_db".to_string()), query).unwrap();
+            let plan = Planner::new("test_db", &mut storage, statement)
+                .unwrap()
+                .plan()
+                .unwrap();
+            match plan {
+                Plan::SelectPlan(select_plan) => match select_plan.root {
+                    PlanNode::Project { input, .. } => match *input {
+                        PlanNode::Filter { input, .. } => *input,
+                        other => panic!("unexpected node {:?}", other),
+                    },
+                    other => panic!("unexpected node {:?}", other),
+                },
+                other => panic!("unexpected plan {:?}", other),
+            }
+        };
+        assert!(matches!(
+            access_path("SELECT name FROM users WHERE id = 2"),
+            PlanNode::IndexScan {
+                index: IndexPlan::PrimaryKey,
+                ..
+            }
+        ));
+        assert!(matches!(
+            access_path("SELECT id FROM users WHERE name = 'Carol'"),
+            PlanNode::IndexScan {
+                index: IndexPlan::Unique(_),
+                ..
+            }
+        ));
+        assert!(matches!(
+            access_path("SELECT id FROM users WHERE age = 30"),
+            PlanNode::Scan { .. }
+        ));
+
+        let select = |query: &str| match rrrdb.execute("test_db", query).unwrap() {
+            OkDBResult::SelectResult(result_set) => result_set,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(
+            select("SELECT name FROM users WHERE id = 2").get(0),
+            Some(&Record::new(vec![FieldValue::Text("Bob".to_string())]))
+        );
+        assert_eq!(
+            select("SELECT id FROM users WHERE name = 'Carol'").get(0),
+            Some(&Record::new(vec![FieldValue::Int(3)]))
+        );
+        // compared the same way as by a scan
+        assert_eq!(select("SELECT id FROM users WHERE id = 9").get(0), None);
+        assert_eq!(select("SELECT id FROM users WHERE id = '2'").get(0), None);
+        assert_eq!(select("SELECT id FROM users WHERE name = 1").get(0), None);
+        assert_eq!(
+            select("SELECT id FROM users WHERE age = 30").get(1),
+            Some(&Record::new(vec![FieldValue::Int(3)]))
+        );
+        // only equality is filtered on, rather than taken for one or ignored
+        for query in &[
+            "SELECT name FROM users WHERE id > 1",
+            "SELECT id FROM users WHERE name != 'Carol'",
+            "SELECT id FROM users WHERE name",
+            "SELECT id FROM users WHERE true",
+            "SELECT id FROM users WHERE name->>'admin'",
+        ] {
+            let result = rrrdb.execute("test_db", query);
+            assert!(
+                matches!(result, Err(DBError::Unsupported(_))),
+                "{}: {:?}",
+                query,
+                result
+            );
+        }
+        // functions are called only in VALUES
+        assert!(matches!(
+            rrrdb.execute("test_db", "SELECT id FROM users WHERE nextval('s')"),
+            Err(DBError::Syntax { .. })
+        ));
+
+        // placeholders are bound to the looked up value
+        let mut session = rrrdb.session();
+        session.execute("USE test_db").unwrap();
+        let prepared = session
+            .prepare("SELECT name FROM users WHERE id = ?")
+            .unwrap();
+        match session.execute_prepared(&prepared, vec![FieldValue::Int(1)]) {
+            Ok(OkDBResult::SelectResult(result_set)) => assert_eq!(
+                result_set.get(0),
+                Some(&Record::new(vec![FieldValue::Text("Alice".to_string())]))
+            ),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn explain() {
+        let rrrdb = build_crean_database("./test_tmp_database_explain");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, name varchar UNIQUE, age integer)",
+            )
+            .unwrap();
+        for (id, name, age) in &[(1, "Alice", 20), (2, "Bob", 30), (3, "Carol", 30)] {
+            rrrdb
+                .execute(
+                    "test_db",
+                    &format!("INSERT INTO users VALUES ({}, '{}', {})", id, name, age),
+                )
+                .unwrap();
+        }
+        let explain = |query: &str| match rrrdb.execute("test_db", query).unwrap() {
+            OkDBResult::SelectResult(result_set) => result_set,
+            other => panic!("unexpected result {:?}", other),
+        };
+        let text = |value: Option<&FieldValue>| match value {
+            Some(FieldValue::Text(text)) => text.to_owned(),
+            other => panic!("unexpected value {:?}", other),
+        };
+
+        let result_set = explain("EXPLAIN SELECT name FROM users WHERE id = 2");
+        let lines: Vec<String> = (0..3)
+            .map(|i| text(result_set.get(i).and_then(|record| record.get(0))))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "Project (name)",
+                "  -> Filter (users.id = 2)",
+                "    -> Index Scan using primary key on users (id = 2)",
+            ]
+        );
+        assert_eq!(result_set.get(3), None);
+
+        let result_set = explain("EXPLAIN SELECT id FROM users WHERE name = 'Carol'");
+        assert_eq!(
+            text(result_set.get(2).and_then(|record| record.get(0))),
+            "    -> Index Scan using users_name_key on users (name = 'Carol')"
+        );
+
+        // rows returned by each operator
+        let result_set = explain("EXPLAIN ANALYZE SELECT id FROM users WHERE age = 30");
+        let rows: Vec<(String, Option<&FieldValue>)> = (0..3)
+            .map(|i| {
+                let record = result_set.get(i).unwrap();
+                (text(record.get(0)), record.get(3))
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("Project (id)".to_string(), Some(&FieldValue::Int(2))),
+                (
+                    "  -> Filter (users.age = 30)".to_string(),
+                    Some(&FieldValue::Int(2))
+                ),
+                (
+                    "    -> Scan on users".to_string(),
+                    Some(&FieldValue::Int(3))
+                ),
+            ]
+        );
+        assert!(matches!(
+            result_set.get(0).and_then(|record| record.get(4)),
+            Some(FieldValue::Int(_))
+        ));
+
+        assert!(matches!(
+            rrrdb.execute(
+                "test_db",
+                "EXPLAIN INSERT INTO users VALUES (4, 'Dave', 40)"
+            ),
+            Err(DBError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn operators_without_grammar() {
+        use sql::executor::Executor;
+        use sql::planner::{
+            AggregateFunction, AggregatePlan, ColumnRef, ExplainPlan, Plan, PlanNode, SelectPlan,
+            SortKey,
+        };
+
+        let rrrdb = build_crean_database("./test_tmp_database_operators_without_grammar");
+        for statement in &[
+            "CREATE TABLE teams (id integer, name varchar)",
+            "CREATE TABLE users (id integer, name varchar, team_id integer)",
+            "INSERT INTO teams VALUES (10, 'red')",
+            "INSERT INTO teams VALUES (20, 'blue')",
+            "INSERT INTO users VALUES (1, 'Alice', 10)",
+            "INSERT INTO users VALUES (2, 'Bob', 20)",
+            "INSERT INTO users VALUES (3, 'Carol', 10)",
+            "INSERT INTO users VALUES (4, 'Dave', NULL)",
+        ] {
+            rrrdb.execute("test_db", statement).unwrap();
+        }
+        let mut storage = storage::Storage::session(&rrrdb.underlying);
+        let database = SchemaStore::new(&mut storage)
+            .find_schema("test_db")
+            .unwrap()
+            .unwrap();
+        let scan = |table_name: &str| PlanNode::Scan {
+            table: database.table(table_name).unwrap(),
+        };
+
+        // the team with the most users, which no SQL can ask for yet
+        let join = PlanNode::Join {
+            left: Box::new(scan("users")),
+            right: Box::new(scan("teams")),
+            on: (
+                ColumnRef::new("users", "team_id"),
+                ColumnRef::new("teams", "id"),
+            ),
+        };
+        let aggregate = PlanNode::Aggregate {
+            input: Box::new(join),
+            group_by: vec![ColumnRef::new("teams", "name")],
+            aggregates: vec![
+                AggregatePlan::new(AggregateFunction::Count, None, "count(*)"),
+                AggregatePlan::new(
+                    AggregateFunction::Min,
+                    Some(ColumnRef::new("users", "name")),
+                    "min(name)",
+                ),
+            ],
+        };
+        let sort = PlanNode::Sort {
+            input: Box::new(aggregate),
+            keys: vec![SortKey::new(ColumnRef::new("", "count(*)"), true)],
+        };
+        let plan = SelectPlan {
+            database: database.clone(),
+            root: PlanNode::Limit {
+                input: Box::new(sort),
+                limit: Some(1),
+                offset: 0,
+            },
+        };
+
+        match Executor::new(&mut storage, Plan::SelectPlan(plan.clone()))
+            .execute()
+            .unwrap()
+        {
+            OkDBResult::SelectResult(result_set) => {
+                assert_eq!(result_set.columns(), vec!["name", "count(*)", "min(name)"]);
+                assert_eq!(result_set.metadata().fields()[2].type_name(), "varchar");
+                assert_eq!(
+                    result_set.iter().collect::<Vec<_>>(),
+                    vec![&Record::new(vec![
+                        FieldValue::from("red"),
+                        FieldValue::Int(2),
+                        FieldValue::from("Alice"),
+                    ])]
+                );
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let explain = Plan::ExplainPlan(ExplainPlan {
+            plan,
+            analyze: true,
+        });
+        match Executor::new(&mut storage, explain).execute().unwrap() {
+            OkDBResult::SelectResult(result_set) => {
+                let lines: Vec<(&FieldValue, &FieldValue)> = result_set
+                    .iter()
+                    .map(|record| (record.get(0).unwrap(), record.get(3).unwrap()))
+                    .collect();
+                assert_eq!(
+                    lines,
+                    vec![
+                        (&FieldValue::from("Limit (1 offset 0)"), &FieldValue::Int(1)),
+                        // Limit stops pulling rows once it has one
+                        (
+                            &FieldValue::from("  -> Sort (count(*) DESC)"),
+                            &FieldValue::Int(1)
+                        ),
+                        (
+                            &FieldValue::from(
+                                "    -> Aggregate (count(*), min(name)) group by (teams.name)"
+                            ),
+                            &FieldValue::Int(2)
+                        ),
+                        (
+                            &FieldValue::from(
+                                "      -> Nested Loop Join (users.team_id = teams.id)"
+                            ),
+                            &FieldValue::Int(3)
+                        ),
+                        (
+                            &FieldValue::from("        -> Scan on users"),
+                            &FieldValue::Int(4)
+                        ),
+                        (
+                            &FieldValue::from("        -> Scan on teams"),
+                            &FieldValue::Int(2)
+                        ),
+                    ]
+                );
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn analyze() {
+        let rrrdb = build_crean_database("./test_tmp_database_analyze");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, name varchar, age integer)",
+            )
+            .unwrap();
+        let explain = |query: &str| match rrrdb.execute("test_db", query).unwrap() {
+            OkDBResult::SelectResult(result_set) => (0..)
+                .map_while(|i| result_set.get(i).cloned())
+                .map(|record| {
+                    let plan = match record.get(0) {
+                        Some(FieldValue::Text(plan)) => plan.trim_start().to_string(),
+                        other => panic!("unexpected value {:?}", other),
+                    };
+                    (plan, record.get(1).cloned().unwrap())
+                })
+                .collect::<Vec<(String, FieldValue)>>(),
+            other => panic!("unexpected result {:?}", other),
+        };
+
+        // scanning an empty table is cheaper than looking it up
+        assert_eq!(
+            explain("EXPLAIN SELECT name FROM users WHERE id = 1")[2].0,
+            "-> Scan on users"
+        );
+        for id in 0..20 {
+            rrrdb
+                .execute(
+                    "test_db",
+                    &format!(
+                        "INSERT INTO users VALUES ({}, 'user{}', {})",
+                        id,
+                        id,
+                        20 + id % 4
+                    ),
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            explain("EXPLAIN SELECT name FROM users WHERE id = 1")[2],
+            (
+                "-> Index Scan using primary key on users (id = 1)".to_string(),
+                FieldValue::Int(1)
+            )
+        );
+        // rows RocksDB estimates, with a default selectivity
+        assert_eq!(
+            explain("EXPLAIN SELECT name FROM users WHERE age = 21")[1],
+            ("-> Filter (users.age = 21)".to_string(), FieldValue::Int(2))
+        );
+
+        rrrdb.execute("test_db", "ANALYZE users").unwrap();
+        let mut storage = storage::Storage::session(&rrrdb.underlying);
+        let statistics = SchemaStore::new(&mut storage)
+            .find_statistics("test_db", "users")
+            .unwrap()
+            .unwrap();
+        assert_eq!(statistics.row_count, 20);
+        assert_eq!(statistics.column("age").unwrap().distinct_count, 4);
+        assert_eq!(
+            explain("EXPLAIN SELECT name FROM users WHERE age = 21")[1],
+            ("-> Filter (users.age = 21)".to_string(), FieldValue::Int(5))
+        );
+        assert_eq!(
+            explain("EXPLAIN SELECT name FROM users WHERE age = 99")[1].1,
+            FieldValue::Int(0)
+        );
+
+        // statistics are kept until the next ANALYZE
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (20, 'user20', 99)")
+            .unwrap();
+        assert_eq!(
+            explain("EXPLAIN SELECT name FROM users WHERE age = 99")[1].1,
+            FieldValue::Int(0)
+        );
+        rrrdb.execute("test_db", "ANALYZE").unwrap();
+        assert_eq!(
+            explain("EXPLAIN SELECT name FROM users WHERE age = 99")[1].1,
+            FieldValue::Int(1)
+        );
+
+        assert!(matches!(
+            rrrdb.execute("test_db", "ANALYZE teams"),
+            Err(DBError::UnknownTable(_))
+        ));
+    }
+
+    #[test]
+    fn result_set_accessors() {
+        let rrrdb = build_crean_database("./test_tmp_database_result_set");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, name varchar, attrs json)",
+            )
+            .unwrap();
+        rrrdb
+            .execute(
+                "test_db",
+                "INSERT INTO users VALUES (1, 'Alice', '{\"admin\": true}')",
+            )
+            .unwrap();
+        rrrdb
+            .execute("test_db", "INSERT INTO users (id) VALUES (2)")
+            .unwrap();
+
+        let result_set = match rrrdb.execute("test_db", "SELECT * FROM users").unwrap() {
+            OkDBResult::SelectResult(result_set) => result_set,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(result_set.len(), 2);
+        assert!(!result_set.is_empty());
+        assert_eq!(result_set.columns(), vec!["id", "name", "attrs"]);
+        assert_eq!(result_set.metadata().fields()[2].type_name(), "json");
+
+        let alice = result_set.iter().next().unwrap();
+        assert_eq!(alice.get_by_name("name"), Some(&FieldValue::from("Alice")));
+        assert_eq!(alice.get_i64("id").unwrap(), Some(1));
+        assert_eq!(alice.get_str("name").unwrap(), Some("Alice"));
+        assert_eq!(
+            alice.get_json("attrs").unwrap(),
+            Some(serde_json::json!({"admin": true}))
+        );
+        assert!(matches!(
+            alice.get_i64("name"),
+            Err(DBError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            alice.get_str("age"),
+            Err(DBError::UnknownColumn(_))
+        ));
+        // NULL
+        let ids: Vec<(Option<i64>, Option<&str>)> = (&result_set)
+            .into_iter()
+            .map(|record| {
+                (
+                    record.get_i64("id").unwrap(),
+                    record.get_str("name").unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(ids, vec![(Some(1), Some("Alice")), (Some(2), None)]);
+
+        // streamed rows are named as well
+        let names: Vec<String> = rrrdb
+            .query("test_db", "SELECT name FROM users WHERE id = 1")
+            .unwrap()
+            .map(|row| row.unwrap().get_str("name").unwrap().unwrap().to_string())
+            .collect();
+        assert_eq!(names, vec!["Alice".to_string()]);
+        // and compared by their values only
+        assert_eq!(
+            result_set.into_iter().last(),
+            Some(Record::new(vec![
+                FieldValue::Int(2),
+                FieldValue::Null,
+                FieldValue::Null
+            ]))
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromRow, ToRow, Debug, PartialEq)]
+    #[rrrdb(crate = "crate::rrrdb")]
+    struct User {
+        id: i64,
+        #[rrrdb(rename = "name")]
+        user_name: Option<String>,
+        attrs: Option<serde_json::Value>,
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn map_rows() {
+        let rrrdb = build_crean_database("./test_tmp_database_map_rows");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, name varchar, attrs json)",
+            )
+            .unwrap();
+        let alice = User {
+            id: 1,
+            user_name: Some("Alice".to_string()),
+            attrs: Some(serde_json::json!({"admin": true})),
+        };
+        let bob = User {
+            id: 2,
+            user_name: None,
+            attrs: None,
+        };
+        rrrdb.insert("test_db", "users", &alice).unwrap();
+        rrrdb.insert("test_db", "users", &bob).unwrap();
+
+        let users: Vec<User> = rrrdb.query_as("test_db", "SELECT * FROM users").unwrap();
+        assert_eq!(users, vec![alice, bob]);
+
+        // the column is missing
+        assert!(matches!(
+            rrrdb.query_as::<User>("test_db", "SELECT id, name FROM users"),
+            Err(DBError::UnknownColumn(_))
+        ));
+        // NULL into a field which isn't an Option
+        #[derive(FromRow, Debug)]
+        #[rrrdb(crate = "crate::rrrdb")]
+        struct Name {
+            name: String,
+        }
+        assert!(matches!(
+            rrrdb.query_as::<Name>("test_db", "SELECT name FROM users WHERE id = 2"),
+            Err(DBError::TypeMismatch { .. })
+        ));
+        // names are quoted in the statement
+        let err = rrrdb
+            .insert(
+                "test_db",
+                "users; DROP",
+                &User {
+                    id: 3,
+                    user_name: None,
+                    attrs: None,
+                },
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.without_location(),
+            &DBError::UnknownTable("users; DROP".to_string())
+        );
+    }
+
+    #[test]
+    fn table_definition() {
+        let rrrdb = build_crean_database("./test_tmp_database_table_definition");
+        rrrdb.execute("test_db", "CREATE DATABASE copy_db").unwrap();
+        let statements = vec![
+            "CREATE TABLE users (id integer AUTO_INCREMENT, name varchar NOT NULL DEFAULT 'x' CHECK (name != ''), attrs json, UNIQUE (name))",
+            "CREATE TABLE posts (id integer, user_id integer REFERENCES users(id) ON DELETE SET NULL, body varchar CHECK (body -> 'lang' = 'en'))",
+            "CREATE TABLE \"user tags\" (\"select\" varchar DEFAULT 'it''s' CHECK (\"select\" != ''), \"a\"\"b\" integer)",
+        ];
+        for statement in statements {
+            rrrdb.execute("test_db", statement).unwrap();
+        }
+        assert_eq!(
+            rrrdb.table_names("test_db").unwrap(),
+            vec![
+                "users".to_string(),
+                "posts".to_string(),
+                "user tags".to_string()
+            ]
+        );
+        // names are quoted unless they are plain words
+        assert_eq!(
+            rrrdb.table_definition("test_db", "user tags").unwrap(),
+            "CREATE TABLE \"user tags\" (\n  \
+             \"select\" varchar DEFAULT 'it''s' CHECK (\"select\" != ''),\n  \
+             \"a\"\"b\" integer\n\
+             )"
+        );
+        assert_eq!(
+            rrrdb.table_definition("test_db", "posts").unwrap(),
+            "CREATE TABLE posts (\n  \
+             id integer,\n  \
+             user_id integer,\n  \
+             body varchar CHECK (body->'$.lang' = 'en'),\n  \
+             CONSTRAINT posts_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL\n\
+             )"
+        );
+        assert_eq!(
+            rrrdb.table_definition("test_db", "comments"),
+            Err(DBError::UnknownTable("comments".to_string()))
+        );
+
+        // the definitions create the same tables
+        for table_name in rrrdb.table_names("test_db").unwrap() {
+            let definition = rrrdb.table_definition("test_db", &table_name).unwrap();
+            rrrdb.execute("copy_db", &definition).unwrap();
+            assert_eq!(
+                rrrdb.table_definition("copy_db", &table_name).unwrap(),
+                definition
+            );
+        }
+    }
+
+    #[test]
+    fn keywords_as_names() {
+        let rrrdb = build_crean_database("./test_tmp_database_keywords_as_names");
+        let mut session = rrrdb.session();
+        session.execute("USE test_db").unwrap();
+        for statement in &[
+            "CREATE TABLE kv (id serial, key varchar, level integer, start integer DEFAULT 0, check integer CHECK (check > 0))",
+            "CREATE SEQUENCE sequence START WITH 10",
+            "INSERT INTO kv (key, level, check) VALUES ('a', 1, nextval('sequence'))",
+            "INSERT INTO kv (key, level, check) VALUES ('b', 2, nextval('sequence'))",
+        ] {
+            session.execute(statement).unwrap();
+        }
+        let result_set = match session
+            .execute("SELECT key, check FROM kv WHERE level = 1")
+            .unwrap()
+        {
+            OkDBResult::SelectResult(result_set) => result_set,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(result_set.columns(), vec!["key", "check"]);
+        let record = result_set.iter().next().unwrap();
+        assert_eq!(record.get_str("key").unwrap(), Some("a"));
+        assert_eq!(record.get_i64("check").unwrap(), Some(10));
+
+        // keywords are still keywords where the grammar expects them
+        session
+            .execute("set transaction isolation level repeatable read")
+            .unwrap();
+        session
+            .execute("CREATE TABLE use (by integer, with varchar)")
+            .unwrap();
+        session.execute("CREATE DATABASE set").unwrap();
+        session.execute("USE set").unwrap();
+        assert_eq!(session.table_names().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn located_errors() {
+        let rrrdb = build_crean_database("./test_tmp_database_located_errors");
+        rrrdb
+            .execute("test_db", "CREATE TABLE users (id integer, name varchar)")
+            .unwrap();
+        let location = |err: &DBError| err.location().map(|l| (l.line, l.column));
+
+        let err = rrrdb
+            .execute("test_db", "SELECT id FROM users\nWHERE age = 1")
+            .unwrap_err();
+        assert_eq!(
+            err.without_location(),
+            &DBError::UnknownColumn("age".to_string())
+        );
+        assert_eq!(location(&err), Some((2, 7)));
+        assert_eq!(
+            err.to_string(),
+            "column age not found at line 2, column 7\nWHERE age = 1\n      ^"
+        );
+
+        let err = rrrdb
+            .query("test_db", "SELECT id, age FROM users")
+            .err()
+            .unwrap();
+        assert_eq!(location(&err), Some((1, 12)));
+        let err = rrrdb
+            .execute("test_db", "SELECT id FROM teams")
+            .unwrap_err();
+        assert_eq!(location(&err), Some((1, 16)));
+        let err = rrrdb
+            .execute("test_db", "INSERT INTO users (id, age) VALUES (1, 2)")
+            .unwrap_err();
+        assert_eq!(location(&err), Some((1, 24)));
+
+        // the mismatched value, not the column it's for
+        let err = rrrdb
+            .execute("test_db", "INSERT INTO users VALUES ('abc', 'Alice')")
+            .unwrap_err();
+        assert!(
+            matches!(err.without_location(), DBError::TypeMismatch { .. }),
+            "err: {:?}",
+            err
+        );
+        assert_eq!(location(&err), Some((1, 27)));
+        let err = rrrdb
+            .prepare("test_db", "INSERT INTO users VALUES (1, 'Alice', 2)")
+            .and_then(|prepared| rrrdb.execute_prepared(&prepared, Params::default()))
+            .unwrap_err();
+        assert_eq!(location(&err), Some((1, 39)));
+        assert!(err
+            .to_string()
+            .ends_with("VALUES (1, 'Alice', 2)\n                                      ^"));
+
+        // values bound to placeholders are located at the placeholder
+        let prepared = rrrdb
+            .prepare("test_db", "INSERT INTO users VALUES (?, ?)")
+            .unwrap();
+        let err = rrrdb
+            .execute_prepared(
+                &prepared,
+                vec![FieldValue::Text("abc".to_string()), FieldValue::Null],
+            )
+            .unwrap_err();
+        assert_eq!(location(&err), Some((1, 27)));
+    }
+
+    #[test]
+    fn error_kinds() {
+        let rrrdb = build_crean_database("./test_tmp_database_error_kinds");
+        rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (id integer, name varchar, UNIQUE (name))",
+            )
+            .unwrap();
+
+        let err = rrrdb
+            .execute("test_db", "INSERT users VALUES (1)")
+            .unwrap_err();
+        assert!(matches!(err, DBError::Syntax { .. }), "err: {:?}", err);
+        assert_eq!(
+            rrrdb
+                .execute("test_db", "INSERT INTO teams VALUES (1)")
+                .unwrap_err()
+                .without_location(),
+            &DBError::UnknownTable("teams".to_string())
+        );
+        assert_eq!(
+            rrrdb
+                .execute("test_db", "INSERT INTO users (id, age) VALUES (1, 2)")
+                .unwrap_err()
+                .without_location(),
+            &DBError::UnknownColumn("users.age".to_string())
+        );
+        assert_eq!(
+            rrrdb
+                .execute("test_db", "CREATE TABLE users (id integer)")
+                .unwrap_err(),
+            DBError::AlreadyExists("table users".to_string())
+        );
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (1, 'Alice')")
+            .unwrap();
+        let err = rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (2, 'Alice')")
+            .unwrap_err();
+        match &err {
+            DBError::ConstraintViolation { constraint, .. } => {
+                assert_eq!(constraint, "users_name_key")
+            }
+            _ => panic!("err: {:?}", err),
+        }
+        // usable as a boxed error
+        let boxed: Box<dyn std::error::Error> = Box::new(err);
+        assert!(boxed.to_string().starts_with("constraint violation"));
+    }
+
+    #[test]
+    fn malformed_statements() {
+        let rrrdb = build_crean_database("./test_tmp_database_malformed");
+        rrrdb
+            .execute("test_db", "CREATE TABLE users (id integer, name varchar)")
+            .unwrap();
+        rrrdb
+            .execute("test_db", "INSERT INTO users VALUES (1, 'Alice')")
+            .unwrap();
+        for query in &[
+            "",
+            "SELECT",
+            "SELEC * FROM users",
+            "SELECT * FROM",
+            "SELECT * FROM users WHERE id =",
+            "SELECT * FROM users WHERE id = name",
+            "SELECT * FROM users WHERE id = 1.5",
+            "SELECT * FROM teams",
+            "SELECT age FROM users",
+            "SELECT id FROM users WHERE age = 1",
+            "SELECT 1 FROM users",
+            "SELECT id + 1 FROM users",
+            "SELECT nextval('a') FROM users",
+            "SELECT id-> FROM users",
+            "SELECT id->'a'->> FROM users",
+            "SELECT * FROM users WHERE id->",
+            "INSERT",
+            "INSERT INTO",
+            "INSERT INTO users",
+            "INSERT INTO users VALUES",
+            "INSERT INTO users VALUES (",
+            "INSERT INTO users VALUES (1,",
+            "INSERT INTO users VALUES (1 + 1, 'a')",
+            "INSERT INTO users (id, name VALUES (1, 'a')",
+            "INSERT INTO users VALUES (nextval(1), 'a')",
+            "INSERT INTO users VALUES (unknown(), 'a')",
+            "CREATE",
+            "CREATE TABLE",
+            "CREATE TABLE t",
+            "CREATE TABLE t (",
+            "CREATE TABLE t (id float)",
+            "CREATE TABLE t (id integer CHECK)",
+            "CREATE TABLE t (id integer CHECK (id >",
+            "CREATE TABLE t (id integer DEFAULT)",
+            "CREATE TABLE t (id integer REFERENCES)",
+            "CREATE SEQUENCE",
+            "CREATE SEQUENCE s START WITH",
+            "(((",
+            ")",
+            "'unterminated",
+            "BEGIN BEGIN",
+            "COMMIT",
+            "SET",
+            "SET TRANSACTION ISOLATION LEVEL",
+            "USE",
+        ] {
+            let result = rrrdb.execute("test_db", query);
+            assert!(result.is_err(), "query: {}, result: {:?}", query, result);
+        }
+        rrrdb
+            .execute("unknown_db", "SELECT * FROM users")
+            .unwrap_err();
+        rrrdb
+            .execute("test_db", "CREATE TABLE names (name varchar)")
+            .unwrap();
+        rrrdb
+            .execute("test_db", "INSERT INTO names VALUES ('Alice')")
+            .unwrap_err();
+        // compared as they are
+        for query in &[
+            "SELECT * FROM users WHERE id = 'abc'",
+            "SELECT * FROM users WHERE name = 1",
+        ] {
+            match rrrdb.execute("test_db", query).unwrap() {
+                OkDBResult::SelectResult(result_set) => {
+                    assert_eq!(result_set.records.len(), 0, "query: {}", query)
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn execute_batch() {
+        let rrrdb = build_crean_database("./test_tmp_database_execute_batch");
+        let results = rrrdb
+            .execute_batch(
+                "test_db",
+                "CREATE TABLE users (id integer, name varchar);\n\
+                 INSERT INTO users VALUES (1, 'Alice;');\n\
+                 INSERT INTO users VALUES (2, 'Bob');\n\
+                 SELECT name FROM users WHERE id = 1;\n",
+            )
+            .unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(
+            results[3],
+            OkDBResult::SelectResult(ResultSet::new(
+                vec![Record::new(vec![FieldValue::from("Alice;")])],
+                ResultMetadata::new(vec![FieldMetadata::new("name", "varchar")])
+            ))
+        );
+
+        // stops at the failing statement, and points at what's wrong in the script
+        let err = rrrdb
+            .execute_batch(
+                "test_db",
+                "INSERT INTO users VALUES (3, 'Carol');\n\
+                 INSERT INTO teams VALUES (1);\n\
+                 INSERT INTO users VALUES (4, 'Dave');",
+            )
+            .unwrap_err();
+        assert_eq!(
+            (err.index, err.location.line, err.error.without_location()),
+            (1, 2, &DBError::UnknownTable("teams".to_string()))
+        );
+        assert_eq!(
+            err.to_string(),
+            "statement 2 at line 2 failed: table teams not found at line 2, column 13\n\
+             INSERT INTO teams VALUES (1);\n            ^"
+        );
+        let count = |rrrdb: &RrrDB| {
+            rrrdb
+                .query("test_db", "SELECT * FROM users")
+                .unwrap()
+                .count()
+        };
+        assert_eq!(count(&rrrdb), 3);
+
+        // nothing runs with a syntax error
+        let err = rrrdb
+            .execute_batch(
+                "test_db",
+                "INSERT INTO users VALUES (5, 'Eve'); INSERT users VALUES (6)",
+            )
+            .unwrap_err();
+        assert_eq!((err.index, err.location.column), (1, 38));
+        assert!(matches!(err.error, DBError::Syntax { .. }));
+        assert_eq!(count(&rrrdb), 3);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let rrrdb = build_crean_database("./test_tmp_database_syntax_errors");
+        let err = rrrdb
+            .execute("test_db", "INSERT users VALUES (1)")
+            .unwrap_err();
+        match &err {
+            DBError::Syntax {
+                location: Some(location),
+                ..
+            } => assert_eq!((location.line, location.column), (1, 8)),
+            _ => panic!("err: {:?}", err),
+        }
+        assert_eq!(
+            err.to_string(),
+            "syntax error at line 1, column 8: \
+             Unexpected token found while processing insert into statement. token: 'Word(\"users\")'\n\
+             INSERT users VALUES (1)\n       ^"
+        );
+
+        // on the second line, and at the end of the query
+        let err = rrrdb
+            .execute(
+                "test_db",
+                "CREATE TABLE users (\n\tid integer CHECK (id > 0) DEFAULT",
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().ends_with(
+                "\n\tid integer CHECK (id > 0) DEFAULT\n\t                                 ^"
+            ),
+            "err: {}",
+            err
+        );
+        let err = rrrdb
+            .execute("test_db", "SELECT * FROM users WHERE id !x 1")
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("syntax error at line 1, column 30: "),
+            "err: {}",
+            err
+        );
+    }
+
+    fn build_crean_database(path: &str) -> RrrDB {
         if Path::new(path).exists() {
             std::fs::remove_dir_all(path).unwrap();
             thread::sleep(time::Duration::from_millis(100));
@@ -5,6 +5,7 @@ use std::{
 };
 
 pub(crate) use ast::*;
+pub use tokenizer::Location;
 use tokenizer::*;
 
 mod ast;
@@ -14,12 +15,24 @@ mod tokenizer;
 pub(crate) enum ParserError {
     TokenizeError(String),
     ParseError(String),
+    // any of the above, located in the query
+    SyntaxError {
+        message: String,
+        location: Location,
+        source_line: String,
+    },
 }
 impl ToString for ParserError {
     fn to_string(&self) -> String {
         match self {
             ParserError::TokenizeError(msg) => format!("TokenizeError: {}", msg),
             ParserError::ParseError(msg) => format!("ParseError: {}", msg),
+            ParserError::SyntaxError {
+                message, location, ..
+            } => format!(
+                "SyntaxError at line {}, column {}: {}",
+                location.line, location.column, message
+            ),
         }
     }
 }
@@ -29,37 +42,238 @@ impl From<TokenizeError> for ParserError {
     }
 }
 
+// a statement of a script, as written
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScriptStatement {
+    // without its `;`
+    pub text: String,
+    // where the statement starts in the script
+    pub location: Location,
+    // by `;`, which the last statement of a script may miss
+    pub terminated: bool,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct Parser {
     tokens: Vec<Token>,
+    // of each token, and of the end of the query
+    locations: Vec<Location>,
+    end: Location,
     pos: usize,
     database_name: Option<String>,
+    // `?`s seen so far, which are numbered like `$1`
+    placeholders: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>, database_name: Option<String>) -> Self {
         Self {
             tokens,
+            locations: vec![],
+            end: Location::default(),
             pos: 0,
             database_name,
+            placeholders: 0,
         }
     }
 
+    // a single statement, which may end with `;`
     pub fn parse_sql(database_name: Option<String>, query: &str) -> Result<Statement, ParserError> {
-        let tokens = Tokenizer::new(query).tokenize()?;
+        let mut statements =
+            Self::parse_script(database_name.clone(), query).map_err(|(_, _, e)| e)?;
+        match statements.len() {
+            // fails as an empty statement does
+            0 => {
+                let (_, end) = Tokenizer::new(query)
+                    .tokenize_with_locations()
+                    .map_err(|e| Self::syntax_error(query, e.message, e.location))?;
+                Self::parse_tokens(query, vec![], end, database_name)
+            }
+            1 => Ok(statements.remove(0).1),
+            _ => Err(Self::syntax_error(
+                query,
+                "only a single statement is allowed, and execute_batch runs more".to_string(),
+                statements[1].0,
+            )),
+        }
+    }
+
+    // the statements of a script separated by `;`, with where each starts. empty ones are skipped.
+    // a failure comes with the index of the statement, and where it starts
+    pub fn parse_script(
+        database_name: Option<String>,
+        script: &str,
+    ) -> Result<Vec<(Location, Statement)>, (usize, Location, ParserError)> {
+        let (tokens, end) = match Tokenizer::new(script).tokenize_with_locations() {
+            Ok(tokenized) => tokenized,
+            Err(e) => {
+                // the tokens before the failure are tokenized again to find its statement
+                let (tokens, _) = Tokenizer::new(&script[..e.location.offset])
+                    .tokenize_with_locations()
+                    .unwrap_or_default();
+                let statements = Self::split_statements(tokens);
+                let (index, location) = match statements.last() {
+                    Some((tokens, false)) => (statements.len() - 1, tokens[0].1),
+                    _ => (statements.len(), e.location),
+                };
+                return Err((
+                    index,
+                    location,
+                    Self::syntax_error(script, e.message, e.location),
+                ));
+            }
+        };
+        Self::split_statements(tokens)
+            .into_iter()
+            .enumerate()
+            .map(|(index, (tokens, terminated))| {
+                let location = tokens[0].1;
+                // a statement ends right before its `;`
+                let statement_end = if terminated {
+                    tokens[tokens.len() - 1].1
+                } else {
+                    end
+                };
+                let tokens = tokens
+                    .into_iter()
+                    .filter(|(token, _)| token != &Token::SemiColon)
+                    .collect();
+                Self::parse_tokens(script, tokens, statement_end, database_name.clone())
+                    .map(|statement| (location, statement))
+                    .map_err(|e| (index, location, e))
+            })
+            .collect()
+    }
+
+    // the statements of a script as they are written, split as `parse_script` does, so that they
+    // can be run one at a time. what can't be tokenized is left in its statement for the parser
+    // to report, unless more of the script may complete it. the statement then runs to the end
+    pub fn split_script(script: &str) -> Vec<ScriptStatement> {
+        let mut tokens = vec![];
+        let mut start = Location::default();
+        loop {
+            match Tokenizer::new(script).tokenize_from(start) {
+                Ok((rest, _)) => {
+                    tokens.extend(rest);
+                    break;
+                }
+                Err(e) => {
+                    let (rest, _) = Tokenizer::new(&script[..e.location.offset])
+                        .tokenize_from(start)
+                        .unwrap_or_default();
+                    tokens.extend(rest);
+                    // stands for the token which failed
+                    tokens.push((Token::EOF, e.location));
+                    if e.incomplete {
+                        break;
+                    }
+                    start = e.location;
+                    if let Some(ch) = script[start.offset..].chars().next() {
+                        start.advance(ch);
+                    }
+                }
+            }
+        }
+        Self::split_statements(tokens)
+            .into_iter()
+            .map(|(tokens, terminated)| {
+                let location = tokens[0].1;
+                let end = if terminated {
+                    tokens[tokens.len() - 1].1.offset
+                } else {
+                    script.len()
+                };
+                ScriptStatement {
+                    text: script[location.offset..end].trim_end().to_string(),
+                    location,
+                    terminated,
+                }
+            })
+            .collect()
+    }
+
+    // tokens of each statement, from its first token other than whitespaces to its `;` if terminated
+    fn split_statements(tokens: Vec<(Token, Location)>) -> Vec<(Vec<(Token, Location)>, bool)> {
+        let mut statements = vec![];
+        let mut current: Vec<(Token, Location)> = vec![];
+        for (token, location) in tokens {
+            match token {
+                Token::Whitespace(_) if current.is_empty() => {}
+                Token::SemiColon if current.is_empty() => {}
+                Token::SemiColon => {
+                    current.push((token, location));
+                    statements.push((std::mem::take(&mut current), true));
+                }
+                token => current.push((token, location)),
+            }
+        }
+        if !current.is_empty() {
+            statements.push((current, false));
+        }
+        statements
+    }
+
+    fn parse_tokens(
+        query: &str,
+        tokens: Vec<(Token, Location)>,
+        end: Location,
+        database_name: Option<String>,
+    ) -> Result<Statement, ParserError> {
+        let (tokens, locations) = tokens.into_iter().unzip();
         let mut parser = Self::new(tokens, database_name);
-        parser.parse()
+        parser.locations = locations;
+        parser.end = end;
+        parser.parse().map_err(|e| parser.locate(query, e))
+    }
+
+    // errors without a location point at the token being processed
+    fn locate(&self, query: &str, error: ParserError) -> ParserError {
+        let (message, location) = match error {
+            ParserError::TokenizeError(message) | ParserError::ParseError(message) => {
+                (message, self.token_location(self.pos))
+            }
+            ParserError::SyntaxError {
+                message, location, ..
+            } => (message, location),
+        };
+        Self::syntax_error(query, message, location)
     }
 
+    // of the token right before the position, which was read last
+    fn token_location(&self, pos: usize) -> Location {
+        self.locations
+            .get(pos.saturating_sub(1))
+            .copied()
+            .unwrap_or(self.end)
+    }
+
+    fn syntax_error(query: &str, message: String, location: Location) -> ParserError {
+        let source_line = query
+            .lines()
+            .nth(location.line - 1)
+            .unwrap_or_default()
+            .to_string();
+        ParserError::SyntaxError {
+            message,
+            location,
+            source_line,
+        }
+    }
+
+    // the source line is filled in by `locate`
     fn unexpected_token<A, T: Debug>(
         stage: &str,
         unexpected_token: &T,
-        pos: usize,
+        location: Location,
     ) -> Result<A, ParserError> {
-        Err(ParserError::ParseError(format!(
-            "Unexpected token found while processing {}. token: '{:?}' at {}",
-            stage, unexpected_token, pos
-        )))
+        Err(ParserError::SyntaxError {
+            message: format!(
+                "Unexpected token found while processing {}. token: '{:?}'",
+                stage, unexpected_token
+            ),
+            location,
+            source_line: String::new(),
+        })
     }
 
     pub fn parse(&mut self) -> Result<Statement, ParserError> {
@@ -67,17 +281,189 @@ impl Parser {
             (Token::Keyword(tokenizer::Keyword::Select), _) => self.parse_select_statement(),
             (Token::Keyword(tokenizer::Keyword::Insert), _) => self.parse_insert_statement(),
             (Token::Keyword(tokenizer::Keyword::Create), _) => self.parse_create_statement(),
-            (unexpected_token, pos) => Self::unexpected_token("parse", unexpected_token, pos),
+            (token, _) if token.is_keyword(Keyword::Begin) => {
+                self.parse_transaction_statement(TransactionStatement::Begin)
+            }
+            (token, _) if token.is_keyword(Keyword::Start) => {
+                self.expect_keyword(Keyword::Transaction, "START TRANSACTION")?;
+                self.parse_transaction_statement(TransactionStatement::Begin)
+            }
+            (token, _) if token.is_keyword(Keyword::Commit) => {
+                self.parse_transaction_statement(TransactionStatement::Commit)
+            }
+            (token, _) if token.is_keyword(Keyword::Rollback) => {
+                self.parse_transaction_statement(TransactionStatement::Rollback)
+            }
+            (token, _) if token.is_keyword(Keyword::Set) => {
+                if self.peek_token().is_keyword(Keyword::Transaction) {
+                    self.parse_set_transaction_statement()
+                } else {
+                    self.parse_set_variable_statement()
+                }
+            }
+            (token, _) if token.is_keyword(Keyword::Use) => self.parse_use_statement(),
+            (token, _) if token.is_keyword(Keyword::Explain) => self.parse_explain_statement(),
+            (token, _) if token.is_keyword(Keyword::Analyze) => self.parse_analyze_statement(),
+            (unexpected_token, location) => {
+                Self::unexpected_token("parse", unexpected_token, location)
+            }
+        }
+    }
+
+    // ANALYZE [table]
+    fn parse_analyze_statement(&mut self) -> Result<Statement, ParserError> {
+        let table_name = match self.next_token() {
+            (Token::Word(table_name), _) => Some(table_name.to_owned()),
+            (Token::EOF, _) => None,
+            (unexpected_token, location) => {
+                return Self::unexpected_token("ANALYZE", unexpected_token, location)
+            }
+        };
+        if table_name.is_some() {
+            self.expect_token(Token::EOF, "ANALYZE")?;
+        }
+        Ok(Statement::Analyze { table_name })
+    }
+
+    // EXPLAIN [ANALYZE] statement
+    fn parse_explain_statement(&mut self) -> Result<Statement, ParserError> {
+        let analyze = self.peek_token().is_keyword(Keyword::Analyze);
+        if analyze {
+            self.next_token();
+        }
+        let statement = self.parse()?;
+        Ok(Statement::Explain {
+            statement: Box::new(statement),
+            analyze,
+        })
+    }
+
+    // SET TRANSACTION ISOLATION LEVEL { READ COMMITTED | READ UNCOMMITTED | REPEATABLE READ | SNAPSHOT }
+    fn parse_set_transaction_statement(&mut self) -> Result<Statement, ParserError> {
+        self.expect_keyword(Keyword::Transaction, "SET TRANSACTION")?;
+        self.expect_keyword(Keyword::Isolation, "SET TRANSACTION")?;
+        self.expect_keyword(Keyword::Level, "SET TRANSACTION")?;
+        let mut words = vec![];
+        loop {
+            match self.next_token() {
+                (Token::Word(word), _) => words.push(word.to_lowercase()),
+                (Token::EOF, _) => break,
+                (unexpected_token, location) => {
+                    return Self::unexpected_token("isolation level", unexpected_token, location)
+                }
+            }
+        }
+        // READ UNCOMMITTED behaves as READ COMMITTED, as it does in PostgreSQL
+        let isolation_level = match words.join(" ").as_ref() {
+            "read committed" | "read uncommitted" => IsolationLevel::ReadCommitted,
+            "repeatable read" | "snapshot" => IsolationLevel::Snapshot,
+            other => {
+                return Err(ParserError::ParseError(format!(
+                    "unsupported isolation level: {}",
+                    other
+                )))
+            }
+        };
+        Ok(Statement::Transaction(
+            TransactionStatement::SetIsolationLevel(isolation_level),
+        ))
+    }
+
+    // SET name { = | TO } value
+    fn parse_set_variable_statement(&mut self) -> Result<Statement, ParserError> {
+        let name = match self.next_token() {
+            (Token::Word(name), _) => name.to_lowercase(),
+            (unexpected_token, location) => {
+                return Self::unexpected_token("SET", unexpected_token, location)
+            }
+        };
+        match self.next_token() {
+            (Token::Eq, _) => (),
+            (Token::Word(to), _) if to.eq_ignore_ascii_case("to") => (),
+            (unexpected_token, location) => {
+                return Self::unexpected_token("SET", unexpected_token, location)
+            }
+        }
+        let value = match self.next_token() {
+            (Token::Word(value), _)
+            | (Token::Number(value), _)
+            | (Token::SingleQuotedString(value), _) => value.to_owned(),
+            (unexpected_token, location) => {
+                return Self::unexpected_token("SET", unexpected_token, location)
+            }
+        };
+        self.expect_token(Token::EOF, "SET")?;
+        Ok(Statement::Session(SessionStatement::SetVariable {
+            name,
+            value,
+        }))
+    }
+
+    // USE database_name
+    fn parse_use_statement(&mut self) -> Result<Statement, ParserError> {
+        let database_name = match self.next_token() {
+            (Token::Word(database_name), _) => database_name.to_owned(),
+            (unexpected_token, location) => {
+                return Self::unexpected_token("USE", unexpected_token, location)
+            }
+        };
+        self.expect_token(Token::EOF, "USE")?;
+        Ok(Statement::Session(SessionStatement::Use(database_name)))
+    }
+
+    fn database_name(&self) -> Result<String, ParserError> {
+        self.database_name.clone().ok_or(ParserError::ParseError(
+            "no database is selected".to_string(),
+        ))
+    }
+
+    // BEGIN [TRANSACTION] | COMMIT [TRANSACTION] | ROLLBACK [TRANSACTION]
+    fn parse_transaction_statement(
+        &mut self,
+        statement: TransactionStatement,
+    ) -> Result<Statement, ParserError> {
+        if self.peek_token().is_keyword(Keyword::Transaction) {
+            self.next_token();
+        }
+        match self.next_token() {
+            (Token::EOF, _) => Ok(Statement::Transaction(statement)),
+            (unexpected_token, location) => {
+                Self::unexpected_token("transaction statement", unexpected_token, location)
+            }
         }
     }
 
     fn parse_select_statement(&mut self) -> Result<Statement, ParserError> {
         let projections: Vec<Projection> = {
             let mut v = vec![];
-            self.consume_tokens(|token, pos| match token {
+            // Some(as_text) right after `->` or `->>`
+            let mut json_operator: Option<bool> = None;
+            self.consume_tokens(|token, location| match token {
+                Token::EOF | Token::Keyword(tokenizer::Keyword::From)
+                    if json_operator.is_some() =>
+                {
+                    Self::unexpected_token("JSON path", token, location)
+                }
                 Token::EOF => Ok(false),
                 Token::Keyword(tokenizer::Keyword::From) => Ok(false),
                 Token::Comma | Token::Whitespace(_) => Ok(true),
+                Token::Arrow | Token::LongArrow => {
+                    json_operator = Some(token == &Token::LongArrow);
+                    Ok(true)
+                }
+                path @ Token::SingleQuotedString(_) | path @ Token::Number(_)
+                    if json_operator.is_some() =>
+                {
+                    let as_text = json_operator.take().unwrap();
+                    match v.pop() {
+                        Some(Projection::Expression(target)) => {
+                            let expr = Self::build_json_extract(target, path, as_text, location)?;
+                            v.push(Projection::Expression(expr));
+                            Ok(true)
+                        }
+                        _ => Self::unexpected_token("projections", token, location),
+                    }
+                }
                 Token::Mul => {
                     v.push(Projection::Wildcard);
                     Ok(true)
@@ -95,23 +481,28 @@ impl Parser {
                     Ok(true)
                 }
                 Token::Word(ident) => {
-                    v.push(Projection::Expression(Expression::Ident(ident.to_string())));
+                    let ident = Spanned::new(ident.to_string(), location);
+                    v.push(Projection::Expression(Expression::Ident(ident)));
                     Ok(true)
                 }
-                unexpected_token => Self::unexpected_token("projections", unexpected_token, pos),
+                unexpected_token => {
+                    Self::unexpected_token("projections", unexpected_token, location)
+                }
             })?;
             v
         };
         let froms = {
-            let (token, pos) = self.next_token();
-            let mut v: Vec<String> = vec![];
+            let (token, location) = self.next_token();
+            let mut v: Vec<Spanned<String>> = vec![];
             match token {
                 Token::EOF => Ok(v),
                 Token::Word(name) => {
-                    v.push(name.to_owned());
+                    v.push(Spanned::new(name.to_owned(), location));
                     Ok(v)
                 }
-                unexpected_token => Self::unexpected_token("from statement", unexpected_token, pos),
+                unexpected_token => {
+                    Self::unexpected_token("from statement", unexpected_token, location)
+                }
             }
         }?;
         let predicate: Predicate = self.parse_predicate()?;
@@ -123,64 +514,128 @@ impl Parser {
     fn parse_insert_statement(&mut self) -> Result<Statement, ParserError> {
         match self.next_token() {
             (Token::Keyword(tokenizer::Keyword::Into), _) => {}
-            (unexpected_token, pos) => {
-                return Self::unexpected_token("insert into statement", unexpected_token, pos);
+            (unexpected_token, location) => {
+                return Self::unexpected_token("insert into statement", unexpected_token, location);
             }
         }
         match self.next_token() {
-            (Token::Word(table_name), _) => {
-                let table_name = table_name.to_owned();
+            (Token::Word(table_name), location) => {
+                let table_name = Spanned::new(table_name.to_owned(), location);
+                let columns = if self.peek_token() == Token::LParen {
+                    self.next_token();
+                    Some(self.parse_column_names()?)
+                } else {
+                    None
+                };
                 match self.next_token() {
                     (Token::Keyword(Keyword::Values), _) => {
                         let values = self.parse_insert_values()?;
-                        Ok(Statement::Insert(Insert::new(table_name, values)))
+                        let insert = match columns {
+                            Some(columns) => Insert::with_columns(table_name, columns, values),
+                            None => Insert::new(table_name, values),
+                        };
+                        Ok(Statement::Insert(insert))
                     }
-                    (unexpected_token, pos) => {
+                    (unexpected_token, location) => {
                         return Self::unexpected_token(
                             "insert into statement",
                             unexpected_token,
-                            pos,
+                            location,
                         );
                     }
                 }
             }
-            (unexpected_token, pos) => {
-                return Self::unexpected_token("insert into statement", unexpected_token, pos);
+            (unexpected_token, location) => {
+                return Self::unexpected_token("insert into statement", unexpected_token, location);
+            }
+        }
+    }
+    // :column_name(, :column_name)*\) after an opening parenthesis
+    fn parse_column_names(&mut self) -> Result<Vec<Spanned<String>>, ParserError> {
+        let mut columns = vec![];
+        loop {
+            match self.next_token() {
+                (Token::Word(column_name), location) => {
+                    columns.push(Spanned::new(column_name.to_owned(), location))
+                }
+                (unexpected_token, location) => {
+                    return Self::unexpected_token("column names", unexpected_token, location)
+                }
+            }
+            match self.next_token() {
+                (Token::Comma, _) => continue,
+                (Token::RParen, _) => break,
+                (unexpected_token, location) => {
+                    return Self::unexpected_token("column names", unexpected_token, location)
+                }
             }
         }
+        Ok(columns)
     }
-    fn parse_insert_values(&mut self) -> Result<Vec<Value>, ParserError> {
+
+    // constraints aren't planned, so they need no locations
+    fn names(columns: Vec<Spanned<String>>) -> Vec<String> {
+        columns.into_iter().map(|column| column.node).collect()
+    }
+
+    fn parse_insert_values(&mut self) -> Result<Vec<Spanned<Expression>>, ParserError> {
         match self.next_token() {
             (&Token::LParen, _) => Ok(()),
-            (unexpected_token, pos) => {
-                Self::unexpected_token("insert values", unexpected_token, pos)
+            (unexpected_token, location) => {
+                Self::unexpected_token("insert values", unexpected_token, location)
             }
         }?;
         let mut results = vec![];
-        let mut is_rparen = false;
         loop {
-            self.consume_tokens(|token, pos| match token {
-                &Token::RParen => {
-                    is_rparen = true;
-                    Ok(false)
+            let location = self.peek_location();
+            results.push(Spanned::new(self.parse_insert_value()?, location));
+            match self.next_token() {
+                (Token::Comma, _) => continue,
+                (Token::RParen, _) => break,
+                (unexpected_token, location) => {
+                    return Self::unexpected_token("insert values", unexpected_token, location)
                 }
-                &Token::EOF => Self::unexpected_token("insert values", &Token::EOF, pos),
-                &Token::Comma => Ok(false),
-                Token::Number(num) => {
-                    results.push(Value::Number(num.clone()));
-                    Ok(true)
+            }
+        }
+        Ok(results)
+    }
+
+    // :literal | :function_name\((:literal(, :literal)*)?\)
+    fn parse_insert_value(&mut self) -> Result<Expression, ParserError> {
+        let name = match self.peek_token() {
+            Token::Word(name) => {
+                let pos = self.pos;
+                self.next_token();
+                if self.peek_token() != Token::LParen {
+                    // true/false
+                    self.pos = pos;
+                    return Ok(Expression::Value(self.parse_literal("insert values")?));
                 }
-                Token::SingleQuotedString(s) => {
-                    results.push(Value::QuotedString(s.clone()));
-                    Ok(true)
+                name
+            }
+            _ => return Ok(Expression::Value(self.parse_literal("insert values")?)),
+        };
+        self.next_token();
+        let mut args = vec![];
+        if self.peek_token() == Token::RParen {
+            self.next_token();
+        } else {
+            loop {
+                args.push(Expression::Value(self.parse_literal("function arguments")?));
+                match self.next_token() {
+                    (Token::Comma, _) => continue,
+                    (Token::RParen, _) => break,
+                    (unexpected_token, location) => {
+                        return Self::unexpected_token(
+                            "function arguments",
+                            unexpected_token,
+                            location,
+                        )
+                    }
                 }
-                unexpected_token => Self::unexpected_token("insert values", unexpected_token, pos),
-            })?;
-            if is_rparen {
-                break;
             }
         }
-        Ok(results)
+        Ok(Expression::Function { name, args })
     }
 
     fn parse_create_statement(&mut self) -> Result<Statement, ParserError> {
@@ -189,19 +644,86 @@ impl Parser {
                 self.parse_create_database_statement()
             }
             (Token::Keyword(tokenizer::Keyword::Table), _) => self.parse_create_table_statement(),
-            (unexpected_token, pos) => {
-                Self::unexpected_token("create statement", unexpected_token, pos)
+            (token, _) if token.is_keyword(Keyword::Sequence) => {
+                self.parse_create_sequence_statement()
+            }
+            (unexpected_token, location) => {
+                Self::unexpected_token("create statement", unexpected_token, location)
             }
         }
     }
+
+    // create sequence :name [START [WITH] :number] [INCREMENT [BY] :number]
+    fn parse_create_sequence_statement(&mut self) -> Result<Statement, ParserError> {
+        let name = match self.next_token() {
+            (Token::Word(name), _) => name.to_owned(),
+            (unexpected_token, location) => {
+                return Self::unexpected_token(
+                    "create sequence statement",
+                    unexpected_token,
+                    location,
+                )
+            }
+        };
+        let mut start = None;
+        let mut increment = None;
+        loop {
+            match self.next_token() {
+                (token, _) if token.is_keyword(Keyword::Start) => {
+                    if self.peek_token().is_keyword(Keyword::With) {
+                        self.next_token();
+                    }
+                    start = Some(self.parse_integer("START")?);
+                }
+                (token, _) if token.is_keyword(Keyword::Increment) => {
+                    if self.peek_token().is_keyword(Keyword::By) {
+                        self.next_token();
+                    }
+                    increment = Some(self.parse_integer("INCREMENT")?);
+                }
+                (Token::EOF, _) => break,
+                (unexpected_token, location) => {
+                    return Self::unexpected_token(
+                        "create sequence statement",
+                        unexpected_token,
+                        location,
+                    )
+                }
+            }
+        }
+        if increment == Some(0) {
+            return Err(ParserError::ParseError(format!(
+                "INCREMENT of sequence {} must not be zero",
+                name
+            )));
+        }
+        Ok(Statement::CreateSequence(CreateSequence {
+            database_name: self.database_name()?,
+            name,
+            start,
+            increment,
+        }))
+    }
+
+    fn parse_integer(&mut self, stage: &str) -> Result<i64, ParserError> {
+        match self.parse_literal(stage)? {
+            Value::Number(num) => num.parse::<i64>().map_err(|_| {
+                ParserError::ParseError(format!("{} expects an integer, but got {}", stage, num))
+            }),
+            other => Err(ParserError::ParseError(format!(
+                "{} expects an integer, but got {:?}",
+                stage, other
+            ))),
+        }
+    }
     fn parse_create_database_statement(&mut self) -> Result<Statement, ParserError> {
         match self.next_token() {
             (Token::Word(database_name), _) => {
                 let stmt = Statement::CreateDatabase(CreateDatabase::new(database_name.to_owned()));
                 Ok(stmt)
             }
-            (unexpected_token, pos) => {
-                Self::unexpected_token("create database statement", unexpected_token, pos)
+            (unexpected_token, location) => {
+                Self::unexpected_token("create database statement", unexpected_token, location)
             }
         }
     }
@@ -210,62 +732,271 @@ impl Parser {
         match self.next_token() {
             (Token::Word(table_name), _) => {
                 let table_name = table_name.to_owned(); // enable to use self.database_name
-                let columns = self.parse_create_table_column_definitions()?;
-                let stmt = Statement::CreateTable(CreateTable::new(
-                    self.database_name.clone().unwrap().to_string(),
+                let (columns, constraints) = self.parse_create_table_column_definitions()?;
+                let stmt = Statement::CreateTable(CreateTable::with_constraints(
+                    self.database_name()?,
                     table_name,
                     columns,
+                    constraints,
                 ));
                 Ok(stmt)
             }
-            (unexpected_token, pos) => {
-                Self::unexpected_token("create database statement", unexpected_token, pos)
+            (unexpected_token, location) => {
+                Self::unexpected_token("create database statement", unexpected_token, location)
             }
         }
     }
 
-    // create table :table_name \((:column_name :column_type)(, :column_name :column_type)*\)
+    // create table :table_name \((:column_name :column_type :column_constraint*)(, :column_name :column_type :column_constraint*)*(, :table_constraint)*\)
     fn parse_create_table_column_definitions(
         &mut self,
-    ) -> Result<Vec<ColumnDefinition>, ParserError> {
+    ) -> Result<(Vec<ColumnDefinition>, Vec<TableConstraint>), ParserError> {
         match self.next_token() {
             (&Token::LParen, _) => Ok(()),
-            (unexpected_token, pos) => {
-                Self::unexpected_token("create table column definitions", unexpected_token, pos)
-            }
+            (unexpected_token, location) => Self::unexpected_token(
+                "create table column definitions",
+                unexpected_token,
+                location,
+            ),
         }?;
         let mut results = vec![];
-        let mut is_rparen = false;
+        let mut table_constraints = vec![];
         loop {
-            let mut v = vec![];
-            self.consume_tokens(|token, pos| match token {
-                &Token::RParen => {
-                    is_rparen = true;
-                    Ok(false)
+            if let Some(table_constraint) = self.parse_table_constraint()? {
+                table_constraints.push(table_constraint);
+                match self.next_token() {
+                    (Token::Comma, _) => continue,
+                    (Token::RParen, _) => break,
+                    (unexpected_token, location) => {
+                        return Self::unexpected_token(
+                            "create table constraints",
+                            unexpected_token,
+                            location,
+                        )
+                    }
                 }
-                &Token::EOF => {
-                    Self::unexpected_token("create table column definitions", &Token::EOF, pos)
+            }
+            let column_name = match self.next_token() {
+                (Token::Word(column_name), _) => column_name.to_owned(),
+                (unexpected_token, location) => {
+                    return Self::unexpected_token(
+                        "create table column definitions",
+                        unexpected_token,
+                        location,
+                    )
                 }
-                &Token::Comma => Ok(false),
-                token => {
-                    v.push(token.clone());
-                    Ok(true)
+            };
+            let column_type = match self.next_token() {
+                (Token::Word(column_type), _) => column_type.to_owned(),
+                (unexpected_token, location) => {
+                    return Self::unexpected_token(
+                        "create table column definitions",
+                        unexpected_token,
+                        location,
+                    )
+                }
+            };
+            let constraints = self.parse_column_constraints()?;
+            results.push(ColumnDefinition::with_constraints(
+                column_name,
+                column_type,
+                constraints,
+            ));
+            match self.next_token() {
+                (Token::Comma, _) => continue,
+                (Token::RParen, _) => break,
+                (unexpected_token, location) => {
+                    return Self::unexpected_token(
+                        "create table column definitions",
+                        unexpected_token,
+                        location,
+                    )
                 }
-            })?;
-            if let &[Token::Word(column_name), Token::Word(column_type)] = &&v[..] {
-                results.push(ColumnDefinition::new(
-                    column_name.to_owned(),
-                    column_type.to_owned(),
-                ));
-            } else {
-                return Self::unexpected_token("create table column definitions", &v, self.pos);
             }
-            if is_rparen {
-                break;
+        }
+
+        Ok((results, table_constraints))
+    }
+
+    // [CONSTRAINT :name] UNIQUE (:column_name(, :column_name)*)
+    // [CONSTRAINT :name] FOREIGN KEY (:column_name) REFERENCES :table_name(:column_name) [ON DELETE :action]
+    fn parse_table_constraint(&mut self) -> Result<Option<TableConstraint>, ParserError> {
+        let name = match self.peek_token() {
+            Token::Keyword(Keyword::Constraint) => {
+                self.next_token();
+                match self.next_token() {
+                    (Token::Word(name), _) => Some(name.to_owned()),
+                    (unexpected_token, location) => {
+                        return Self::unexpected_token("CONSTRAINT", unexpected_token, location)
+                    }
+                }
+            }
+            Token::Keyword(Keyword::Unique) | Token::Keyword(Keyword::Foreign) => None,
+            _ => return Ok(None),
+        };
+        match self.next_token() {
+            (Token::Keyword(Keyword::Unique), _) => {
+                self.expect_token(Token::LParen, "UNIQUE")?;
+                let columns = Self::names(self.parse_column_names()?);
+                Ok(Some(TableConstraint::Unique { name, columns }))
+            }
+            (Token::Keyword(Keyword::Foreign), _) => {
+                self.expect_keyword(Keyword::Key, "FOREIGN KEY")?;
+                self.expect_token(Token::LParen, "FOREIGN KEY")?;
+                let columns = Self::names(self.parse_column_names()?);
+                let column = match &columns[..] {
+                    [column] => column.to_owned(),
+                    _ => {
+                        return Err(ParserError::ParseError(format!(
+                            "FOREIGN KEY supports only a single column, but got ({})",
+                            columns.join(", ")
+                        )))
+                    }
+                };
+                self.expect_keyword(Keyword::References, "FOREIGN KEY")?;
+                let reference = self.parse_foreign_key_reference()?;
+                Ok(Some(TableConstraint::ForeignKey {
+                    name,
+                    column,
+                    reference,
+                }))
+            }
+            (unexpected_token, location) => {
+                Self::unexpected_token("table constraint", unexpected_token, location)
             }
         }
+    }
 
-        Ok(results)
+    // :table_name(:column_name) [ON DELETE RESTRICT | CASCADE | SET NULL] after REFERENCES
+    fn parse_foreign_key_reference(&mut self) -> Result<ForeignKeyReference, ParserError> {
+        let table_name = match self.next_token() {
+            (Token::Word(table_name), _) => table_name.to_owned(),
+            (unexpected_token, location) => {
+                return Self::unexpected_token("REFERENCES", unexpected_token, location)
+            }
+        };
+        self.expect_token(Token::LParen, "REFERENCES")?;
+        let column_name = match self.next_token() {
+            (Token::Word(column_name), _) => column_name.to_owned(),
+            (unexpected_token, location) => {
+                return Self::unexpected_token("REFERENCES", unexpected_token, location)
+            }
+        };
+        self.expect_token(Token::RParen, "REFERENCES")?;
+        let on_delete = if self.peek_token().is_keyword(Keyword::On) {
+            self.next_token();
+            self.expect_keyword(Keyword::Delete, "ON DELETE")?;
+            match self.next_token() {
+                (token, _) if token.is_keyword(Keyword::Restrict) => ReferentialAction::Restrict,
+                (token, _) if token.is_keyword(Keyword::Cascade) => ReferentialAction::Cascade,
+                (token, _) if token.is_keyword(Keyword::Set) => {
+                    self.expect_token(Token::Keyword(Keyword::Null), "ON DELETE SET NULL")?;
+                    ReferentialAction::SetNull
+                }
+                (unexpected_token, location) => {
+                    return Self::unexpected_token("ON DELETE", unexpected_token, location)
+                }
+            }
+        } else {
+            ReferentialAction::Restrict
+        };
+        Ok(ForeignKeyReference {
+            table_name,
+            column_name,
+            on_delete,
+        })
+    }
+
+    // [NOT NULL | NULL] [DEFAULT :literal] [CHECK (:expression)] [UNIQUE] [REFERENCES ...] [AUTO_INCREMENT] in any order
+    fn parse_column_constraints(&mut self) -> Result<Vec<ColumnConstraint>, ParserError> {
+        let mut constraints = vec![];
+        loop {
+            match self.peek_token() {
+                token if token.is_keyword(Keyword::Not) => {
+                    self.next_token();
+                    match self.next_token() {
+                        (Token::Keyword(Keyword::Null), _) => {
+                            constraints.push(ColumnConstraint::NotNull)
+                        }
+                        (unexpected_token, location) => {
+                            return Self::unexpected_token("NOT NULL", unexpected_token, location)
+                        }
+                    }
+                }
+                Token::Keyword(Keyword::Null) => {
+                    // explicitly nullable, which is the default
+                    self.next_token();
+                }
+                token if token.is_keyword(Keyword::Default) => {
+                    self.next_token();
+                    let value = self.parse_literal("column default")?;
+                    constraints.push(ColumnConstraint::Default(value));
+                }
+                token if token.is_keyword(Keyword::Check) => {
+                    self.next_token();
+                    match self.next_token() {
+                        (Token::LParen, _) => {}
+                        (unexpected_token, location) => {
+                            return Self::unexpected_token("CHECK", unexpected_token, location)
+                        }
+                    }
+                    let expr = self.parse_expression(None)?;
+                    match self.next_token() {
+                        (Token::RParen, _) => {}
+                        (unexpected_token, location) => {
+                            return Self::unexpected_token("CHECK", unexpected_token, location)
+                        }
+                    }
+                    constraints.push(ColumnConstraint::Check(expr));
+                }
+                Token::Keyword(Keyword::Unique) => {
+                    self.next_token();
+                    constraints.push(ColumnConstraint::Unique);
+                }
+                Token::Word(word) if word.to_lowercase() == "auto_increment" => {
+                    self.next_token();
+                    constraints.push(ColumnConstraint::AutoIncrement);
+                }
+                token if token.is_keyword(Keyword::References) => {
+                    self.next_token();
+                    let reference = self.parse_foreign_key_reference()?;
+                    constraints.push(ColumnConstraint::References(reference));
+                }
+                _ => return Ok(constraints),
+            }
+        }
+    }
+
+    fn parse_literal(&mut self, stage: &str) -> Result<Value, ParserError> {
+        match self.next_token() {
+            (Token::Number(num), _) => Ok(Value::Number(num.to_owned())),
+            (Token::Minus, _) => match self.next_token() {
+                (Token::Number(num), _) => Ok(Value::Number(format!("-{}", num))),
+                (unexpected_token, location) => {
+                    Self::unexpected_token(stage, unexpected_token, location)
+                }
+            },
+            (Token::SingleQuotedString(s), _) => Ok(Value::QuotedString(s.to_owned())),
+            (Token::Keyword(Keyword::Null), _) => Ok(Value::Null),
+            (Token::Placeholder(p), _) => {
+                let p = p.to_owned();
+                Ok(self.placeholder(p))
+            }
+            (Token::Word(word), _) if word.to_lowercase() == "true" => Ok(Value::Boolean(true)),
+            (Token::Word(word), _) if word.to_lowercase() == "false" => Ok(Value::Boolean(false)),
+            (unexpected_token, location) => {
+                Self::unexpected_token(stage, unexpected_token, location)
+            }
+        }
+    }
+
+    fn placeholder(&mut self, placeholder: String) -> Value {
+        if placeholder == "?" {
+            self.placeholders += 1;
+            Value::Placeholder(format!("${}", self.placeholders))
+        } else {
+            Value::Placeholder(placeholder)
+        }
     }
 
     // return true if the next token is EOF, otherwise false
@@ -287,7 +1018,7 @@ impl Parser {
     }
 
     fn parse_predicate(&mut self) -> Result<Predicate, ParserError> {
-        if let (&Token::Keyword(tokenizer::Keyword::Where), pos) = self.next_token() {
+        if let (&Token::Keyword(tokenizer::Keyword::Where), _) = self.next_token() {
             loop {
                 if self.skip_stop_words()? {
                     break;
@@ -304,7 +1035,7 @@ impl Parser {
         &mut self,
         processing: Option<Expression>,
     ) -> Result<Expression, ParserError> {
-        let (token, pos) = self.next_token();
+        let (token, location) = self.next_token();
         match token {
             Token::SingleQuotedString(s) => {
                 let expr = Expression::quoted_string(&s);
@@ -318,16 +1049,31 @@ impl Parser {
                 let expr = match ident.as_str() {
                     "true" => Expression::boolean(true),
                     "false" => Expression::boolean(false),
-                    s => Expression::ident(s),
+                    s => Expression::Ident(Spanned::new(s.to_string(), location)),
                 };
                 self.continue_parse_expr(expr, processing)
             }
+            Token::Keyword(Keyword::Null) => {
+                self.continue_parse_expr(Expression::null(), processing)
+            }
+            Token::Placeholder(p) => {
+                let p = p.to_owned();
+                let expr = Expression::Value(self.placeholder(p));
+                self.continue_parse_expr(expr, processing)
+            }
+            // a closing parenthesis or a comma ends the expression, e.g. `CHECK (age >= 0)`
+            Token::RParen | Token::Comma if processing.is_some() => {
+                self.prev_token();
+                Ok(processing.unwrap())
+            }
             Token::Eq => self.build_binoperator(BinaryOperator::Eq, processing),
             Token::Neq => self.build_binoperator(BinaryOperator::Neq, processing),
             Token::Lt => self.build_binoperator(BinaryOperator::Lt, processing),
             Token::Lte => self.build_binoperator(BinaryOperator::Lte, processing),
             Token::Gt => self.build_binoperator(BinaryOperator::Gt, processing),
             Token::Gte => self.build_binoperator(BinaryOperator::Gte, processing),
+            Token::Arrow => self.build_json_extract_operator(false, processing),
+            Token::LongArrow => self.build_json_extract_operator(true, processing),
             // Token::Plus => { Ok(BinaryOperator::Plus)},
             // Token::Minus => { Ok(BinaryOperator::Minus)},
             // Token::Mul => { Ok(BinaryOperator::Mul)},
@@ -340,7 +1086,7 @@ impl Parser {
             Token::EOF => processing.ok_or(ParserError::ParseError(format!(
                 "Unexpected EOF while parse_expression",
             ))),
-            unexpected_token => Self::unexpected_token("expression", unexpected_token, pos),
+            unexpected_token => Self::unexpected_token("expression", unexpected_token, location),
         }
     }
 
@@ -365,7 +1111,66 @@ impl Parser {
                 let right = self.parse_expression(None)?;
                 Ok(op.build(left, right))
             }
-            None => Self::unexpected_token("Left expression", &op, self.pos),
+            None => Self::unexpected_token("Left expression", &op, self.token_location(self.pos)),
+        }
+    }
+
+    fn build_json_extract_operator(
+        &mut self,
+        as_text: bool,
+        processing: Option<Expression>,
+    ) -> Result<Expression, ParserError> {
+        match processing {
+            Some(target) => {
+                let (token, location) = self.next_token();
+                let token = token.clone();
+                let expr = Self::build_json_extract(target, &token, as_text, location)?;
+                self.parse_expression(Some(expr))
+            }
+            None => {
+                let operator = if as_text {
+                    Token::LongArrow
+                } else {
+                    Token::Arrow
+                };
+                Self::unexpected_token("Left expression", &operator, self.token_location(self.pos))
+            }
+        }
+    }
+
+    fn build_json_extract(
+        target: Expression,
+        path_token: &Token,
+        as_text: bool,
+        location: Location,
+    ) -> Result<Expression, ParserError> {
+        let path = match path_token {
+            Token::SingleQuotedString(s) => JsonPath::parse(s).map_err(ParserError::ParseError)?,
+            Token::Number(num) => match num.parse::<usize>() {
+                Ok(index) => JsonPath::new(vec![JsonPathElement::Index(index)]),
+                Err(_) => return Self::unexpected_token("JSON path", path_token, location),
+            },
+            unexpected_token => {
+                return Self::unexpected_token("JSON path", unexpected_token, location)
+            }
+        };
+        match target {
+            // `attrs->'a'->>'b'` is flattened into a single extraction of `$.a.b`
+            Expression::JsonExtract {
+                target,
+                path: parent,
+                as_text: false,
+            } => Ok(Expression::JsonExtract {
+                target,
+                path: parent.append(path),
+                as_text,
+            }),
+            Expression::Ident(_) => Ok(Expression::JsonExtract {
+                target: Box::new(target),
+                path,
+                as_text,
+            }),
+            unexpected => Self::unexpected_token("JSON extraction", &unexpected, location),
         }
     }
 
@@ -384,27 +1189,61 @@ impl Parser {
         }
     }
 
-    fn next_token(&mut self) -> (&Token, usize) {
+    fn expect_keyword(&mut self, expected: Keyword, stage: &str) -> Result<(), ParserError> {
+        match self.next_token() {
+            (token, _) if token.is_keyword(expected) => Ok(()),
+            (unexpected_token, location) => {
+                Self::unexpected_token(stage, unexpected_token, location)
+            }
+        }
+    }
+
+    fn expect_token(&mut self, expected: Token, stage: &str) -> Result<(), ParserError> {
+        match self.next_token() {
+            (token, _) if token == &expected => Ok(()),
+            (unexpected_token, location) => {
+                Self::unexpected_token(stage, unexpected_token, location)
+            }
+        }
+    }
+
+    fn peek_token(&mut self) -> Token {
+        let pos = self.pos;
+        let token = self.next_token().0.clone();
+        self.pos = pos;
+        token
+    }
+
+    fn peek_location(&mut self) -> Location {
+        let pos = self.pos;
+        let location = self.next_token().1;
+        self.pos = pos;
+        location
+    }
+
+    // the next token other than whitespaces, and where it starts
+    fn next_token(&mut self) -> (&Token, Location) {
         if self.pos >= self.tokens.len() {
-            return (&Token::EOF, self.pos);
+            return (&Token::EOF, self.end);
         }
         loop {
             self.pos += 1;
+            let location = self.token_location(self.pos);
             match self.tokens.get(self.pos - 1) {
                 Some(Token::Whitespace(_)) => continue,
-                Some(token) => return (token, self.pos),
-                None => return (&Token::EOF, self.pos),
+                Some(token) => return (token, location),
+                None => return (&Token::EOF, location),
             }
         }
     }
 
     fn consume_tokens(
         &mut self,
-        mut consumer: impl FnMut(&Token, usize) -> Result<bool, ParserError>,
+        mut consumer: impl FnMut(&Token, Location) -> Result<bool, ParserError>,
     ) -> Result<(), ParserError> {
         loop {
-            let (t, pos) = self.next_token();
-            let _continue = consumer(t, pos)?;
+            let (t, location) = self.next_token();
+            let _continue = consumer(t, location)?;
             if _continue {
                 continue;
             } else {
@@ -454,7 +1293,7 @@ mod tests {
             ],
             Statement::Select(Query::new(
                 vec![Projection::Wildcard],
-                vec!["users".to_string()],
+                vec!["users".into()],
                 Predicate::empty(),
             )),
         );
@@ -483,9 +1322,9 @@ mod tests {
             ],
             Statement::Select(Query::new(
                 vec![Projection::Wildcard],
-                vec!["users".to_string()],
+                vec!["users".into()],
                 Predicate::new(Expression::BinOperator {
-                    lhs: Box::new(Expression::Ident("id".to_string())),
+                    lhs: Box::new(Expression::ident("id")),
                     rhs: Box::new(Expression::Value(Value::Number("1".to_string()))),
                     op: BinaryOperator::Eq,
                 }),
@@ -493,6 +1332,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_placeholders() {
+        let parse = |sql| Parser::parse_sql(Some("test_db".to_string()), sql).unwrap();
+        let placeholder = |p: &str| Expression::Value(Value::Placeholder(p.to_string()));
+        assert_eq!(
+            parse("SELECT * FROM users WHERE id = ?"),
+            Statement::Select(Query::new(
+                vec![Projection::Wildcard],
+                vec!["users".into()],
+                Predicate::new(Expression::BinOperator {
+                    lhs: Box::new(Expression::ident("id")),
+                    rhs: Box::new(placeholder("$1")),
+                    op: BinaryOperator::Eq,
+                }),
+            ))
+        );
+        assert_eq!(
+            parse("INSERT INTO users VALUES (?, :name, ?, $1)"),
+            Statement::Insert(Insert {
+                table_name: "users".into(),
+                columns: None,
+                values: vec![
+                    placeholder("$1").into(),
+                    placeholder(":name").into(),
+                    placeholder("$2").into(),
+                    placeholder("$1").into(),
+                ],
+            })
+        );
+    }
+
     #[test]
     fn parse_create_database() {
         parser_assertion(
@@ -562,15 +1432,605 @@ mod tests {
                 Token::RParen,
             ],
             Statement::Insert(Insert::new(
-                "users".to_string(),
+                "users".into(),
                 vec![
-                    Value::Number("1".to_string()),
-                    Value::QuotedString("alice".to_string()),
+                    Expression::number("1").into(),
+                    Expression::quoted_string("alice").into(),
                 ],
             )),
         );
     }
 
+    #[test]
+    fn parse_select_json_extract() {
+        parser_assertion(
+            vec![
+                // SELECT attrs->'address'->>'city' FROM users WHERE attrs->>'$.tags[0]' = 'admin'
+                Token::Keyword(Keyword::Select),
+                Token::Whitespace(Whitespace::Space),
+                Token::Word("attrs".to_string()),
+                Token::Arrow,
+                Token::SingleQuotedString("address".to_string()),
+                Token::LongArrow,
+                Token::SingleQuotedString("city".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::Keyword(Keyword::From),
+                Token::Whitespace(Whitespace::Space),
+                Token::Word("users".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::Keyword(Keyword::Where),
+                Token::Whitespace(Whitespace::Space),
+                Token::Word("attrs".to_string()),
+                Token::LongArrow,
+                Token::SingleQuotedString("$.tags[0]".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::Eq,
+                Token::Whitespace(Whitespace::Space),
+                Token::SingleQuotedString("admin".to_string()),
+            ],
+            Statement::Select(Query::new(
+                vec![Projection::Expression(Expression::JsonExtract {
+                    target: Box::new(Expression::ident("attrs")),
+                    path: JsonPath::new(vec![
+                        JsonPathElement::Key("address".to_string()),
+                        JsonPathElement::Key("city".to_string()),
+                    ]),
+                    as_text: true,
+                })],
+                vec!["users".into()],
+                Predicate::new(Expression::BinOperator {
+                    lhs: Box::new(Expression::JsonExtract {
+                        target: Box::new(Expression::ident("attrs")),
+                        path: JsonPath::new(vec![
+                            JsonPathElement::Key("tags".to_string()),
+                            JsonPathElement::Index(0),
+                        ]),
+                        as_text: true,
+                    }),
+                    rhs: Box::new(Expression::Value(Value::QuotedString("admin".to_string()))),
+                    op: BinaryOperator::Eq,
+                }),
+            )),
+        );
+    }
+
+    #[test]
+    fn parse_invalid_json_path() {
+        let result = Parser::parse_sql(
+            Some("test_db".to_string()),
+            "SELECT attrs->'$.tags[x]' FROM users",
+        );
+        assert!(result.is_err(), "result: {:?}", result);
+        let result = Parser::parse_sql(
+            Some("test_db".to_string()),
+            "SELECT attrs->'$.\"tags' FROM users",
+        );
+        assert!(result.is_err(), "result: {:?}", result);
+    }
+
+    #[test]
+    fn json_path_round_trip() {
+        let parse = |sql: &str| match Parser::parse_sql(Some("test_db".to_string()), sql) {
+            Ok(Statement::Select(query)) => query.projections,
+            other => panic!("unexpected result {:?}", other),
+        };
+        for (sql, displayed) in &[
+            ("attrs->'address'->>'city'", "attrs->>'$.address.city'"),
+            ("attrs->'it''s'", "attrs->'$.\"it''s\"'"),
+            ("attrs->'first name'->0", "attrs->'$.\"first name\"[0]'"),
+            (
+                "attrs->>'$.\"a.b\".\"[0]\".\"say \"\"hi\"\"\"[1]'",
+                "attrs->>'$.\"a.b\".\"[0]\".\"say \"\"hi\"\"\"[1]'",
+            ),
+            ("attrs->'$.\"\"'", "attrs->'$.\"\"'"),
+        ] {
+            let projections = parse(&format!("SELECT {} FROM users", sql));
+            let expression = match &projections[0] {
+                Projection::Expression(expression) => expression.to_string(),
+                other => panic!("unexpected projection {:?}", other),
+            };
+            assert_eq!(&expression, displayed);
+            assert_eq!(
+                parse(&format!("SELECT {} FROM users", expression)),
+                projections
+            );
+        }
+    }
+
+    #[test]
+    fn parse_create_table_with_constraints() {
+        let result = Parser::parse_sql(
+            Some("test_db".to_string()),
+            "CREATE TABLE users (id integer NOT NULL, name varchar DEFAULT 'anonymous' NOT NULL, age integer NULL CHECK (age >= 0))",
+        );
+        assert_eq!(
+            result,
+            Ok(Statement::CreateTable(CreateTable::new(
+                "test_db".to_string(),
+                "users".to_string(),
+                vec![
+                    ColumnDefinition::with_constraints(
+                        "id".to_string(),
+                        "integer".to_string(),
+                        vec![ColumnConstraint::NotNull],
+                    ),
+                    ColumnDefinition::with_constraints(
+                        "name".to_string(),
+                        "varchar".to_string(),
+                        vec![
+                            ColumnConstraint::Default(Value::QuotedString("anonymous".to_string())),
+                            ColumnConstraint::NotNull,
+                        ],
+                    ),
+                    ColumnDefinition::with_constraints(
+                        "age".to_string(),
+                        "integer".to_string(),
+                        vec![ColumnConstraint::Check(
+                            BinaryOperator::Gte
+                                .build(Expression::ident("age"), Expression::number("0"),)
+                        )],
+                    ),
+                ],
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_create_table_with_unique() {
+        let result = Parser::parse_sql(
+            Some("test_db".to_string()),
+            "CREATE TABLE users (id integer, email varchar UNIQUE, name varchar, CONSTRAINT users_name_email UNIQUE (name, email))",
+        );
+        assert_eq!(
+            result,
+            Ok(Statement::CreateTable(CreateTable::with_constraints(
+                "test_db".to_string(),
+                "users".to_string(),
+                vec![
+                    ColumnDefinition::new("id".to_string(), "integer".to_string()),
+                    ColumnDefinition::with_constraints(
+                        "email".to_string(),
+                        "varchar".to_string(),
+                        vec![ColumnConstraint::Unique],
+                    ),
+                    ColumnDefinition::new("name".to_string(), "varchar".to_string()),
+                ],
+                vec![TableConstraint::Unique {
+                    name: Some("users_name_email".to_string()),
+                    columns: vec!["name".to_string(), "email".to_string()],
+                }],
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_create_table_with_foreign_keys() {
+        let result = Parser::parse_sql(
+            Some("test_db".to_string()),
+            "CREATE TABLE posts (id integer, user_id integer REFERENCES users(id) ON DELETE CASCADE, editor varchar, FOREIGN KEY (editor) REFERENCES users(email) ON DELETE SET NULL)",
+        );
+        assert_eq!(
+            result,
+            Ok(Statement::CreateTable(CreateTable::with_constraints(
+                "test_db".to_string(),
+                "posts".to_string(),
+                vec![
+                    ColumnDefinition::new("id".to_string(), "integer".to_string()),
+                    ColumnDefinition::with_constraints(
+                        "user_id".to_string(),
+                        "integer".to_string(),
+                        vec![ColumnConstraint::References(ForeignKeyReference {
+                            table_name: "users".to_string(),
+                            column_name: "id".to_string(),
+                            on_delete: ReferentialAction::Cascade,
+                        })],
+                    ),
+                    ColumnDefinition::new("editor".to_string(), "varchar".to_string()),
+                ],
+                vec![TableConstraint::ForeignKey {
+                    name: None,
+                    column: "editor".to_string(),
+                    reference: ForeignKeyReference {
+                        table_name: "users".to_string(),
+                        column_name: "email".to_string(),
+                        on_delete: ReferentialAction::SetNull,
+                    },
+                }],
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_insert_into_with_columns() {
+        let result = Parser::parse_sql(
+            Some("test_db".to_string()),
+            "INSERT INTO users (id, name) VALUES (1, NULL)",
+        );
+        assert_eq!(
+            result,
+            Ok(Statement::Insert(Insert::with_columns(
+                "users".into(),
+                vec!["id".into(), "name".into()],
+                vec![Expression::number("1").into(), Expression::null().into()],
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_explain() {
+        let select = Statement::Select(Query::new(
+            vec![Projection::Wildcard],
+            vec!["users".into()],
+            Predicate::empty(),
+        ));
+        assert_eq!(
+            Parser::parse_sql(Some("test_db".to_string()), "EXPLAIN SELECT * FROM users"),
+            Ok(Statement::Explain {
+                statement: Box::new(select.clone()),
+                analyze: false,
+            })
+        );
+        assert_eq!(
+            Parser::parse_sql(
+                Some("test_db".to_string()),
+                "explain analyze SELECT * FROM users"
+            ),
+            Ok(Statement::Explain {
+                statement: Box::new(select),
+                analyze: true,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_script() {
+        let database_name = Some("test_db".to_string());
+        let statements = Parser::parse_script(
+            database_name.clone(),
+            "ANALYZE users;\n;  \nINSERT INTO users VALUES (1, 'a;b');\nANALYZE",
+        )
+        .unwrap();
+        let starts: Vec<(usize, usize)> = statements
+            .iter()
+            .map(|(location, _)| (location.line, location.column))
+            .collect();
+        assert_eq!(starts, vec![(1, 1), (3, 1), (4, 1)]);
+        assert_eq!(
+            statements[0].1,
+            Statement::Analyze {
+                table_name: Some("users".to_string())
+            }
+        );
+        assert!(matches!(&statements[1].1, Statement::Insert(_)));
+        assert_eq!(statements[2].1, Statement::Analyze { table_name: None });
+
+        // by the index of the failed statement
+        match Parser::parse_script(database_name.clone(), "ANALYZE;\n ANALYZE users users;") {
+            Err((1, location, ParserError::SyntaxError { .. })) => {
+                assert_eq!((location.line, location.column), (2, 2))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        match Parser::parse_script(database_name.clone(), "ANALYZE; ANALYZE !x") {
+            Err((1, location, ParserError::SyntaxError { .. })) => {
+                assert_eq!((location.line, location.column), (1, 10))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        match Parser::parse_script(database_name.clone(), "ANALYZE; !x") {
+            Err((1, location, ParserError::SyntaxError { .. })) => {
+                assert_eq!((location.line, location.column), (1, 10))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // a single statement may end with `;`, but the rest isn't ignored
+        assert_eq!(
+            Parser::parse_sql(database_name.clone(), "ANALYZE users;"),
+            Ok(Statement::Analyze {
+                table_name: Some("users".to_string())
+            })
+        );
+        assert!(matches!(
+            Parser::parse_sql(database_name, "ANALYZE users; ANALYZE"),
+            Err(ParserError::SyntaxError { .. })
+        ));
+    }
+
+    #[test]
+    fn split_script() {
+        let statement = |text: &str, line, column, terminated| ScriptStatement {
+            text: text.to_string(),
+            location: Location {
+                offset: 0,
+                line,
+                column,
+            },
+            terminated,
+        };
+        let split = |script: &str| -> Vec<ScriptStatement> {
+            Parser::split_script(script)
+                .into_iter()
+                .map(|mut statement| {
+                    statement.location.offset = 0;
+                    statement
+                })
+                .collect()
+        };
+        assert_eq!(
+            split(
+                "/* header; */\nCREATE TABLE users (id integer);\n\
+                 -- a comment; not a statement\n\
+                 INSERT INTO users\n  VALUES (1, 'a;b'); /* ; */\n\
+                 SELECT * -- all"
+            ),
+            vec![
+                statement("CREATE TABLE users (id integer)", 2, 1, true),
+                statement("INSERT INTO users\n  VALUES (1, 'a;b')", 4, 1, true),
+                statement("SELECT * -- all", 6, 1, false),
+            ]
+        );
+        assert_eq!(split("  ;\n-- done\n"), vec![]);
+
+        // more of the script may complete a string or a comment
+        assert_eq!(
+            split("SELECT 1; SELECT 'a;\n"),
+            vec![
+                statement("SELECT 1", 1, 1, true),
+                statement("SELECT 'a;", 1, 11, false),
+            ]
+        );
+        assert_eq!(
+            split("SELECT 1; /* a;"),
+            vec![
+                statement("SELECT 1", 1, 1, true),
+                statement("/* a;", 1, 11, false),
+            ]
+        );
+        // but not the rest, which is left to the parser
+        assert_eq!(
+            split("SELECT !x; SELECT 2"),
+            vec![
+                statement("SELECT !x", 1, 1, true),
+                statement("SELECT 2", 1, 12, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_lexical_syntax() {
+        let database_name = Some("test_db".to_string());
+        let expected = Statement::Insert(Insert::new(
+            "select".into(),
+            vec![
+                Expression::number("1").into(),
+                Expression::quoted_string("it's").into(),
+            ],
+        ));
+        assert_eq!(
+            Parser::parse_sql(
+                database_name.clone(),
+                "-- a comment\r\nINSERT INTO \"select\" /* a table */\r\nVALUES(1,'it''s');"
+            ),
+            Ok(expected)
+        );
+        let statements = Parser::parse_script(
+            database_name,
+            "ANALYZE users;-- ; \n/* ; */;ANALYZE \"the users\";",
+        )
+        .unwrap();
+        assert_eq!(
+            statements
+                .into_iter()
+                .map(|(_, statement)| statement)
+                .collect::<Vec<_>>(),
+            vec![
+                Statement::Analyze {
+                    table_name: Some("users".to_string())
+                },
+                Statement::Analyze {
+                    table_name: Some("the users".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_keywords_as_names() {
+        let parse = |sql: &str| Parser::parse_sql(Some("test_db".to_string()), sql);
+        assert_eq!(
+            parse("SELECT key FROM kv WHERE level = 1"),
+            Ok(Statement::Select(Query::new(
+                vec![Projection::Expression(Expression::ident("key"))],
+                vec!["kv".into()],
+                Predicate::new(
+                    BinaryOperator::Eq.build(Expression::ident("level"), Expression::number("1"))
+                ),
+            )))
+        );
+        assert_eq!(
+            parse("CREATE TABLE set (key varchar, default integer DEFAULT 0 CHECK (default >= 0))"),
+            Ok(Statement::CreateTable(CreateTable::new(
+                "test_db".to_string(),
+                "set".to_string(),
+                vec![
+                    ColumnDefinition::new("key".to_string(), "varchar".to_string()),
+                    ColumnDefinition::with_constraints(
+                        "default".to_string(),
+                        "integer".to_string(),
+                        vec![
+                            ColumnConstraint::Default(Value::Number("0".to_string())),
+                            ColumnConstraint::Check(
+                                BinaryOperator::Gte
+                                    .build(Expression::ident("default"), Expression::number("0"))
+                            ),
+                        ]
+                    ),
+                ],
+            )))
+        );
+        assert_eq!(
+            parse("create sequence start start with 1"),
+            Ok(Statement::CreateSequence(CreateSequence {
+                database_name: "test_db".to_string(),
+                name: "start".to_string(),
+                start: Some(1),
+                increment: None,
+            }))
+        );
+        for sql in &[
+            "INSERT INTO use (check, by, with, sequence) VALUES (1, 2, 3, 4)",
+            "USE level",
+            "ANALYZE key",
+            "CREATE TABLE t (id integer REFERENCES check(key) ON DELETE SET NULL)",
+        ] {
+            assert!(parse(sql).is_ok(), "{}: {:?}", sql, parse(sql));
+        }
+        // but not those which start table constraints or are values
+        for sql in &[
+            "CREATE TABLE t (unique integer)",
+            "SELECT null FROM t",
+            "CREATE TABLE from (id integer)",
+        ] {
+            assert!(parse(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn parse_analyze() {
+        assert_eq!(
+            Parser::parse_sql(Some("test_db".to_string()), "ANALYZE users"),
+            Ok(Statement::Analyze {
+                table_name: Some("users".to_string())
+            })
+        );
+        assert_eq!(
+            Parser::parse_sql(Some("test_db".to_string()), "ANALYZE"),
+            Ok(Statement::Analyze { table_name: None })
+        );
+        assert!(Parser::parse_sql(Some("test_db".to_string()), "ANALYZE users teams").is_err());
+    }
+
+    #[test]
+    fn parse_sequences() {
+        let result = Parser::parse_sql(
+            Some("test_db".to_string()),
+            "CREATE SEQUENCE order_no START WITH 100 INCREMENT BY 10",
+        );
+        assert_eq!(
+            result,
+            Ok(Statement::CreateSequence(CreateSequence {
+                database_name: "test_db".to_string(),
+                name: "order_no".to_string(),
+                start: Some(100),
+                increment: Some(10),
+            }))
+        );
+
+        let result = Parser::parse_sql(
+            Some("test_db".to_string()),
+            "CREATE TABLE orders (id serial, no integer AUTO_INCREMENT, paid varchar)",
+        );
+        assert_eq!(
+            result,
+            Ok(Statement::CreateTable(CreateTable::new(
+                "test_db".to_string(),
+                "orders".to_string(),
+                vec![
+                    ColumnDefinition::new("id".to_string(), "serial".to_string()),
+                    ColumnDefinition::with_constraints(
+                        "no".to_string(),
+                        "integer".to_string(),
+                        vec![ColumnConstraint::AutoIncrement],
+                    ),
+                    ColumnDefinition::new("paid".to_string(), "varchar".to_string()),
+                ],
+            )))
+        );
+
+        let result = Parser::parse_sql(
+            Some("test_db".to_string()),
+            "INSERT INTO orders VALUES (nextval('order_no'), NULL, true)",
+        );
+        assert_eq!(
+            result,
+            Ok(Statement::Insert(Insert::new(
+                "orders".into(),
+                vec![
+                    Expression::Function {
+                        name: "nextval".to_string(),
+                        args: vec![Expression::quoted_string("order_no")],
+                    }
+                    .into(),
+                    Expression::null().into(),
+                    Expression::Value(Value::Boolean(true)).into(),
+                ],
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_transaction_statements() {
+        let parse = |sql: &str| Parser::parse_sql(Some("test_db".to_string()), sql);
+        assert_eq!(
+            parse("BEGIN"),
+            Ok(Statement::Transaction(TransactionStatement::Begin))
+        );
+        assert_eq!(
+            parse("START TRANSACTION"),
+            Ok(Statement::Transaction(TransactionStatement::Begin))
+        );
+        assert_eq!(
+            parse("COMMIT TRANSACTION"),
+            Ok(Statement::Transaction(TransactionStatement::Commit))
+        );
+        assert_eq!(
+            parse("rollback"),
+            Ok(Statement::Transaction(TransactionStatement::Rollback))
+        );
+        assert!(parse("COMMIT users").is_err());
+        assert_eq!(
+            parse("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ"),
+            Ok(Statement::Transaction(
+                TransactionStatement::SetIsolationLevel(IsolationLevel::Snapshot)
+            ))
+        );
+        assert_eq!(
+            parse("SET TRANSACTION ISOLATION LEVEL read committed"),
+            Ok(Statement::Transaction(
+                TransactionStatement::SetIsolationLevel(IsolationLevel::ReadCommitted)
+            ))
+        );
+        assert!(parse("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE").is_err());
+    }
+
+    #[test]
+    fn parse_session_statements() {
+        assert_eq!(
+            Parser::parse_sql(None, "USE test_db"),
+            Ok(Statement::Session(SessionStatement::Use(
+                "test_db".to_string()
+            )))
+        );
+        assert!(Parser::parse_sql(None, "USE").is_err());
+        assert_eq!(
+            Parser::parse_sql(None, "SET Application_Name = 'batch'"),
+            Ok(Statement::Session(SessionStatement::SetVariable {
+                name: "application_name".to_string(),
+                value: "batch".to_string(),
+            }))
+        );
+        assert_eq!(
+            Parser::parse_sql(None, "SET statement_timeout TO 100"),
+            Ok(Statement::Session(SessionStatement::SetVariable {
+                name: "statement_timeout".to_string(),
+                value: "100".to_string(),
+            }))
+        );
+        assert!(Parser::parse_sql(None, "SET statement_timeout 100").is_err());
+        assert!(Parser::parse_sql(None, "CREATE TABLE users (id integer)").is_err());
+    }
+
     fn parser_assertion(tokens: Vec<Token>, expected: Statement) {
         let mut parser = Parser::new(tokens, Some("test_db".to_string()));
         let result = parser.parse();
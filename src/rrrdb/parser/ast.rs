@@ -1,4 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+use super::tokenizer::{Keyword, Location};
 use crate::rrrdb::schema::Column;
+pub(crate) use crate::rrrdb::storage::IsolationLevel;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum Statement {
@@ -6,6 +10,30 @@ pub(crate) enum Statement {
     Insert(Insert),
     CreateDatabase(CreateDatabase),
     CreateTable(CreateTable),
+    CreateSequence(CreateSequence),
+    Transaction(TransactionStatement),
+    Session(SessionStatement),
+    // ANALYZE runs the statement as well
+    Explain {
+        statement: Box<Statement>,
+        analyze: bool,
+    },
+    // every table of the database without a table name
+    Analyze {
+        table_name: Option<String>,
+    },
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum SessionStatement {
+    Use(String),
+    SetVariable { name: String, value: String },
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum TransactionStatement {
+    Begin,
+    Commit,
+    Rollback,
+    SetIsolationLevel(IsolationLevel),
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct Query {
@@ -27,7 +55,7 @@ pub(crate) enum Projection {
     Expression(Expression),
     Wildcard,
 }
-type Table = String;
+type Table = Spanned<String>;
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct Predicate {
     pub expression: Option<Expression>,
@@ -44,12 +72,97 @@ impl Predicate {
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct Insert {
-    pub(crate) table_name: String,
-    pub(crate) values: Vec<Value>,
+    pub(crate) table_name: Spanned<String>,
+    // None means all columns of the table in their defined order
+    pub(crate) columns: Option<Vec<Spanned<String>>>,
+    // literals or function calls such as nextval('seq')
+    pub(crate) values: Vec<Spanned<Expression>>,
 }
 impl Insert {
-    pub fn new(table_name: String, values: Vec<Value>) -> Self {
-        Self { table_name, values }
+    pub fn new(table_name: Spanned<String>, values: Vec<Spanned<Expression>>) -> Self {
+        Self {
+            table_name,
+            columns: None,
+            values,
+        }
+    }
+    pub fn with_columns(
+        table_name: Spanned<String>,
+        columns: Vec<Spanned<String>>,
+        values: Vec<Spanned<Expression>>,
+    ) -> Self {
+        Self {
+            table_name,
+            columns: Some(columns),
+            values,
+        }
+    }
+}
+
+// a node with where it's written in the statement, so that errors found while planning can
+// point at it. nodes built by hand have no location. the location takes no part in comparing
+// nodes, and isn't serialized, so CHECK expressions stored in schemas stay as they were
+#[derive(Debug, Clone)]
+pub(crate) struct Spanned<T> {
+    pub(crate) node: T,
+    pub(crate) location: Option<Location>,
+}
+impl<T> Spanned<T> {
+    pub fn new(node: T, location: Location) -> Self {
+        Self {
+            node,
+            location: Some(location),
+        }
+    }
+}
+impl<T> From<T> for Spanned<T> {
+    fn from(node: T) -> Self {
+        Self {
+            node,
+            location: None,
+        }
+    }
+}
+impl From<&str> for Spanned<String> {
+    fn from(node: &str) -> Self {
+        Self::from(node.to_string())
+    }
+}
+impl<T> std::ops::Deref for Spanned<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+impl<T> std::ops::DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.node
+    }
+}
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+impl<T: Eq> Eq for Spanned<T> {}
+impl<T: std::hash::Hash> std::hash::Hash for Spanned<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.node.hash(state)
+    }
+}
+impl<T: std::fmt::Display> std::fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.node.fmt(f)
+    }
+}
+impl<T: Serialize> Serialize for Spanned<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.node.serialize(serializer)
+    }
+}
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Spanned<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self::from)
     }
 }
 
@@ -63,49 +176,126 @@ impl CreateDatabase {
     }
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CreateSequence {
+    pub(crate) database_name: String,
+    pub(crate) name: String,
+    pub(crate) start: Option<i64>,
+    pub(crate) increment: Option<i64>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct CreateTable {
     pub(crate) database_name: String,
     pub(crate) table_name: String,
     pub(crate) column_definitions: Vec<ColumnDefinition>,
+    pub(crate) constraints: Vec<TableConstraint>,
 }
 impl CreateTable {
     pub fn new(
         database_name: String,
         table_name: String,
         column_definitions: Vec<ColumnDefinition>,
+    ) -> Self {
+        Self::with_constraints(database_name, table_name, column_definitions, vec![])
+    }
+    pub fn with_constraints(
+        database_name: String,
+        table_name: String,
+        column_definitions: Vec<ColumnDefinition>,
+        constraints: Vec<TableConstraint>,
     ) -> Self {
         Self {
             database_name,
             table_name,
             column_definitions,
+            constraints,
         }
     }
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum TableConstraint {
+    Unique {
+        name: Option<String>,
+        columns: Vec<String>,
+    },
+    ForeignKey {
+        name: Option<String>,
+        column: String,
+        reference: ForeignKeyReference,
+    },
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ForeignKeyReference {
+    pub(crate) table_name: String,
+    pub(crate) column_name: String,
+    pub(crate) on_delete: ReferentialAction,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ReferentialAction {
+    Restrict,
+    Cascade,
+    SetNull,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct ColumnDefinition {
     pub(crate) name: String,
     pub(crate) column_type: String,
+    pub(crate) constraints: Vec<ColumnConstraint>,
 }
 impl ColumnDefinition {
     pub fn new(name: String, column_type: String) -> Self {
-        Self { name, column_type }
+        Self::with_constraints(name, column_type, vec![])
+    }
+    pub fn with_constraints(
+        name: String,
+        column_type: String,
+        constraints: Vec<ColumnConstraint>,
+    ) -> Self {
+        Self {
+            name,
+            column_type,
+            constraints,
+        }
+    }
+    // SERIAL is a shorthand for integer AUTO_INCREMENT
+    pub fn is_auto_increment(&self) -> bool {
+        self.column_type.to_lowercase() == "serial"
+            || self.constraints.contains(&ColumnConstraint::AutoIncrement)
     }
 }
-
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ColumnConstraint {
+    NotNull,
+    Default(Value),
+    Check(Expression),
+    Unique,
+    References(ForeignKeyReference),
+    AutoIncrement,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum Expression {
-    Ident(String),
+    Ident(Spanned<String>),
     Value(Value),
     BinOperator {
         lhs: Box<Expression>,
         rhs: Box<Expression>,
         op: BinaryOperator,
     },
+    // `target -> path` returns a JSON value, `target ->> path` returns it as text
+    JsonExtract {
+        target: Box<Expression>,
+        path: JsonPath,
+        as_text: bool,
+    },
+    Function {
+        name: String,
+        args: Vec<Expression>,
+    },
 }
 
 impl Expression {
     pub fn ident(i: &str) -> Expression {
-        Self::Ident(i.to_string())
+        Self::Ident(i.into())
     }
     pub fn number(n: &str) -> Expression {
         Self::Value(Value::Number(n.to_string()))
@@ -121,12 +311,14 @@ impl Expression {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum Value {
     Number(String),
     QuotedString(String),
     Boolean(bool),
     Null,
+    // `$1` or `:name`, replaced by a bound parameter before execution
+    Placeholder(String),
 }
 impl Value {
     pub fn to_string_opt(&self) -> Option<String> {
@@ -135,13 +327,36 @@ impl Value {
             Value::QuotedString(s) => Some(s.to_owned()),
             Value::Boolean(b) => Some(b.to_string()),
             Value::Null => None,
+            Value::Placeholder(p) => Some(p.to_owned()),
         }
     }
     pub fn to_string(&self) -> String {
         self.to_string_opt().unwrap_or(String::from("null"))
     }
+    // as written in a statement, with strings quoted
+    pub fn to_sql(&self) -> String {
+        match self {
+            Value::QuotedString(s) => format!("'{}'", s.replace('\'', "''")),
+            Value::Null => String::from("NULL"),
+            other => other.to_string(),
+        }
+    }
 }
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+// a name as written in a statement, double-quoted unless it's a plain word other than keywords
+pub(crate) fn identifier(name: &str) -> String {
+    let plain = name.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_')
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+        && Keyword::find(name).is_none();
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum BinaryOperator {
     Eq,
     Neq,
@@ -153,6 +368,51 @@ pub(crate) enum BinaryOperator {
     Or,
 }
 
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // operands which are operations themselves are parenthesized
+        let operand = |expr: &Expression| match expr {
+            Expression::BinOperator { .. } => format!("({})", expr),
+            expr => expr.to_string(),
+        };
+        match self {
+            Expression::Ident(ident) => write!(f, "{}", identifier(ident)),
+            Expression::Value(value) => write!(f, "{}", value.to_sql()),
+            Expression::BinOperator { lhs, rhs, op } => {
+                write!(f, "{} {} {}", operand(lhs), op, operand(rhs))
+            }
+            Expression::JsonExtract {
+                target,
+                path,
+                as_text,
+            } => {
+                let operator = if *as_text { "->>" } else { "->" };
+                write!(f, "{}{}{}", target, operator, path.to_sql())
+            }
+            Expression::Function { name, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            BinaryOperator::Eq => "=",
+            BinaryOperator::Neq => "!=",
+            BinaryOperator::Lt => "<",
+            BinaryOperator::Lte => "<=",
+            BinaryOperator::Gt => ">",
+            BinaryOperator::Gte => ">=",
+            BinaryOperator::And => "AND",
+            BinaryOperator::Or => "OR",
+        };
+        write!(f, "{}", op)
+    }
+}
+
 impl BinaryOperator {
     pub fn build(self, left: Expression, right: Expression) -> Expression {
         Expression::BinOperator {
@@ -162,3 +422,129 @@ impl BinaryOperator {
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum JsonPathElement {
+    Key(String),
+    Index(usize),
+}
+
+// path into a JSON document, written either as a single key (`'name'`), an array index (`0`)
+// or a `$`-rooted path like `'$.address.lines[0]'`, where keys other than plain words are
+// double-quoted like `'$."first name"'`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct JsonPath {
+    pub(crate) elements: Vec<JsonPathElement>,
+}
+
+impl JsonPath {
+    pub fn new(elements: Vec<JsonPathElement>) -> Self {
+        Self { elements }
+    }
+
+    pub fn parse(path: &str) -> Result<JsonPath, String> {
+        if !path.starts_with('$') {
+            return Ok(Self::new(vec![JsonPathElement::Key(path.to_string())]));
+        }
+        let mut elements = vec![];
+        let mut chars = path[1..].chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '.' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    let mut key = String::new();
+                    loop {
+                        match chars.next() {
+                            // `""` is an escaped quote
+                            Some('"') if chars.peek() == Some(&'"') => {
+                                key.push('"');
+                                chars.next();
+                            }
+                            Some('"') => break,
+                            Some(ch) => key.push(ch),
+                            None => return Err(format!("unclosed '\"' in JSON path '{}'", path)),
+                        }
+                    }
+                    elements.push(JsonPathElement::Key(key));
+                }
+                '.' => {
+                    let mut key = String::new();
+                    while let Some(&ch) = chars.peek() {
+                        if ch == '.' || ch == '[' {
+                            break;
+                        }
+                        key.push(ch);
+                        chars.next();
+                    }
+                    if key.is_empty() {
+                        return Err(format!("empty key in JSON path '{}'", path));
+                    }
+                    elements.push(JsonPathElement::Key(key));
+                }
+                '[' => {
+                    let mut index = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(ch) => index.push(ch),
+                            None => return Err(format!("unclosed '[' in JSON path '{}'", path)),
+                        }
+                    }
+                    let index = index.parse::<usize>().map_err(|_| {
+                        format!("invalid index '{}' in JSON path '{}'", index, path)
+                    })?;
+                    elements.push(JsonPathElement::Index(index));
+                }
+                unexpected => {
+                    return Err(format!(
+                        "unexpected '{}' in JSON path '{}'",
+                        unexpected, path
+                    ))
+                }
+            }
+        }
+        Ok(Self::new(elements))
+    }
+
+    pub fn append(mut self, other: JsonPath) -> JsonPath {
+        self.elements.extend(other.elements);
+        self
+    }
+
+    // the path as a string literal in a statement
+    pub fn to_sql(&self) -> String {
+        format!("'{}'", self.to_string().replace('\'', "''"))
+    }
+
+    pub fn extract<'a>(&self, document: &'a serde_json::Value) -> Option<&'a serde_json::Value> {
+        self.elements
+            .iter()
+            .try_fold(document, |current, element| match element {
+                JsonPathElement::Key(key) => current.get(key),
+                JsonPathElement::Index(index) => current.get(index),
+            })
+    }
+}
+
+impl std::fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "$")?;
+        for element in &self.elements {
+            match element {
+                JsonPathElement::Key(key) => {
+                    let plain = key.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_')
+                        && key
+                            .chars()
+                            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+                    if plain {
+                        write!(f, ".{}", key)?
+                    } else {
+                        write!(f, ".\"{}\"", key.replace('"', "\"\""))?
+                    }
+                }
+                JsonPathElement::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,55 @@
 use std::iter::Peekable;
 use std::str::Chars;
 
+// where a token starts in the query. line and column count from 1, and column counts characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Location {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Location {
+    pub(crate) fn advance(&mut self, ch: char) {
+        self.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+// characters of the query, keeping track of the location of the next one
+#[derive(Clone)]
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    location: Location,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        self.location.advance(ch);
+        Some(ch)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct Tokenizer {
     query: String,
@@ -14,21 +63,47 @@ impl Tokenizer {
     }
 
     pub fn tokenize(&mut self) -> Result<Vec<Token>, TokenizeError> {
-        let mut peekable: Peekable<Chars> = self.query.chars().peekable();
-        let mut tokens: Vec<Token> = vec![];
+        let (tokens, _) = self.tokenize_with_locations()?;
+        Ok(tokens.into_iter().map(|(token, _)| token).collect())
+    }
+
+    // tokens with their locations, and the location of the end of the query
+    pub fn tokenize_with_locations(
+        &mut self,
+    ) -> Result<(Vec<(Token, Location)>, Location), TokenizeError> {
+        self.tokenize_from(Location::default())
+    }
 
-        while let Some(token) = self.get_next_token(&mut peekable)? {
-            tokens.push(token);
+    // the tokens from the location in the query on
+    pub fn tokenize_from(
+        &mut self,
+        start: Location,
+    ) -> Result<(Vec<(Token, Location)>, Location), TokenizeError> {
+        let mut peekable = Cursor {
+            chars: self.query[start.offset..].chars().peekable(),
+            location: start,
+        };
+        let mut tokens = vec![];
+
+        loop {
+            let location = peekable.location;
+            match self.get_next_token(&mut peekable) {
+                Ok(Some(token)) => tokens.push((token, location)),
+                Ok(None) => return Ok((tokens, location)),
+                Err(message) => {
+                    return Err(TokenizeError {
+                        message,
+                        location,
+                        incomplete: peekable.peek().is_none(),
+                    })
+                }
+            }
         }
-        Ok(tokens)
     }
 
-    fn get_next_token(
-        &self,
-        peekable: &mut Peekable<Chars>,
-    ) -> Result<Option<Token>, TokenizeError> {
+    fn get_next_token(&self, peekable: &mut Cursor) -> Result<Option<Token>, String> {
         let return_ok = |token| Ok(Some(token));
-        let return_err = |message| Err(TokenizeError { message });
+        let return_err = |message| Err(message);
         match peekable.peek() {
             None => Ok(None),
             Some(&ch) => match peekable.next().unwrap() {
@@ -56,18 +131,86 @@ impl Tokenizer {
                     _ => return_ok(Token::Gt),
                 },
                 '+' => return_ok(Token::Plus),
-                '-' => return_ok(Token::Minus),
+                '-' => match peekable.peek() {
+                    Some('>') => {
+                        peekable.next();
+                        match peekable.peek() {
+                            Some('>') => {
+                                peekable.next();
+                                return_ok(Token::LongArrow)
+                            }
+                            _ => return_ok(Token::Arrow),
+                        }
+                    }
+                    // until the end of the line, leaving the newline
+                    Some('-') => {
+                        peekable.next();
+                        let mut s = String::new();
+                        while let Some(&ch) = peekable.peek() {
+                            if ch == '\n' || ch == '\r' {
+                                break;
+                            }
+                            peekable.next();
+                            s.push(ch);
+                        }
+                        return_ok(Token::Whitespace(Whitespace::LineComment(s)))
+                    }
+                    _ => return_ok(Token::Minus),
+                },
                 '*' => return_ok(Token::Mul),
-                '/' => return_ok(Token::Div),
+                '/' => match peekable.peek() {
+                    Some('*') => {
+                        peekable.next();
+                        let mut s = String::new();
+                        loop {
+                            match peekable.next() {
+                                Some('*') if peekable.peek() == Some(&'/') => {
+                                    peekable.next();
+                                    break;
+                                }
+                                Some(ch) => s.push(ch),
+                                None => return return_err(format!("Unterminated comment")),
+                            }
+                        }
+                        return_ok(Token::Whitespace(Whitespace::BlockComment(s)))
+                    }
+                    _ => return_ok(Token::Div),
+                },
                 '%' => return_ok(Token::Mod),
                 '(' => return_ok(Token::LParen),
                 ')' => return_ok(Token::RParen),
                 '.' => return_ok(Token::Period),
                 ';' => return_ok(Token::SemiColon),
                 ',' => return_ok(Token::Comma),
+                '?' => return_ok(Token::Placeholder(String::from("?"))),
+                // `$1` and `:name`
+                '$' | ':' => {
+                    let mut s = String::new();
+                    s.push(ch);
+                    while let Some(&ch) = peekable.peek() {
+                        match ch {
+                            '0'..='9' => (),
+                            'a'..='z' | 'A'..='Z' | '_' if s.starts_with(':') => (),
+                            _ => break,
+                        }
+                        peekable.next();
+                        s.push(ch);
+                    }
+                    if s.len() == 1 {
+                        return return_err(format!("Unknown token: {}", s));
+                    }
+                    return_ok(Token::Placeholder(s))
+                }
                 ' ' => return_ok(Token::Whitespace(Whitespace::Space)),
                 '\t' => return_ok(Token::Whitespace(Whitespace::Tab)),
                 '\n' => return_ok(Token::Whitespace(Whitespace::Newline)),
+                // `\r\n` is a newline
+                '\r' => {
+                    if peekable.peek() == Some(&'\n') {
+                        peekable.next();
+                    }
+                    return_ok(Token::Whitespace(Whitespace::Newline))
+                }
                 '0'..='9' => {
                     let mut s = String::new();
                     s.push(ch);
@@ -85,48 +228,87 @@ impl Tokenizer {
                     return_ok(Token::Number(s))
                 }
                 '\'' => {
-                    let mut s = String::new();
-                    while let Some(&ch) = peekable.peek() {
-                        match ch {
-                            '\'' => {
-                                peekable.next(); // consume '
-                                break;
-                            }
-                            _ => {
-                                peekable.next();
-                                s.push(ch);
-                            }
-                        }
-                    }
+                    let s = Self::quoted(peekable, '\'')
+                        .ok_or(format!("Unterminated string literal"))?;
                     return_ok(Token::SingleQuotedString(s))
                 }
+                // an identifier which may be a keyword or hold any characters
+                '"' => match Self::quoted(peekable, '"') {
+                    Some(s) if s.is_empty() => return_err(format!("Empty quoted identifier")),
+                    Some(s) => return_ok(Token::Word(s)),
+                    None => return_err(format!("Unterminated quoted identifier")),
+                },
                 _ => {
                     let mut s = String::new();
                     s.push(ch);
                     while let Some(&ch) = peekable.peek() {
-                        match ch {
-                            ',' | ' ' | '\n' | '\t' | '(' | ')' => {
-                                break;
-                            }
-                            _ => {
-                                peekable.next();
-                                s.push(ch);
-                            }
+                        if Self::ends_word(ch) {
+                            break;
                         }
+                        peekable.next();
+                        s.push(ch);
                     }
                     match Keyword::find(s.as_ref()) {
-                        Some(keyword) => return_ok(Token::Keyword(keyword)),
-                        None => return_ok(Token::Word(s)),
+                        Some(keyword) if keyword.is_reserved() => {
+                            return_ok(Token::Keyword(keyword))
+                        }
+                        _ => return_ok(Token::Word(s)),
                     }
                 }
             },
         }
     }
+
+    // the rest of a quoted string, in which a doubled quote stands for a quote.
+    // None if the closing quote is missing
+    fn quoted(peekable: &mut Cursor, quote: char) -> Option<String> {
+        let mut s = String::new();
+        loop {
+            match peekable.next()? {
+                ch if ch == quote => {
+                    if peekable.peek() != Some(&quote) {
+                        return Some(s);
+                    }
+                    peekable.next();
+                    s.push(quote);
+                }
+                ch => s.push(ch),
+            }
+        }
+    }
+
+    // whitespaces, and characters starting other tokens like `id=2` or `users;`
+    fn ends_word(ch: char) -> bool {
+        ch.is_whitespace()
+            || matches!(
+                ch,
+                ',' | '('
+                    | ')'
+                    | ';'
+                    | '='
+                    | '!'
+                    | '<'
+                    | '>'
+                    | '+'
+                    | '-'
+                    | '*'
+                    | '/'
+                    | '%'
+                    | '.'
+                    | '\''
+                    | '"'
+                    | '?'
+            )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct TokenizeError {
     pub message: String,
+    pub location: Location,
+    // the query ends where the token fails, e.g. in an unterminated string, so that more of it
+    // may complete the token
+    pub incomplete: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -136,6 +318,8 @@ pub(crate) enum Token {
     Word(String),
     Number(String),
     SingleQuotedString(String),
+    // `?`, `$1` or `:name`
+    Placeholder(String),
     Comma,
     Whitespace(Whitespace),
     Eq,        // =
@@ -153,6 +337,19 @@ pub(crate) enum Token {
     RParen,    // )
     Period,    // .
     SemiColon, // ;
+    Arrow,     // ->
+    LongArrow, // ->>
+}
+
+impl Token {
+    // a reserved keyword, or a word spelled like the keyword in any case
+    pub(crate) fn is_keyword(&self, keyword: Keyword) -> bool {
+        match self {
+            Token::Keyword(k) => *k == keyword,
+            Token::Word(word) => Keyword::find(word) == Some(keyword),
+            _ => false,
+        }
+    }
 }
 
 impl std::fmt::Display for Token {
@@ -163,6 +360,7 @@ impl std::fmt::Display for Token {
             Token::Word(word) => write!(f, "{}", word),
             Token::Number(number) => write!(f, "{}", number),
             Token::SingleQuotedString(s) => write!(f, "{}", s),
+            Token::Placeholder(s) => write!(f, "{}", s),
             Token::Comma => write!(f, ","),
             Token::Whitespace(whitespace) => write!(f, "{}", whitespace),
             Token::Eq => write!(f, "="),
@@ -180,6 +378,8 @@ impl std::fmt::Display for Token {
             Token::RParen => write!(f, ")"),
             Token::Period => write!(f, "."),
             Token::SemiColon => write!(f, ";"),
+            Token::Arrow => write!(f, "->"),
+            Token::LongArrow => write!(f, "->>"),
         }
     }
 }
@@ -213,13 +413,79 @@ macro_rules! define_keywords {
   };
 }
 
-define_keywords!(Create, Database, Table, Select, From, Where, Insert, Into, Values);
+define_keywords!(
+    Create,
+    Database,
+    Table,
+    Select,
+    From,
+    Where,
+    Insert,
+    Into,
+    Values,
+    Not,
+    Null,
+    Default,
+    Check,
+    Unique,
+    Constraint,
+    Foreign,
+    Key,
+    References,
+    On,
+    Delete,
+    Restrict,
+    Cascade,
+    Set,
+    Sequence,
+    Start,
+    With,
+    Increment,
+    By,
+    Begin,
+    Commit,
+    Rollback,
+    Transaction,
+    Isolation,
+    Level,
+    Use,
+    Explain,
+    Analyze
+);
+
+impl Keyword {
+    // only these are tokenized as keywords, as they are values or start statements and table
+    // constraints where a name may be written too. the others are words matched where the
+    // grammar expects them, so that they can still be used as names like `key` or `level`
+    pub(crate) fn is_reserved(&self) -> bool {
+        matches!(
+            self,
+            Keyword::Create
+                | Keyword::Database
+                | Keyword::Table
+                | Keyword::Select
+                | Keyword::From
+                | Keyword::Where
+                | Keyword::Insert
+                | Keyword::Into
+                | Keyword::Values
+                | Keyword::Null
+                | Keyword::Unique
+                | Keyword::Constraint
+                | Keyword::Foreign
+        )
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum Whitespace {
     Space,
     Newline,
     Tab,
+    // `-- comment`, without the newline
+    LineComment(String),
+    // `/* comment */`
+    BlockComment(String),
 }
 impl std::fmt::Display for Whitespace {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -227,6 +493,8 @@ impl std::fmt::Display for Whitespace {
             Whitespace::Space => write!(f, " "),
             Whitespace::Newline => write!(f, "\\n"),
             Whitespace::Tab => write!(f, "\\t"),
+            Whitespace::LineComment(comment) => write!(f, "--{}", comment),
+            Whitespace::BlockComment(comment) => write!(f, "/*{}*/", comment),
         }
     }
 }
@@ -349,6 +617,155 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tokenize_json_extract() {
+        tokenizer_assertion(
+            "SELECT attrs->'a' FROM users WHERE attrs ->> '$.b' = 'x'",
+            vec![
+                Token::Keyword(Keyword::Select),
+                Token::Whitespace(Whitespace::Space),
+                Token::Word("attrs".to_string()),
+                Token::Arrow,
+                Token::SingleQuotedString("a".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::Keyword(Keyword::From),
+                Token::Whitespace(Whitespace::Space),
+                Token::Word("users".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::Keyword(Keyword::Where),
+                Token::Whitespace(Whitespace::Space),
+                Token::Word("attrs".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::LongArrow,
+                Token::Whitespace(Whitespace::Space),
+                Token::SingleQuotedString("$.b".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::Eq,
+                Token::Whitespace(Whitespace::Space),
+                Token::SingleQuotedString("x".to_string()),
+            ],
+        );
+    }
+
+    #[test]
+    fn tokenize_placeholders() {
+        tokenizer_assertion(
+            "VALUES (?, $12, :user_name)",
+            vec![
+                Token::Keyword(Keyword::Values),
+                Token::Whitespace(Whitespace::Space),
+                Token::LParen,
+                Token::Placeholder("?".to_string()),
+                Token::Comma,
+                Token::Whitespace(Whitespace::Space),
+                Token::Placeholder("$12".to_string()),
+                Token::Comma,
+                Token::Whitespace(Whitespace::Space),
+                Token::Placeholder(":user_name".to_string()),
+                Token::RParen,
+            ],
+        );
+        assert!(Tokenizer::new("VALUES ($)").tokenize().is_err());
+    }
+
+    #[test]
+    fn tokenize_locations() {
+        let (tokens, end) = Tokenizer::new("SELECT *\nFROM ユーザー WHERE")
+            .tokenize_with_locations()
+            .unwrap();
+        let location = |offset, line, column| Location {
+            offset,
+            line,
+            column,
+        };
+        assert_eq!(
+            tokens[0],
+            (Token::Keyword(Keyword::Select), location(0, 1, 1))
+        );
+        assert_eq!(tokens[2], (Token::Mul, location(7, 1, 8)));
+        assert_eq!(
+            tokens[4],
+            (Token::Keyword(Keyword::From), location(9, 2, 1))
+        );
+        assert_eq!(
+            tokens[6],
+            (Token::Word("ユーザー".to_string()), location(14, 2, 6))
+        );
+        assert_eq!(tokens[8].1, location(27, 2, 11));
+        assert_eq!(end, location(32, 2, 16));
+
+        let err = Tokenizer::new("SELECT\n  !a").tokenize().unwrap_err();
+        assert_eq!(err.location, location(9, 2, 3));
+    }
+
+    #[test]
+    fn tokenize_word_boundaries() {
+        tokenizer_assertion(
+            "id=2;name!=x.y<='a'",
+            vec![
+                Token::Word("id".to_string()),
+                Token::Eq,
+                Token::Number("2".to_string()),
+                Token::SemiColon,
+                Token::Word("name".to_string()),
+                Token::Neq,
+                Token::Word("x".to_string()),
+                Token::Period,
+                Token::Word("y".to_string()),
+                Token::Lte,
+                Token::SingleQuotedString("a".to_string()),
+            ],
+        );
+    }
+
+    #[test]
+    fn tokenize_comments() {
+        tokenizer_assertion(
+            "SELECT 1 -- one; two\r\n/* a\n* b */*-2/3",
+            vec![
+                Token::Keyword(Keyword::Select),
+                Token::Whitespace(Whitespace::Space),
+                Token::Number("1".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::Whitespace(Whitespace::LineComment(" one; two".to_string())),
+                Token::Whitespace(Whitespace::Newline),
+                Token::Whitespace(Whitespace::BlockComment(" a\n* b ".to_string())),
+                Token::Mul,
+                Token::Minus,
+                Token::Number("2".to_string()),
+                Token::Div,
+                Token::Number("3".to_string()),
+            ],
+        );
+        let err = Tokenizer::new("SELECT /* 1").tokenize().unwrap_err();
+        assert_eq!(err.message, "Unterminated comment");
+    }
+
+    #[test]
+    fn tokenize_quotes() {
+        tokenizer_assertion(
+            "'it''s' \"select\"\"s\" \"my table\"",
+            vec![
+                Token::SingleQuotedString("it's".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::Word("select\"s".to_string()),
+                Token::Whitespace(Whitespace::Space),
+                Token::Word("my table".to_string()),
+            ],
+        );
+        tokenizer_assertion("''", vec![Token::SingleQuotedString(String::new())]);
+        for (sql, message) in vec![
+            ("'it''s", "Unterminated string literal"),
+            ("\"users", "Unterminated quoted identifier"),
+            ("\"\"", "Empty quoted identifier"),
+        ] {
+            let err = Tokenizer::new(sql).tokenize().unwrap_err();
+            assert_eq!(err.message, message, "sql: {}", sql);
+        }
+        assert!(Tokenizer::new("'it''s").tokenize().unwrap_err().incomplete);
+        assert!(!Tokenizer::new("a != !b").tokenize().unwrap_err().incomplete);
+    }
+
     fn tokenizer_assertion(sql: &str, expected: Vec<Token>) {
         let mut tokenizer = Tokenizer::new(sql);
         let result: Result<Vec<Token>, TokenizeError> = tokenizer.tokenize();
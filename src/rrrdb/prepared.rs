@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use crate::rrrdb::{
+    parser::{Expression, Statement, Value},
+    sql::planner::Plan,
+    DBError, FieldValue,
+};
+
+// a statement parsed once, and planned once if it's a SELECT, executed with bound parameters.
+// parameters are bound as values, never parsed as SQL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparedStatement {
+    pub(crate) database_name: Option<String>,
+    // for the source lines of errors
+    pub(crate) query: String,
+    statement: Statement,
+    // planning an INSERT evaluates defaults and draws values from sequences, so it's done
+    // on every execution
+    plan: Option<Plan>,
+    // `$1` and `:name` in the statement. `?`s are numbered like `$1` by the parser
+    placeholders: Vec<String>,
+}
+
+// bound by position to `?` and `$1`, or by name to `:name`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Params {
+    Positional(Vec<FieldValue>),
+    Named(HashMap<String, FieldValue>),
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params::Positional(vec![])
+    }
+}
+
+impl From<Vec<FieldValue>> for Params {
+    fn from(values: Vec<FieldValue>) -> Self {
+        Params::Positional(values)
+    }
+}
+
+impl From<&[FieldValue]> for Params {
+    fn from(values: &[FieldValue]) -> Self {
+        Params::Positional(values.to_vec())
+    }
+}
+
+impl From<Vec<(&str, FieldValue)>> for Params {
+    fn from(values: Vec<(&str, FieldValue)>) -> Self {
+        Params::Named(
+            values
+                .into_iter()
+                .map(|(name, value)| (name.trim_start_matches(':').to_string(), value))
+                .collect(),
+        )
+    }
+}
+
+impl PreparedStatement {
+    pub(crate) fn new(
+        database_name: Option<String>,
+        query: &str,
+        statement: Statement,
+        plan: Option<Plan>,
+    ) -> Self {
+        let placeholders = Self::placeholders(&statement);
+        Self {
+            database_name,
+            query: query.to_string(),
+            statement,
+            plan,
+            placeholders,
+        }
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.placeholders.len()
+    }
+
+    pub(crate) fn placeholders(statement: &Statement) -> Vec<String> {
+        let mut placeholders = vec![];
+        let mut statement = statement.clone();
+        // never fails
+        let _ = visit_values(&mut statement, &mut |value| {
+            if let Value::Placeholder(p) = value {
+                if !placeholders.contains(p) {
+                    placeholders.push(p.to_owned());
+                }
+            }
+            Ok(())
+        });
+        placeholders
+    }
+
+    // the cached plan with the parameters, or None if the statement has to be planned
+    pub(crate) fn bind_plan(&self, params: &Params) -> Result<Option<Plan>, DBError> {
+        self.check(params)?;
+        let mut plan = match &self.plan {
+            Some(plan) => plan.clone(),
+            None => return Ok(None),
+        };
+        if let Plan::SelectPlan(select_plan) = &mut plan {
+            for value in select_plan.root.values_mut() {
+                bind(value, params)?;
+            }
+        }
+        Ok(Some(plan))
+    }
+
+    pub(crate) fn bind_statement(&self, params: &Params) -> Result<Statement, DBError> {
+        self.check(params)?;
+        let mut statement = self.statement.clone();
+        visit_values(&mut statement, &mut |value| bind(value, params))?;
+        Ok(statement)
+    }
+
+    // every placeholder has a parameter, and every parameter has a placeholder
+    fn check(&self, params: &Params) -> Result<(), DBError> {
+        let expected = match params {
+            Params::Positional(values) => (1..=values.len()).map(|i| format!("${}", i)).collect(),
+            Params::Named(values) => values.keys().map(|name| format!(":{}", name)).collect(),
+        };
+        let mut placeholders = self.placeholders.clone();
+        placeholders.sort();
+        let mut expected: Vec<String> = expected;
+        expected.sort();
+        if placeholders != expected {
+            return Err(DBError::InvalidParameter(format!(
+                "parameters don't match placeholders {:?}. params: {:?}",
+                self.placeholders, params
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn bind(value: &mut Value, params: &Params) -> Result<(), DBError> {
+    let placeholder = match value {
+        Value::Placeholder(p) => p.to_owned(),
+        _ => return Ok(()),
+    };
+    let param = match params {
+        Params::Positional(values) => placeholder
+            .strip_prefix('$')
+            .and_then(|i| i.parse::<usize>().ok())
+            .and_then(|i| values.get(i.wrapping_sub(1))),
+        Params::Named(values) => placeholder
+            .strip_prefix(':')
+            .and_then(|name| values.get(name)),
+    }
+    .ok_or(DBError::InvalidParameter(format!(
+        "no parameter is bound to {}",
+        placeholder
+    )))?;
+    *value = match param {
+        FieldValue::Int(i) => Value::Number(i.to_string()),
+        FieldValue::Text(s) | FieldValue::Json(s) => Value::QuotedString(s.to_owned()),
+        FieldValue::Bytes(bytes) => match String::from_utf8(bytes.to_owned()) {
+            Ok(s) => Value::QuotedString(s),
+            Err(_) => {
+                return Err(DBError::InvalidParameter(format!(
+                    "parameter {} is not a valid UTF-8 string",
+                    placeholder
+                )))
+            }
+        },
+        FieldValue::Null => Value::Null,
+    };
+    Ok(())
+}
+
+// placeholders may appear in INSERT values and WHERE clauses, also of an explained statement
+fn visit_values<F>(statement: &mut Statement, f: &mut F) -> Result<(), DBError>
+where
+    F: FnMut(&mut Value) -> Result<(), DBError>,
+{
+    match statement {
+        Statement::Select(query) => match &mut query.predicate.expression {
+            Some(expression) => visit_expression(expression, f),
+            None => Ok(()),
+        },
+        Statement::Insert(insert) => {
+            for expression in &mut insert.values {
+                visit_expression(expression, f)?;
+            }
+            Ok(())
+        }
+        Statement::Explain { statement, .. } => visit_values(statement, f),
+        _ => Ok(()),
+    }
+}
+
+fn visit_expression<F>(expression: &mut Expression, f: &mut F) -> Result<(), DBError>
+where
+    F: FnMut(&mut Value) -> Result<(), DBError>,
+{
+    match expression {
+        Expression::Value(value) => f(value),
+        Expression::Ident(_) => Ok(()),
+        Expression::BinOperator { lhs, rhs, .. } => {
+            visit_expression(lhs, f)?;
+            visit_expression(rhs, f)
+        }
+        Expression::JsonExtract { target, .. } => visit_expression(target, f),
+        Expression::Function { args, .. } => {
+            for arg in args {
+                visit_expression(arg, f)?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::rrrdb::{DBError, FieldValue, Record};
+
+// a value built from a record of a result, reading its columns by name.
+// `#[derive(FromRow)]` reads each field from the column of its name
+pub trait FromRow: Sized {
+    fn from_row(record: &Record) -> Result<Self, DBError>;
+}
+
+// the columns of a row to insert, with their values.
+// `#[derive(ToRow)]` writes each field to the column of its name
+pub trait ToRow {
+    fn to_row(&self) -> Vec<(&'static str, FieldValue)>;
+}
+
+// a value read from a column, failing for a value of another type.
+// NULL is read only into an Option
+pub trait FromField: Sized {
+    fn from_field(column: &str, value: &FieldValue) -> Result<Self, DBError>;
+}
+
+fn mismatch(column: &str, column_type: &str, value: &FieldValue) -> DBError {
+    DBError::TypeMismatch {
+        column: column.to_string(),
+        column_type: column_type.to_string(),
+        value: format!("{:?}", value),
+    }
+}
+
+impl FromField for i64 {
+    fn from_field(column: &str, value: &FieldValue) -> Result<Self, DBError> {
+        match value {
+            FieldValue::Int(i) => Ok(*i),
+            other => Err(mismatch(column, "integer", other)),
+        }
+    }
+}
+
+impl FromField for i32 {
+    fn from_field(column: &str, value: &FieldValue) -> Result<Self, DBError> {
+        let i = i64::from_field(column, value)?;
+        std::convert::TryFrom::try_from(i).map_err(|_| mismatch(column, "i32", value))
+    }
+}
+
+impl FromField for String {
+    fn from_field(column: &str, value: &FieldValue) -> Result<Self, DBError> {
+        match value {
+            FieldValue::Text(s) => Ok(s.to_owned()),
+            other => Err(mismatch(column, "varchar", other)),
+        }
+    }
+}
+
+impl FromField for serde_json::Value {
+    fn from_field(column: &str, value: &FieldValue) -> Result<Self, DBError> {
+        match value {
+            FieldValue::Json(doc) => serde_json::from_str(doc).map_err(|err| {
+                DBError::Corruption(format!("broken JSON in column {}. err: {}", column, err))
+            }),
+            other => Err(mismatch(column, "json", other)),
+        }
+    }
+}
+
+impl FromField for FieldValue {
+    fn from_field(_column: &str, value: &FieldValue) -> Result<Self, DBError> {
+        Ok(value.to_owned())
+    }
+}
+
+impl<T: FromField> FromField for Option<T> {
+    fn from_field(column: &str, value: &FieldValue) -> Result<Self, DBError> {
+        match value {
+            FieldValue::Null => Ok(None),
+            value => T::from_field(column, value).map(Some),
+        }
+    }
+}
+
+impl FromRow for Record {
+    fn from_row(record: &Record) -> Result<Self, DBError> {
+        Ok(record.to_owned())
+    }
+}
@@ -1,11 +1,21 @@
 use serde::{Deserialize, Serialize};
 
+use std::convert::TryFrom;
+
+use crate::rrrdb::{
+    parser::{identifier, Expression, ReferentialAction, Value},
+    DBError,
+};
+
+pub(crate) mod statistics;
 pub(crate) mod store;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct Database {
     pub name: String,
     pub tables: Vec<Table>,
+    #[serde(default)]
+    pub sequences: Vec<Sequence>,
 }
 
 impl Database {
@@ -13,8 +23,15 @@ impl Database {
         Self {
             name,
             tables: vec![],
+            sequences: vec![],
         }
     }
+    pub fn sequence(&self, sequence_name: &str) -> Option<Sequence> {
+        (&self.sequences)
+            .into_iter()
+            .find(|sequence| sequence.name == sequence_name)
+            .cloned()
+    }
     pub fn table(&self, table_name: &str) -> Option<Table> {
         (&self.tables).into_iter().find_map(|table| {
             if table.name == table_name {
@@ -30,11 +47,20 @@ impl Database {
 pub(crate) struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    #[serde(default)]
+    pub unique_constraints: Vec<UniqueConstraint>,
+    #[serde(default)]
+    pub foreign_keys: Vec<ForeignKeyConstraint>,
 }
 
 impl Table {
     pub fn new(name: String, columns: Vec<Column>) -> Self {
-        Self { name, columns }
+        Self {
+            name,
+            columns,
+            unique_constraints: vec![],
+            foreign_keys: vec![],
+        }
     }
 
     pub fn column(&self, column_name: &str) -> Option<Column> {
@@ -48,16 +74,174 @@ impl Table {
     }
 }
 
+// the CREATE TABLE statement defining the table
+impl std::fmt::Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let columns = (&self.columns).into_iter().map(|column| {
+            let mut definition = format!(
+                "{} {}",
+                identifier(&column.name),
+                column.column_type.to_string()
+            );
+            if column.not_null {
+                definition.push_str(" NOT NULL");
+            }
+            if let Some(default) = &column.default {
+                definition.push_str(&format!(" DEFAULT {}", default.to_sql()));
+            }
+            if let Some(check) = &column.check {
+                definition.push_str(&format!(" CHECK ({})", check));
+            }
+            if column.sequence.is_some() {
+                definition.push_str(" AUTO_INCREMENT");
+            }
+            definition
+        });
+        let unique_constraints = (&self.unique_constraints).into_iter().map(|unique| {
+            let columns: Vec<String> = (&unique.columns)
+                .into_iter()
+                .map(|column| identifier(column))
+                .collect();
+            format!(
+                "CONSTRAINT {} UNIQUE ({})",
+                identifier(&unique.name),
+                columns.join(", ")
+            )
+        });
+        let foreign_keys = (&self.foreign_keys).into_iter().map(|foreign_key| {
+            let on_delete = match foreign_key.on_delete {
+                ReferentialAction::Restrict => "RESTRICT",
+                ReferentialAction::Cascade => "CASCADE",
+                ReferentialAction::SetNull => "SET NULL",
+            };
+            format!(
+                "CONSTRAINT {} FOREIGN KEY ({}) REFERENCES {}({}) ON DELETE {}",
+                identifier(&foreign_key.name),
+                identifier(&foreign_key.column),
+                identifier(&foreign_key.referenced_table),
+                identifier(&foreign_key.referenced_column),
+                on_delete
+            )
+        });
+        let definitions: Vec<String> = columns
+            .chain(unique_constraints)
+            .chain(foreign_keys)
+            .collect();
+        write!(
+            f,
+            "CREATE TABLE {} (\n  {}\n)",
+            identifier(&self.name),
+            definitions.join(",\n  ")
+        )
+    }
+}
+
+// the counter lives in the metadata column family, so only the definition is kept here
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Sequence {
+    pub name: String,
+    pub start: i64,
+    pub increment: i64,
+}
+
+impl Sequence {
+    pub fn new(name: String, start: Option<i64>, increment: Option<i64>) -> Self {
+        Self {
+            name,
+            start: start.unwrap_or(1),
+            increment: increment.unwrap_or(1),
+        }
+    }
+
+    // the sequence backing a SERIAL or AUTO_INCREMENT column
+    pub fn for_column(table_name: &str, column_name: &str) -> Self {
+        Self::new(format!("{}_{}_seq", table_name, column_name), None, None)
+    }
+
+    // value handed out by the n-th nextval, counting from 1
+    pub fn nth_value(&self, n: i64) -> i64 {
+        self.start + (n - 1) * self.increment
+    }
+}
+
+// backed by a dedicated column family that maps the unique columns' values to the primary key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct UniqueConstraint {
+    pub name: String,
+    pub columns: Vec<String>,
+}
+
+impl UniqueConstraint {
+    pub fn new(table_name: &str, name: Option<String>, columns: Vec<String>) -> Self {
+        let name = name.unwrap_or(format!("{}_{}_key", table_name, columns.join("_")));
+        Self { name, columns }
+    }
+}
+
+// the referenced column is either the primary key or covered by a single-column unique constraint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ForeignKeyConstraint {
+    pub name: String,
+    pub column: String,
+    pub referenced_table: String,
+    pub referenced_column: String,
+    pub on_delete: ReferentialAction,
+}
+
+impl ForeignKeyConstraint {
+    pub fn new(
+        table_name: &str,
+        name: Option<String>,
+        column: String,
+        referenced_table: String,
+        referenced_column: String,
+        on_delete: ReferentialAction,
+    ) -> Self {
+        let name = name.unwrap_or(format!("{}_{}_fkey", table_name, column));
+        Self {
+            name,
+            column,
+            referenced_table,
+            referenced_column,
+            on_delete,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct Column {
     pub name: String,
     pub column_type: ColumnType,
+    #[serde(default)]
+    pub not_null: bool,
+    #[serde(default)]
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub check: Option<Expression>,
+    // filled from this sequence when omitted or NULL on INSERT
+    #[serde(default)]
+    pub sequence: Option<String>,
 }
 
 impl Column {
     pub const ID: &'static str = "id";
     pub fn new(name: String, column_type: ColumnType) -> Self {
-        Self { name, column_type }
+        Self {
+            name,
+            column_type,
+            not_null: false,
+            default: None,
+            check: None,
+            sequence: None,
+        }
+    }
+
+    pub fn not_null_constraint_name(&self, table_name: &str) -> String {
+        format!("{}_{}_not_null", table_name, self.name)
+    }
+
+    pub fn check_constraint_name(&self, table_name: &str) -> String {
+        format!("{}_{}_check", table_name, self.name)
     }
 }
 
@@ -87,16 +271,24 @@ macro_rules! define_column_types {
   };
 }
 
-define_column_types!(Varchar, Integer);
+define_column_types!(Varchar, Integer, Json);
+
+impl TryFrom<String> for ColumnType {
+    type Error = DBError;
 
-impl From<String> for ColumnType {
-    fn from(s: String) -> Self {
+    fn try_from(s: String) -> Result<Self, Self::Error> {
         match s.as_ref() {
-            "string" => ColumnType::Varchar,
-            "varchar" => ColumnType::Varchar,
-            "int" => ColumnType::Integer,
-            "integer" => ColumnType::Integer,
-            default => panic!("unexpected type({}) was given", default),
+            "string" => Ok(ColumnType::Varchar),
+            "varchar" => Ok(ColumnType::Varchar),
+            "int" => Ok(ColumnType::Integer),
+            "integer" => Ok(ColumnType::Integer),
+            "json" => Ok(ColumnType::Json),
+            // SERIAL is an integer column filled from a sequence
+            "serial" => Ok(ColumnType::Integer),
+            other => Err(DBError::InvalidDefinition(format!(
+                "unknown column type {}",
+                other
+            ))),
         }
     }
 }
@@ -0,0 +1,169 @@
+use std::{cmp::Ordering, collections::HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::rrrdb::schema::{ColumnType, Table};
+
+// collected by ANALYZE, and kept until the next ANALYZE of the table
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct TableStatistics {
+    pub row_count: u64,
+    pub columns: Vec<ColumnStatistics>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct ColumnStatistics {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub null_count: u64,
+    pub distinct_count: u64,
+    // equi-depth, in the order of the values
+    pub histogram: Vec<Bucket>,
+}
+
+// values up to `upper`, which are greater than the previous bucket's
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Bucket {
+    pub upper: String,
+    pub count: u64,
+    pub distinct_count: u64,
+}
+
+impl TableStatistics {
+    pub const BUCKETS: usize = 10;
+
+    // rows are the stored values of each column by name, which are absent for NULL
+    pub fn collect<'a, R>(table: &Table, rows: R) -> Self
+    where
+        R: IntoIterator<Item = &'a std::collections::HashMap<String, String>>,
+    {
+        let rows: Vec<_> = rows.into_iter().collect();
+        let columns = (&table.columns)
+            .into_iter()
+            .map(|column| {
+                let mut values: Vec<&String> = (&rows)
+                    .into_iter()
+                    .filter_map(|row| row.get(&column.name))
+                    .collect();
+                values.sort_by(|a, b| compare(&column.column_type, a, b));
+                let distinct_count = values.iter().collect::<HashSet<_>>().len() as u64;
+                ColumnStatistics {
+                    name: column.name.clone(),
+                    column_type: column.column_type.clone(),
+                    null_count: (rows.len() - values.len()) as u64,
+                    distinct_count,
+                    histogram: Self::histogram(&values),
+                }
+            })
+            .collect();
+        Self {
+            row_count: rows.len() as u64,
+            columns,
+        }
+    }
+
+    // a value never spans buckets, so a bucket may hold more than its share
+    fn histogram(sorted: &Vec<&String>) -> Vec<Bucket> {
+        let depth = (sorted.len() + Self::BUCKETS - 1) / Self::BUCKETS;
+        let mut buckets: Vec<Bucket> = vec![];
+        let mut start = 0;
+        while start < sorted.len() {
+            let mut end = (start + depth).min(sorted.len());
+            while end < sorted.len() && sorted[end] == sorted[end - 1] {
+                end += 1;
+            }
+            let values = &sorted[start..end];
+            buckets.push(Bucket {
+                upper: values[values.len() - 1].to_owned(),
+                count: values.len() as u64,
+                distinct_count: values.iter().collect::<HashSet<_>>().len() as u64,
+            });
+            start = end;
+        }
+        buckets
+    }
+
+    pub fn column(&self, column_name: &str) -> Option<&ColumnStatistics> {
+        (&self.columns)
+            .into_iter()
+            .find(|column| column.name == column_name)
+    }
+}
+
+impl ColumnStatistics {
+    // the estimated number of rows holding the value, spread evenly over its bucket
+    pub fn equal_rows(&self, value: &str) -> f64 {
+        let column_type = &self.column_type;
+        match (&self.histogram)
+            .into_iter()
+            .find(|bucket| compare(column_type, value, &bucket.upper) != Ordering::Greater)
+        {
+            Some(bucket) => bucket.count as f64 / bucket.distinct_count.max(1) as f64,
+            None => 0.0,
+        }
+    }
+}
+
+// integers in the order of numbers, and others in the order of their text
+fn compare(column_type: &ColumnType, a: &str, b: &str) -> Ordering {
+    match (column_type, a.parse::<i64>(), b.parse::<i64>()) {
+        (ColumnType::Integer, Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::rrrdb::schema::Column;
+
+    #[test]
+    fn collect() {
+        let table = Table::new(
+            "users".to_string(),
+            vec![
+                Column::new("id".to_string(), ColumnType::Integer),
+                Column::new("age".to_string(), ColumnType::Integer),
+            ],
+        );
+        // ages 0..=9 twice, 100 once, and NULL once
+        let rows: Vec<HashMap<String, String>> = (0..22)
+            .map(|id| {
+                let mut row = HashMap::new();
+                row.insert("id".to_string(), id.to_string());
+                match id {
+                    20 => {
+                        row.insert("age".to_string(), "100".to_string());
+                    }
+                    21 => {}
+                    id => {
+                        row.insert("age".to_string(), (id % 10).to_string());
+                    }
+                }
+                row
+            })
+            .collect();
+        let statistics = TableStatistics::collect(&table, &rows);
+        assert_eq!(statistics.row_count, 22);
+
+        let id = statistics.column("id").unwrap();
+        assert_eq!((id.null_count, id.distinct_count), (0, 22));
+        assert_eq!(id.histogram.len(), 8);
+        // in the order of numbers, not of text
+        assert_eq!(id.histogram[0].upper, "2");
+        assert_eq!(id.histogram[7].upper, "21");
+        assert_eq!(id.equal_rows("5"), 1.0);
+        assert_eq!(id.equal_rows("22"), 0.0);
+
+        let age = statistics.column("age").unwrap();
+        assert_eq!((age.null_count, age.distinct_count), (1, 11));
+        assert_eq!(
+            age.histogram.iter().map(|b| b.count).sum::<u64>(),
+            statistics.row_count - age.null_count
+        );
+        assert_eq!(age.equal_rows("3"), 2.0);
+        assert_eq!(age.equal_rows("100"), 1.0);
+    }
+}
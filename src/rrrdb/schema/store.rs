@@ -1,47 +1,257 @@
-use std::error::Error;
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{PoisonError, RwLock},
+};
 
 use crate::rrrdb::{
-    schema::*,
+    schema::{statistics::TableStatistics, *},
     storage::{Namespace, Storage},
     DBError,
 };
 
+// every statement reads its schema, so the latest ones are kept deserialized.
+// an entry is dropped when its key is written, and the generation tells a reader loading one
+// that it may already be stale.
+#[derive(Default)]
+pub(crate) struct SchemaCache {
+    entries: RwLock<(u64, HashMap<String, Database>)>,
+}
+
+impl SchemaCache {
+    fn get(&self, key: &str) -> (Option<Database>, u64) {
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+        (entries.1.get(key).cloned(), entries.0)
+    }
+
+    fn insert(&self, key: &str, generation: u64, database: Database) {
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        if entries.0 == generation {
+            entries.1.insert(key.to_string(), database);
+        }
+    }
+
+    pub(crate) fn invalidate(&self, key: &str) {
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        entries.0 += 1;
+        entries.1.remove(key);
+    }
+}
+
 pub(crate) struct SchemaStore<'a> {
     db: &'a mut Storage,
 }
 
 impl<'a> SchemaStore<'a> {
-    const SCHEMA_SUFFIX: &'static str = "_schema";
+    const SCHEMA: &'static str = "schema";
+    const SEQUENCE: &'static str = "sequence";
+    const STATISTICS: &'static str = "statistics";
+    // as schemas were saved before keys were length-prefixed
+    const LEGACY_SCHEMA_SUFFIX: &'static str = "_schema";
 
     pub fn new(db: &'a mut Storage) -> SchemaStore<'a> {
         Self { db }
     }
 
+    // the kind of the metadata and the names are length-prefixed like column family names,
+    // e.g. `6:schema_7:test_db`, so that no two keys are the same whatever the names are
+    pub(crate) fn schema_key(database_name: &str) -> String {
+        Namespace::encode(&[Self::SCHEMA, database_name])
+    }
+
+    fn sequence_key(database_name: &str, sequence_name: &str) -> String {
+        Namespace::encode(&[Self::SEQUENCE, database_name, sequence_name])
+    }
+
+    fn statistics_key(database_name: &str, table_name: &str) -> String {
+        Namespace::encode(&[Self::STATISTICS, database_name, table_name])
+    }
+
+    // `6:schema_`, `8:sequence_` or `10:statistics_`
+    fn key_prefix(kind: &str) -> String {
+        format!("{}_", Namespace::encode(&[kind]))
+    }
+
     pub fn find_schema(&self, database_name: &str) -> Result<Option<Database>, DBError> {
-        self.db.get_serialized::<Database>(
-            &Namespace::Metadata,
-            format!("{}{}", database_name, Self::SCHEMA_SUFFIX).as_ref(),
-        )
+        let key = Self::schema_key(database_name);
+        // changed by the statement in progress
+        if self.db.is_written(&Namespace::Metadata, &key) {
+            return self
+                .db
+                .get_serialized::<Database>(&Namespace::Metadata, &key);
+        }
+        let cache = self.db.schema_cache();
+        let (cached, generation) = cache.get(&key);
+        if cached.is_some() {
+            return Ok(cached);
+        }
+        let schema = self
+            .db
+            .get_latest_serialized::<Database>(&Namespace::Metadata, &key)?;
+        if let Some(schema) = &schema {
+            cache.insert(&key, generation, schema.clone());
+        }
+        Ok(schema)
+    }
+
+    // fails on a schema which can't be read
+    pub fn find_all_schemas(&self) -> Result<Vec<Database>, DBError> {
+        let prefix = Self::key_prefix(Self::SCHEMA);
+        let mut schemas = vec![];
+        for (key, value) in self.db.iterator(&Namespace::Metadata)? {
+            if !key.starts_with(&prefix) {
+                continue;
+            }
+            schemas.push(Self::read_schema(&key, &value)?);
+        }
+        Ok(schemas)
+    }
+
+    fn read_schema(key: &str, value: &[u8]) -> Result<Database, DBError> {
+        serde_json::from_slice::<Database>(value)
+            .map_err(|err| DBError::Corruption(format!("schema {} can't be read: {}", key, err)))
+    }
+
+    // moves schemas saved as `{database}_schema` to their length-prefixed keys, all at once.
+    // returns the keys moved from and to
+    pub fn migrate_legacy_schemas(&mut self) -> Result<Vec<(String, String)>, DBError> {
+        let prefixes: Vec<String> = [Self::SCHEMA, Self::SEQUENCE, Self::STATISTICS]
+            .iter()
+            .map(|kind| Self::key_prefix(kind))
+            .collect();
+        let mut legacy = vec![];
+        for (key, value) in self.db.iterator(&Namespace::Metadata)? {
+            if prefixes.iter().any(|prefix| key.starts_with(prefix))
+                || !key.ends_with(Self::LEGACY_SCHEMA_SUFFIX)
+            {
+                continue;
+            }
+            let schema = Self::read_schema(&key, &value)?;
+            if key == format!("{}{}", schema.name, Self::LEGACY_SCHEMA_SUFFIX) {
+                legacy.push((key, value));
+            }
+        }
+        if legacy.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut moved = vec![];
+        self.db.begin_statement();
+        for (key, value) in legacy {
+            let name = &key[..key.len() - Self::LEGACY_SCHEMA_SUFFIX.len()];
+            let to = Self::schema_key(name);
+            let result = self
+                .db
+                .put(&Namespace::Metadata, &to, value.into_vec())
+                .and_then(|_| self.db.delete(&Namespace::Metadata, &key));
+            if let Err(err) = result {
+                self.db.rollback_statement();
+                return Err(err);
+            }
+            moved.push((key, to));
+        }
+        self.db.commit_statement()?;
+        Ok(moved)
     }
 
     pub fn save_schema(&mut self, database: Database) -> Result<(), DBError> {
-        let key = format!("{}{}", &database.name, Self::SCHEMA_SUFFIX);
+        let key = Self::schema_key(&database.name);
         self.db.put_serialized(&Namespace::Metadata, &key, database)
     }
 
-    pub fn create_table(&mut self, database_name: &str, table: Table) -> Result<(), DBError> {
+    // the table and the sequences of its SERIAL columns are saved at once
+    pub fn create_table(
+        &mut self,
+        database_name: &str,
+        table: Table,
+        sequences: Vec<Sequence>,
+    ) -> Result<(), DBError> {
+        let mut schema = self
+            .find_schema(database_name)?
+            .unwrap_or(Database::empty(database_name.to_string()));
+        if let Some(tbl) = schema.table(table.name.as_ref()) {
+            return Err(DBError::AlreadyExists(format!("table {}", tbl.name)));
+        }
+        for sequence in &sequences {
+            Self::ensure_no_sequence(&schema, &sequence.name)?;
+        }
+        schema.tables.push(table);
+        schema.sequences.extend(sequences);
+        self.save_schema(schema)
+    }
+
+    pub fn create_sequence(
+        &mut self,
+        database_name: &str,
+        sequence: Sequence,
+    ) -> Result<(), DBError> {
         let mut schema = self
             .find_schema(database_name)?
             .unwrap_or(Database::empty(database_name.to_string()));
-        match schema.table(table.name.as_ref()) {
-            Some(tbl) => Err(DBError::new(format!(
-                "table already exist. table = {:?}",
-                tbl
+        Self::ensure_no_sequence(&schema, &sequence.name)?;
+        schema.sequences.push(sequence);
+        self.save_schema(schema)
+    }
+
+    fn ensure_no_sequence(schema: &Database, sequence_name: &str) -> Result<(), DBError> {
+        match schema.sequence(sequence_name) {
+            Some(sequence) => Err(DBError::AlreadyExists(format!(
+                "sequence {}",
+                sequence.name
             ))),
-            None => {
-                schema.tables.push(table);
-                self.save_schema(schema)
-            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn find_statistics(
+        &self,
+        database_name: &str,
+        table_name: &str,
+    ) -> Result<Option<TableStatistics>, DBError> {
+        let key = Self::statistics_key(database_name, table_name);
+        self.db.get_serialized(&Namespace::Metadata, &key)
+    }
+
+    pub fn save_statistics(
+        &mut self,
+        database_name: &str,
+        table_name: &str,
+        statistics: TableStatistics,
+    ) -> Result<(), DBError> {
+        let key = Self::statistics_key(database_name, table_name);
+        self.db
+            .put_serialized(&Namespace::Metadata, &key, statistics)
+    }
+
+    // the rows of a table, counted by ANALYZE or estimated by RocksDB
+    pub fn estimate_row_count(
+        &self,
+        database_name: &str,
+        table_name: &str,
+    ) -> Result<u64, DBError> {
+        if let Some(statistics) = self.find_statistics(database_name, table_name)? {
+            return Ok(statistics.row_count);
         }
+        let namespace = Namespace::table(database_name, table_name);
+        Ok(self.db.estimate_num_keys(&namespace)?.unwrap_or(0))
+    }
+
+    // every caller gets a counter of its own, as it's incremented and read at once.
+    // values skipped by a crash or a failed statement are never reused.
+    pub fn next_value(&mut self, database_name: &str, sequence_name: &str) -> Result<i64, DBError> {
+        let sequence = self
+            .find_schema(database_name)?
+            .and_then(|schema| schema.sequence(sequence_name))
+            .ok_or(DBError::UnknownSequence(sequence_name.to_string()))?;
+        let key = Self::sequence_key(database_name, sequence_name);
+        let counter = self
+            .db
+            .increment(&Namespace::Metadata, &key)?
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or(DBError::Corruption(format!(
+                "counter of sequence {} is broken",
+                sequence_name
+            )))?;
+        Ok(sequence.nth_value(counter))
     }
 }
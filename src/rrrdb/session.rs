@@ -0,0 +1,276 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::rrrdb::{
+    parser::{identifier, Parser, SessionStatement, Statement},
+    prepared::{Params, PreparedStatement},
+    row::{FromRow, ToRow},
+    schema::store::SchemaStore,
+    sql::{
+        executor::{Executor, Rows},
+        planner::Planner,
+    },
+    storage::{SharedStorage, Storage},
+    DBError, DBResult, FieldValue, OkDBResult,
+};
+
+// a connection to RrrDB, holding the current database, the transaction in progress,
+// session variables and prepared statements.
+// a session runs one statement at a time, and sessions run concurrently.
+pub struct Session {
+    underlying: Arc<SharedStorage>,
+    // holds the transaction in progress
+    storage: Storage,
+    // set by USE
+    database_name: Option<String>,
+    // set by SET name = value
+    variables: HashMap<String, String>,
+    // by database name and query
+    prepared_statements: HashMap<(Option<String>, String), PreparedStatement>,
+}
+
+impl Session {
+    pub(crate) fn new(underlying: &Arc<SharedStorage>) -> Self {
+        Self {
+            underlying: underlying.clone(),
+            storage: Storage::session(underlying),
+            database_name: None,
+            variables: HashMap::new(),
+            prepared_statements: HashMap::new(),
+        }
+    }
+
+    pub fn database_name(&self) -> Option<&str> {
+        self.database_name.as_deref()
+    }
+
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.variables
+            .get(&name.to_lowercase())
+            .map(|value| value.as_str())
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.storage.in_transaction()
+    }
+
+    // runs on the database selected by USE
+    pub fn execute(&mut self, query: &str) -> DBResult {
+        let statement = Parser::parse_sql(self.database_name.clone(), query)?;
+        self.execute_statement(self.database_name.clone(), statement)
+            .map_err(|e| e.with_source(query))
+    }
+
+    // the rows of a SELECT, read from a snapshot taken now as they are iterated.
+    // the transaction's snapshot is read instead under snapshot isolation
+    pub fn query(&mut self, query: &str) -> Result<Rows<'static>, DBError> {
+        self.query_on(self.database_name.clone(), query)
+    }
+
+    pub(crate) fn query_on(
+        &mut self,
+        database_name: Option<String>,
+        query: &str,
+    ) -> Result<Rows<'static>, DBError> {
+        let statement = Parser::parse_sql(database_name.clone(), query)?;
+        if !matches!(statement, Statement::Select(_)) {
+            return Err(DBError::InvalidStatement(
+                "only SELECT returns rows".to_string(),
+            ));
+        }
+        if !PreparedStatement::placeholders(&statement).is_empty() {
+            return Err(DBError::InvalidParameter(
+                "a statement with placeholders must be prepared".to_string(),
+            ));
+        }
+        let database_name = database_name.ok_or(DBError::NoDatabaseSelected)?;
+        self.storage.begin_statement();
+        let rows = Planner::new(&database_name, &mut self.storage, statement)
+            .and_then(|mut planner| planner.plan())
+            .and_then(|plan| Executor::new(&mut self.storage, plan).query());
+        self.storage.rollback_statement();
+        rows.map_err(|e| e.with_source(query))
+    }
+
+    // the rows of a SELECT, each built into a T
+    pub fn query_as<T: FromRow>(&mut self, query: &str) -> Result<Vec<T>, DBError> {
+        self.query_as_on(self.database_name.clone(), query)
+    }
+
+    pub(crate) fn query_as_on<T: FromRow>(
+        &mut self,
+        database_name: Option<String>,
+        query: &str,
+    ) -> Result<Vec<T>, DBError> {
+        self.query_on(database_name, query)?
+            .map(|record| record.and_then(|record| T::from_row(&record)))
+            .collect()
+    }
+
+    // inserts the columns of the row into the table, through a prepared statement
+    pub fn insert<T: ToRow>(&mut self, table_name: &str, row: &T) -> DBResult {
+        self.insert_on(self.database_name.clone(), table_name, row)
+    }
+
+    pub(crate) fn insert_on<T: ToRow>(
+        &mut self,
+        database_name: Option<String>,
+        table_name: &str,
+        row: &T,
+    ) -> DBResult {
+        let (columns, values): (Vec<&str>, Vec<FieldValue>) = row.to_row().into_iter().unzip();
+        let columns: Vec<String> = columns.into_iter().map(identifier).collect();
+        let query = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            identifier(table_name),
+            columns.join(", "),
+            vec!["?"; columns.len()].join(", ")
+        );
+        let prepared = self.prepare_on(database_name, &query)?;
+        self.execute_prepared(&prepared, values)
+    }
+
+    // the names of the tables in the database, in the order they were created
+    pub fn table_names(&mut self) -> Result<Vec<String>, DBError> {
+        self.table_names_on(self.database_name.clone())
+    }
+
+    pub(crate) fn table_names_on(
+        &mut self,
+        database_name: Option<String>,
+    ) -> Result<Vec<String>, DBError> {
+        let database_name = database_name.ok_or(DBError::NoDatabaseSelected)?;
+        self.storage.begin_statement();
+        let database = SchemaStore::new(&mut self.storage).find_schema(&database_name);
+        self.storage.rollback_statement();
+        Ok(database?
+            .map(|database| database.tables)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|table| table.name)
+            .collect())
+    }
+
+    // the CREATE TABLE statement of the table
+    pub fn table_definition(&mut self, table_name: &str) -> Result<String, DBError> {
+        self.table_definition_on(self.database_name.clone(), table_name)
+    }
+
+    pub(crate) fn table_definition_on(
+        &mut self,
+        database_name: Option<String>,
+        table_name: &str,
+    ) -> Result<String, DBError> {
+        let database_name = database_name.ok_or(DBError::NoDatabaseSelected)?;
+        self.storage.begin_statement();
+        let database = SchemaStore::new(&mut self.storage).find_schema(&database_name);
+        self.storage.rollback_statement();
+        database?
+            .and_then(|database| database.table(table_name))
+            .map(|table| table.to_string())
+            .ok_or(DBError::UnknownTable(table_name.to_string()))
+    }
+
+    // parses the query, and plans it if it's a SELECT, only once in the session
+    pub fn prepare(&mut self, query: &str) -> Result<PreparedStatement, DBError> {
+        self.prepare_on(self.database_name.clone(), query)
+    }
+
+    pub(crate) fn prepare_on(
+        &mut self,
+        database_name: Option<String>,
+        query: &str,
+    ) -> Result<PreparedStatement, DBError> {
+        let key = (database_name.clone(), query.to_string());
+        if let Some(prepared) = self.prepared_statements.get(&key) {
+            return Ok(prepared.clone());
+        }
+        let statement = Parser::parse_sql(database_name.clone(), query)?;
+        let plan = match (&statement, &database_name) {
+            (Statement::Select(_), Some(name)) => {
+                self.storage.begin_statement();
+                let plan = Planner::new(name, &mut self.storage, statement.clone())
+                    .and_then(|mut planner| planner.plan());
+                self.storage.rollback_statement();
+                Some(plan.map_err(|e| e.with_source(query))?)
+            }
+            _ => None,
+        };
+        let prepared = PreparedStatement::new(database_name, query, statement, plan);
+        self.prepared_statements.insert(key, prepared.clone());
+        Ok(prepared)
+    }
+
+    pub fn execute_prepared<P: Into<Params>>(
+        &mut self,
+        prepared: &PreparedStatement,
+        params: P,
+    ) -> DBResult {
+        let params = params.into();
+        match prepared.bind_plan(&params)? {
+            Some(plan) => self.run(false, |storage| Executor::new(storage, plan).execute()),
+            None => {
+                let statement = prepared.bind_statement(&params)?;
+                self.execute_statement(prepared.database_name.clone(), statement)
+            }
+        }
+        .map_err(|e| e.with_source(&prepared.query))
+    }
+
+    pub(crate) fn execute_statement(
+        &mut self,
+        database_name: Option<String>,
+        statement: Statement,
+    ) -> DBResult {
+        let database_name = match statement {
+            Statement::Session(statement) => return self.execute_session_statement(statement),
+            Statement::Select(_)
+            | Statement::Insert(_)
+            | Statement::Explain { .. }
+            | Statement::Analyze { .. } => database_name.ok_or(DBError::NoDatabaseSelected)?,
+            _ => database_name.unwrap_or_default(),
+        };
+        if !PreparedStatement::placeholders(&statement).is_empty() {
+            return Err(DBError::InvalidParameter(
+                "a statement with placeholders must be prepared".to_string(),
+            ));
+        }
+        let creates = matches!(
+            statement,
+            Statement::CreateDatabase(_) | Statement::CreateTable(_) | Statement::CreateSequence(_)
+        );
+        self.run(creates, |storage| {
+            let plan = Planner::new(&database_name, storage, statement)?.plan()?;
+            Executor::new(storage, plan).execute()
+        })
+    }
+
+    // a statement is applied all or nothing
+    fn run<F>(&mut self, creates: bool, f: F) -> DBResult
+    where
+        F: FnOnce(&mut Storage) -> DBResult,
+    {
+        // CREATE statements are refused inside a transaction by the executor, before they wait
+        let _schema_lock = if creates && !self.in_transaction() {
+            Some(self.underlying.lock_schema())
+        } else {
+            None
+        };
+        self.storage.begin_statement();
+        let result = f(&mut self.storage);
+        match &result {
+            Ok(_) => self.storage.commit_statement()?,
+            Err(_) => self.storage.rollback_statement(),
+        }
+        result
+    }
+
+    fn execute_session_statement(&mut self, statement: SessionStatement) -> DBResult {
+        match statement {
+            SessionStatement::Use(database_name) => self.database_name = Some(database_name),
+            SessionStatement::SetVariable { name, value } => {
+                self.variables.insert(name, value);
+            }
+        }
+        Ok(OkDBResult::ExecutionResult)
+    }
+}
@@ -1,2 +1,4 @@
+pub(crate) mod evaluator;
 pub(crate) mod executor;
+pub(crate) mod operator;
 pub(crate) mod planner;
@@ -0,0 +1,118 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::rrrdb::{parser::*, DBError};
+
+// evaluates an expression against a single row, following SQL's three-valued logic where
+// any comparison with NULL yields NULL
+pub(crate) struct Evaluator<'a> {
+    row: &'a HashMap<String, Value>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(row: &'a HashMap<String, Value>) -> Self {
+        Self { row }
+    }
+
+    pub fn evaluate(&self, expr: &Expression) -> Result<Value, DBError> {
+        match expr {
+            Expression::Value(value) => Ok(value.to_owned()),
+            Expression::Ident(ident) => self
+                .row
+                .get(ident.as_str())
+                .cloned()
+                .ok_or_else(|| DBError::UnknownColumn(ident.to_string()).at(ident.location)),
+            Expression::BinOperator { lhs, rhs, op } => {
+                let lhs = self.evaluate(lhs)?;
+                let rhs = self.evaluate(rhs)?;
+                Self::apply_operator(op, lhs, rhs)
+            }
+            Expression::JsonExtract {
+                target,
+                path,
+                as_text,
+            } => {
+                let document = match self.evaluate(target)? {
+                    Value::Null => return Ok(Value::Null),
+                    value => serde_json::from_str::<serde_json::Value>(&value.to_string())
+                        .map_err(|err| {
+                            DBError::InvalidStatement(format!("not a JSON document: {}", err))
+                        })?,
+                };
+                Ok(match path.extract(&document) {
+                    None | Some(serde_json::Value::Null) => Value::Null,
+                    Some(serde_json::Value::String(s)) if *as_text => {
+                        Value::QuotedString(s.to_owned())
+                    }
+                    Some(found) => Value::QuotedString(found.to_string()),
+                })
+            }
+            // functions may have side effects like nextval, so they are never evaluated per row
+            Expression::Function { name, .. } => Err(DBError::InvalidStatement(format!(
+                "function {} is not allowed here",
+                name
+            ))),
+        }
+    }
+
+    // CHECK constraints are satisfied unless the expression is definitely false
+    pub fn is_satisfied(&self, expr: &Expression) -> Result<bool, DBError> {
+        match self.evaluate(expr)? {
+            Value::Boolean(b) => Ok(b),
+            Value::Null => Ok(true),
+            other => Err(DBError::InvalidStatement(format!(
+                "expression must be boolean, but got {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn apply_operator(op: &BinaryOperator, lhs: Value, rhs: Value) -> Result<Value, DBError> {
+        match op {
+            BinaryOperator::And => match (lhs, rhs) {
+                (Value::Boolean(false), _) | (_, Value::Boolean(false)) => {
+                    Ok(Value::Boolean(false))
+                }
+                (Value::Boolean(true), Value::Boolean(true)) => Ok(Value::Boolean(true)),
+                _ => Ok(Value::Null),
+            },
+            BinaryOperator::Or => match (lhs, rhs) {
+                (Value::Boolean(true), _) | (_, Value::Boolean(true)) => Ok(Value::Boolean(true)),
+                (Value::Boolean(false), Value::Boolean(false)) => Ok(Value::Boolean(false)),
+                _ => Ok(Value::Null),
+            },
+            comparison => {
+                let ordering = match Self::compare(&lhs, &rhs) {
+                    Some(ordering) => ordering,
+                    None => return Ok(Value::Null),
+                };
+                let result = match comparison {
+                    BinaryOperator::Eq => ordering == Ordering::Equal,
+                    BinaryOperator::Neq => ordering != Ordering::Equal,
+                    BinaryOperator::Lt => ordering == Ordering::Less,
+                    BinaryOperator::Lte => ordering != Ordering::Greater,
+                    BinaryOperator::Gt => ordering == Ordering::Greater,
+                    BinaryOperator::Gte => ordering != Ordering::Less,
+                    BinaryOperator::And | BinaryOperator::Or => unreachable!(),
+                };
+                Ok(Value::Boolean(result))
+            }
+        }
+    }
+
+    // a number compares numerically with anything that parses as a number, otherwise by text
+    fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+        match (lhs, rhs) {
+            (Value::Null, _) | (_, Value::Null) => None,
+            (Value::Boolean(l), Value::Boolean(r)) => Some(l.cmp(r)),
+            (Value::QuotedString(l), Value::QuotedString(r)) => Some(l.cmp(r)),
+            _ => match (
+                lhs.to_string().parse::<i64>(),
+                rhs.to_string().parse::<i64>(),
+            ) {
+                (Ok(l), Ok(r)) => Some(l.cmp(&r)),
+                _ => Some(lhs.to_string().cmp(&rhs.to_string())),
+            },
+        }
+    }
+}
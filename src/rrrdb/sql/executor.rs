@@ -1,24 +1,50 @@
-use std::{collections::HashMap, iter::Map, ops::Deref, todo};
+use std::{
+    collections::HashMap, convert::TryFrom, iter::Map, ops::Deref, rc::Rc, sync::Arc,
+    time::Instant, todo,
+};
 
 use storage::Namespace;
 
 use crate::rrrdb::{storage::Storage, *};
 
-use super::super::schema::store::SchemaStore;
+use super::super::parser::{
+    BinaryOperator, ColumnConstraint, ColumnDefinition, TransactionStatement,
+};
 use super::super::schema::*;
+use super::super::schema::{statistics::TableStatistics, store::SchemaStore};
+use super::operator::{self, Operator, OperatorStats};
 use super::planner::*;
 
 pub(crate) struct Executor<'a> {
     storage: &'a mut Storage,
     plan: Plan,
+    // collects the stats of every operator built, in the order of EXPLAIN, for EXPLAIN ANALYZE
+    analyzed: Option<Vec<Rc<OperatorStats>>>,
 }
 
 impl<'a> Executor<'a> {
     pub fn new(storage: &'a mut Storage, plan: Plan) -> Self {
-        Self { storage, plan }
+        Self {
+            storage,
+            plan,
+            analyzed: None,
+        }
     }
 
     pub fn execute(&mut self) -> DBResult {
+        match &self.plan {
+            // column families can't be created or dropped atomically with other writes
+            Plan::CreateDatabasePlan(_)
+            | Plan::CreateTablePlan(_)
+            | Plan::CreateSequencePlan(_)
+                if self.storage.in_transaction() =>
+            {
+                return Err(DBError::InvalidTransactionState(
+                    "CREATE statements are not supported inside a transaction".to_string(),
+                ))
+            }
+            _ => {}
+        }
         match &self.plan {
             Plan::SelectPlan(select_plan) => self.execute_select(select_plan.clone()),
             Plan::InsertPlan(insert_plan) => self.execute_insert(insert_plan.clone()),
@@ -28,127 +54,249 @@ impl<'a> Executor<'a> {
             Plan::CreateTablePlan(create_table_plan) => {
                 self.execute_create_table(create_table_plan.clone())
             }
+            Plan::CreateSequencePlan(create_sequence_plan) => {
+                self.execute_create_sequence(create_sequence_plan.clone())
+            }
+            Plan::TransactionPlan(transaction) => self.execute_transaction(transaction.clone()),
+            Plan::ExplainPlan(explain_plan) => self.execute_explain(explain_plan.clone()),
+            Plan::AnalyzePlan(analyze_plan) => self.execute_analyze(analyze_plan.clone()),
         }
     }
 
-    fn execute_select(&mut self, select_plan: SelectPlan) -> DBResult {
-        let field_metadatas: Vec<FieldMetadata> = select_plan.result_metadata();
-        println!("[execute_select] field_metadatas: {:?}", field_metadatas);
-        let table = &select_plan.plans.get(0).unwrap().table;
-        let namespace = Namespace::table(&select_plan.database.name, &table.name);
-        // support only one table
-        // let namespaces: Vec<Namespace> = (&select_plan.plans)
-        //     .clone()
-        //     .into_iter()
-        //     .map(|p| Namespace::table(&select_plan.database.name, &p.table.name))
-        //     .collect();
-
-        // TODO: concurrent
-        let filters: Vec<Filter> = select_plan
-            .filters
+    // a row per operator with its estimates, and with the rows it returned and the microseconds
+    // spent in it on ANALYZE
+    fn execute_explain(&mut self, explain_plan: ExplainPlan) -> DBResult {
+        let ExplainPlan { plan, analyze } = explain_plan;
+        let cost_model = CostModel::load(
+            &SchemaStore::new(&mut self.storage),
+            &plan.database.name,
+            plan.root.tables(),
+        )?;
+        let mut rows: Vec<Vec<FieldValue>> = (&plan.root)
+            .explain()
             .into_iter()
-            .filter(|f| f.table_name == table.name)
+            .map(|(depth, node)| {
+                let indent = match depth {
+                    0 => String::new(),
+                    depth => format!("{}-> ", "  ".repeat(depth)),
+                };
+                let estimate = cost_model.estimate(node);
+                vec![
+                    FieldValue::Text(format!("{}{}", indent, node)),
+                    FieldValue::Int(estimate.rows.round() as i64),
+                    FieldValue::Text(format!("{:.2}", estimate.cost)),
+                ]
+            })
             .collect();
-        let iterator = self.storage.iterator(&namespace)?; // iterate over given namespace(table)
+        let mut fields = vec![
+            FieldMetadata::new("plan", &ColumnType::Varchar.to_string()),
+            FieldMetadata::new("estimated_rows", &ColumnType::Integer.to_string()),
+            FieldMetadata::new("estimated_cost", &ColumnType::Varchar.to_string()),
+        ];
+        if analyze {
+            self.analyzed = Some(vec![]);
+            let mut operator = self.build_operator(&plan)?;
+            operator::drain(operator.as_mut())?;
+            let analyzed = self.analyzed.take().unwrap_or_default();
+            for (row, stats) in rows.iter_mut().zip(analyzed) {
+                row.push(FieldValue::Int(stats.rows.get() as i64));
+                row.push(FieldValue::Int(stats.elapsed.get().as_micros() as i64));
+            }
+            fields.push(FieldMetadata::new("rows", &ColumnType::Integer.to_string()));
+            fields.push(FieldMetadata::new(
+                "time_us",
+                &ColumnType::Integer.to_string(),
+            ));
+        }
+        let records = rows.into_iter().map(Record::new).collect();
+        Ok(OkDBResult::SelectResult(ResultSet::new(
+            records,
+            ResultMetadata::new(fields),
+        )))
+    }
 
-        let records = iterator
-            .filter_map(|(key, value_bytes)| {
-                match String::from_utf8(value_bytes.into_vec())
-                    .map(|j| serde_json::from_str::<serde_json::Value>(&j))
-                {
-                    Ok(Ok(json)) => {
-                        let record = Self::parse_single_row(&table, json);
-                        println!(
-                            "field_metadatas: {:?}, record: {:?}",
-                            field_metadatas, record
-                        );
-                        if let Some(filter) = Self::apply_filter(&filters, &record) {
-                            println!("skipped by filter {:?}. record = {:?}", filter, record);
-                            return None;
-                        };
-
-                        Some(Self::build_record(&field_metadatas, &record))
-                    }
-                    Ok(Err(err)) => {
-                        panic!(
-                            "unexpected formatted row for key({:?}). err = {:?}",
-                            key, err
-                        );
-                    }
-                    Err(err) => {
-                        panic!(
-                            "unexpected formatted row for key({:?}). err = {:?}",
+    // replaces the statistics of each table with ones collected from its rows
+    fn execute_analyze(&mut self, analyze_plan: AnalyzePlan) -> DBResult {
+        for table in &analyze_plan.tables {
+            let namespace = Namespace::table(&analyze_plan.database_name, &table.name);
+            let rows = self
+                .storage
+                .iterator(&namespace)?
+                .map(|(key, value)| {
+                    serde_json::from_slice::<HashMap<String, String>>(&value).map_err(|err| {
+                        DBError::Corruption(format!(
+                            "unexpected formatted row for key({:?}). err = {}",
                             key, err
-                        );
-                    }
-                }
-            })
-            .collect();
-        let result_set = ResultSet::new(records, ResultMetadata::new(field_metadatas));
-        Ok(OkDBResult::SelectResult(result_set))
-    }
-
-    fn parse_single_row(table: &Table, json: serde_json::Value) -> HashMap<String, FieldValue> {
-        let row: &serde_json::Map<String, serde_json::Value> =
-            &json.as_object().unwrap().to_owned();
-        row.into_iter()
-            .fold(HashMap::new(), |mut map, (column_name, column_value)| {
-                let bytes = column_value.as_str().unwrap().as_bytes().to_vec();
-                match table.column(column_name).unwrap().column_type {
-                    ColumnType::Integer => {
-                        let int = String::from_utf8(bytes).unwrap().parse::<i64>().unwrap();
-                        map.insert(column_name.to_owned(), FieldValue::Int(int));
-                    }
-                    ColumnType::Varchar => {
-                        let s = String::from_utf8(bytes).unwrap();
-                        map.insert(column_name.to_owned(), FieldValue::Text(s));
-                    }
-                }
-                map
-            })
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<HashMap<String, String>>, DBError>>()?;
+            let statistics = TableStatistics::collect(table, &rows);
+            SchemaStore::new(&mut self.storage).save_statistics(
+                &analyze_plan.database_name,
+                &table.name,
+                statistics,
+            )?;
+        }
+        Ok(OkDBResult::ExecutionResult)
     }
 
-    fn apply_filter(filters: &Vec<Filter>, record: &HashMap<String, FieldValue>) -> Option<Filter> {
-        filters
-            .into_iter()
-            .find(|filter| {
-                let value: &FieldValue = record
-                    .get(&filter.column_name)
-                    .expect(&format!("column was not found in record {:?}", record));
-                match (value, &filter.expected_value) {
-                    (FieldValue::Int(i), parser::Value::Number(n)) => {
-                        &n.parse::<i64>().unwrap() != i
-                    }
-                    (FieldValue::Text(t), parser::Value::QuotedString(s)) => t != s,
-                    _ => {
-                        println!(
-                            "unexpected combination. value: {:?}, expected: {:?}",
-                            value, filter.expected_value
-                        );
-                        true
-                    }
-                }
-            })
-            .map(|f| f.to_owned())
+    fn execute_transaction(&mut self, transaction: TransactionStatement) -> DBResult {
+        match transaction {
+            TransactionStatement::Begin => self.storage.begin(),
+            TransactionStatement::Commit => self.storage.commit(),
+            TransactionStatement::Rollback => self.storage.rollback(),
+            TransactionStatement::SetIsolationLevel(isolation_level) => {
+                self.storage.set_isolation_level(isolation_level)
+            }
+        }
+        .map(|_| OkDBResult::ExecutionResult)
     }
 
-    fn build_record(
-        field_metadatas: &Vec<FieldMetadata>,
-        record: &HashMap<String, FieldValue>,
-    ) -> Record {
-        let field_values: Vec<FieldValue> = field_metadatas
-            .into_iter()
-            .filter_map(|meta| {
-                record.into_iter().find_map(|(column_name, field_value)| {
-                    if column_name.deref() == meta.field_name {
-                        Some(field_value.to_owned())
-                    } else {
-                        None
-                    }
-                })
-            })
-            .collect();
-        Record::new(field_values)
+    fn execute_select(&mut self, select_plan: SelectPlan) -> DBResult {
+        let rows = Rows::new(self.build_operator(&select_plan)?, &select_plan);
+        let metadata = rows.metadata().clone();
+        let records = rows.collect::<Result<Vec<Record>, DBError>>()?;
+        Ok(OkDBResult::SelectResult(ResultSet::new(records, metadata)))
+    }
+
+    // rows read lazily through a snapshot, which is kept after the statement ends
+    pub fn query(&mut self) -> Result<Rows<'static>, DBError> {
+        match &self.plan {
+            Plan::SelectPlan(select_plan) => {
+                let select_plan = select_plan.clone();
+                Ok(Rows::new(self.build_operator(&select_plan)?, &select_plan))
+            }
+            _ => Err(DBError::InvalidStatement(
+                "only SELECT returns rows".to_string(),
+            )),
+        }
+    }
+
+    fn build_operator(&mut self, select_plan: &SelectPlan) -> Result<Box<dyn Operator>, DBError> {
+        self.build_node(&select_plan.database, &select_plan.root)
+    }
+
+    fn build_node(
+        &mut self,
+        database: &Database,
+        node: &PlanNode,
+    ) -> Result<Box<dyn Operator>, DBError> {
+        // pushed before the inputs are built, to follow the order of EXPLAIN
+        let stats = self.analyzed.as_mut().map(|analyzed| {
+            let stats = Rc::new(OperatorStats::default());
+            analyzed.push(stats.clone());
+            stats
+        });
+        // an index scan looks its rows up here
+        let start = Instant::now();
+        let operator: Box<dyn Operator> = match node {
+            PlanNode::Scan { table } => {
+                let namespace = Namespace::table(&database.name, &table.name);
+                let cursor = self.storage.cursor(&namespace)?;
+                Box::new(operator::Scan::new(table.clone(), cursor))
+            }
+            PlanNode::IndexScan {
+                table,
+                index,
+                value,
+            } => Box::new(operator::IndexScan::new(
+                self.index_lookup(database, table, index, value)?,
+            )),
+            PlanNode::Filter { input, filters } => Box::new(operator::Filter::new(
+                self.build_node(database, input)?,
+                filters.clone(),
+            )),
+            PlanNode::Project { input, projections } => Box::new(operator::Project::new(
+                self.build_node(database, input)?,
+                projections.clone(),
+            )),
+            PlanNode::Sort { input, keys } => Box::new(operator::Sort::new(
+                self.build_node(database, input)?,
+                keys.clone(),
+            )),
+            PlanNode::Limit {
+                input,
+                limit,
+                offset,
+            } => Box::new(operator::Limit::new(
+                self.build_node(database, input)?,
+                *limit,
+                *offset,
+            )),
+            PlanNode::Aggregate {
+                input,
+                group_by,
+                aggregates,
+            } => Box::new(operator::Aggregate::new(
+                self.build_node(database, input)?,
+                group_by.clone(),
+                aggregates.clone(),
+            )),
+            PlanNode::Join { left, right, on } => Box::new(operator::Join::new(
+                self.build_node(database, left)?,
+                self.build_node(database, right)?,
+                on.clone(),
+            )),
+        };
+        match stats {
+            Some(stats) => {
+                stats.add_elapsed(start.elapsed());
+                Ok(Box::new(operator::Instrument::new(operator, stats)))
+            }
+            None => Ok(operator),
+        }
+    }
+
+    // the row whose indexed column holds the value, if any.
+    // a value of another type than the column's never matches, as in a filter
+    fn index_lookup(
+        &mut self,
+        database: &Database,
+        table: &Table,
+        index: &IndexPlan,
+        value: &parser::Value,
+    ) -> Result<Vec<operator::Row>, DBError> {
+        let column_type = table
+            .column(index.column_name())
+            .map(|column| column.column_type)
+            .ok_or(DBError::UnknownColumn(format!(
+                "{}.{}",
+                table.name,
+                index.column_name()
+            )))?;
+        let key = match (column_type, value) {
+            (ColumnType::Integer, parser::Value::Number(n)) => match n.parse::<i64>() {
+                Ok(i) => i.to_string(),
+                Err(_) => return Ok(vec![]),
+            },
+            (ColumnType::Varchar, parser::Value::QuotedString(s)) => s.to_owned(),
+            _ => return Ok(vec![]),
+        };
+        let table_namespace = Namespace::table(&database.name, &table.name);
+        let id = match index {
+            IndexPlan::PrimaryKey => key,
+            IndexPlan::Unique(unique) => {
+                let namespace = Namespace::index(&database.name, &table.name, &unique.name);
+                match self
+                    .storage
+                    .get(&namespace, &Self::index_key(&vec![&key])?)?
+                {
+                    Some(id) => String::from_utf8(id).map_err(|_| {
+                        DBError::Corruption(format!("unexpected id in index {}", unique.name))
+                    })?,
+                    None => return Ok(vec![]),
+                }
+            }
+        };
+        match self.storage.get(&table_namespace, &id)? {
+            Some(row) => Ok(vec![operator::Scan::decode(
+                table,
+                &id,
+                row.into_boxed_slice(),
+            )?]),
+            None => Ok(vec![]),
+        }
     }
 
     fn execute_create_database(&mut self, create_database: CreateDatabasePlan) -> DBResult {
@@ -157,22 +305,113 @@ impl<'a> Executor<'a> {
     }
 
     fn execute_create_table(&mut self, create_table: CreateTablePlan) -> DBResult {
+        let mut created_cf_names = vec![];
+        let err = match self.create_table(create_table, &mut created_cf_names) {
+            Ok(result) => return Ok(result),
+            Err(err) => err,
+        };
+        // column families can't be part of the statement's WriteBatch, so drop them by hand.
+        // those left are dropped on the next startup
+        let drop_errors: Vec<String> = created_cf_names
+            .into_iter()
+            .filter_map(|cf_name| {
+                self.storage
+                    .drop_column_family(&cf_name)
+                    .err()
+                    .map(|drop_err| format!("ColumnFamily({}): {}", cf_name, drop_err))
+            })
+            .collect();
+        if drop_errors.is_empty() {
+            Err(err)
+        } else {
+            Err(DBError::Storage(format!(
+                "{}, and failed to drop {}",
+                err,
+                drop_errors.join(", ")
+            )))
+        }
+    }
+
+    fn create_table(
+        &mut self,
+        create_table: CreateTablePlan,
+        created_cf_names: &mut Vec<String>,
+    ) -> DBResult {
         let database_name = create_table.database_name;
 
         // create a dedicated column family
-        let cf_name = format!("{}_{}", database_name, create_table.table_name);
-        self.storage.create_column_family(cf_name.as_ref())?;
+        let cf_name = Namespace::table(&database_name, &create_table.table_name).cf_name();
+        self.create_column_family(&cf_name)?;
+        created_cf_names.push(cf_name);
+
+        // and one for each unique index
+        for unique in &create_table.unique_constraints {
+            let namespace =
+                Namespace::index(&database_name, &create_table.table_name, &unique.name);
+            self.create_column_family(&namespace.cf_name())?;
+            created_cf_names.push(namespace.cf_name());
+        }
 
         // store the schema
+        let table_name = create_table.table_name;
         let columns: Vec<Column> = create_table
             .column_definitions
             .into_iter()
-            .map(|column| Column::new(column.name, ColumnType::from(column.column_type)))
-            .collect();
-        let table = Table::new(create_table.table_name.to_string(), columns);
+            .map(|column| Self::build_column(&table_name, column))
+            .collect::<Result<Vec<Column>, DBError>>()?;
+        let mut table = Table::new(table_name.to_string(), columns);
+        table.unique_constraints = create_table.unique_constraints;
+        table.foreign_keys = create_table.foreign_keys;
         let mut store = SchemaStore::new(&mut self.storage);
         store
-            .create_table(database_name.as_ref(), table)
+            .create_table(database_name.as_ref(), table, create_table.sequences)
+            .map(|_| OkDBResult::ExecutionResult)
+    }
+
+    // the table isn't in the schema, so an existing column family is a leftover of a crashed
+    // CREATE TABLE, which is dropped on the next startup. it may hold data, so it's kept till then
+    fn create_column_family(&mut self, cf_name: &str) -> Result<(), DBError> {
+        if self.storage.has_column_family(cf_name) {
+            return Err(DBError::AlreadyExists(format!("ColumnFamily({})", cf_name)));
+        }
+        self.storage.create_column_family(cf_name)
+    }
+
+    fn build_column(
+        table_name: &str,
+        column_definition: ColumnDefinition,
+    ) -> Result<Column, DBError> {
+        let mut column = Column::new(
+            column_definition.name.clone(),
+            ColumnType::try_from(column_definition.column_type.clone())?,
+        );
+        if column_definition.is_auto_increment() {
+            column.sequence = Some(Sequence::for_column(table_name, &column.name).name);
+        }
+        column_definition
+            .constraints
+            .into_iter()
+            .for_each(|constraint| match constraint {
+                ColumnConstraint::NotNull => column.not_null = true,
+                // collected into the table's unique and foreign key constraints by the planner
+                ColumnConstraint::Unique | ColumnConstraint::References(_) => {}
+                ColumnConstraint::AutoIncrement => {}
+                ColumnConstraint::Default(value) => column.default = Some(value),
+                // multiple CHECKs on a column must all hold
+                ColumnConstraint::Check(expr) => {
+                    column.check = Some(match column.check.take() {
+                        Some(check) => BinaryOperator::And.build(check, expr),
+                        None => expr,
+                    })
+                }
+            });
+        Ok(column)
+    }
+
+    fn execute_create_sequence(&mut self, create_sequence: CreateSequencePlan) -> DBResult {
+        let mut store = SchemaStore::new(&mut self.storage);
+        store
+            .create_sequence(&create_sequence.database_name, create_sequence.sequence)
             .map(|_| OkDBResult::ExecutionResult)
     }
 
@@ -181,32 +420,205 @@ impl<'a> Executor<'a> {
             database,
             table,
             values,
+            generated_keys,
         } = insert_plan;
 
         let namespace = &Namespace::table(&database.name, &table.name);
         let id = values
             .iter()
             .find(|value| value.column.name == Column::ID)
-            .expect(&format!(
-                "value for 'id' column is missing. table: {:?}, values: {:?}",
-                table, values
-            ));
+            .and_then(|id| id.value.to_string_opt());
 
-        match id.value.to_string_opt() {
+        match id {
             Some(ref id) => {
                 let mut map = HashMap::with_capacity(values.len());
+                // NULL is stored as an absent column
                 values.into_iter().for_each(|v| {
-                    map.insert(v.column.name, v.value.to_string());
+                    if let Some(value) = v.value.to_string_opt() {
+                        map.insert(v.column.name, value);
+                    }
                 });
-                let serialized = serde_json::to_string(&map)
-                    .map_err(|err| DBError::new(format!("failed to serialize. err: {:?}", err)))?;
+                self.check_foreign_keys(&database, &table, &map)?;
+                self.update_unique_indexes(&database, &table, id, &map)?;
+                let serialized = serde_json::to_string(&map).map_err(|err| {
+                    DBError::Internal(format!("failed to serialize. err: {:?}", err))
+                })?;
                 self.storage.put(namespace, id, serialized.into_bytes())?;
-                Ok(OkDBResult::ExecutionResult)
+                Ok(OkDBResult::InsertResult(Self::build_generated_keys(
+                    generated_keys,
+                )))
             }
-            None => Err(DBError::new(format!(
+            None => Err(DBError::InvalidStatement(format!(
                 "id not found in the given INSERT INTO statement. table: {:?}, values: {:?}",
                 table, values
             ))),
         }
     }
+
+    // a single record of the generated values, or no record when nothing was generated
+    fn build_generated_keys(generated_keys: Vec<RecordValue>) -> ResultSet {
+        let field_metadatas = (&generated_keys)
+            .into_iter()
+            .map(|key| FieldMetadata::new(&key.column.name, &key.column.column_type.to_string()))
+            .collect();
+        let records = if generated_keys.is_empty() {
+            vec![]
+        } else {
+            let values = generated_keys
+                .into_iter()
+                .map(|key| match key.value.to_string().parse::<i64>() {
+                    Ok(i) => FieldValue::Int(i),
+                    Err(_) => FieldValue::Text(key.value.to_string()),
+                })
+                .collect();
+            vec![Record::new(values)]
+        };
+        ResultSet::new(records, ResultMetadata::new(field_metadatas))
+    }
+
+    // every non-NULL referencing value must exist in the referenced table
+    fn check_foreign_keys(
+        &mut self,
+        database: &Database,
+        table: &Table,
+        row: &HashMap<String, String>,
+    ) -> Result<(), DBError> {
+        for foreign_key in &table.foreign_keys {
+            let value = match row.get(&foreign_key.column) {
+                Some(value) => value,
+                None => continue,
+            };
+            // a row may reference itself
+            if foreign_key.referenced_table == table.name
+                && row.get(&foreign_key.referenced_column) == Some(value)
+            {
+                continue;
+            }
+            let found = if foreign_key.referenced_column == Column::ID {
+                let namespace = Namespace::table(&database.name, &foreign_key.referenced_table);
+                self.storage.get(&namespace, value)?
+            } else {
+                let referenced_table = database
+                    .table(&foreign_key.referenced_table)
+                    .ok_or(DBError::UnknownTable(foreign_key.referenced_table.clone()))?;
+                let unique = (&referenced_table.unique_constraints)
+                    .into_iter()
+                    .find(|unique| unique.columns == vec![foreign_key.referenced_column.clone()])
+                    .ok_or(DBError::Corruption(format!(
+                        "no unique index on {}({}) for constraint {}",
+                        foreign_key.referenced_table,
+                        foreign_key.referenced_column,
+                        foreign_key.name
+                    )))?;
+                let namespace =
+                    Namespace::index(&database.name, &referenced_table.name, &unique.name);
+                self.storage
+                    .get(&namespace, &Self::index_key(&vec![value])?)?
+            };
+            if found.is_none() {
+                return Err(DBError::constraint_violation(
+                    &foreign_key.name,
+                    format!(
+                        "key ({})=({}) is not present in table {}",
+                        foreign_key.column, value, foreign_key.referenced_table
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // verify every unique index is free for the row, then point the indexes at it.
+    // inserting an existing id replaces that row, so its previous entries are removed first.
+    fn update_unique_indexes(
+        &mut self,
+        database: &Database,
+        table: &Table,
+        id: &str,
+        row: &HashMap<String, String>,
+    ) -> Result<(), DBError> {
+        if table.unique_constraints.is_empty() {
+            return Ok(());
+        }
+        let table_namespace = Namespace::table(&database.name, &table.name);
+        let previous_row: Option<HashMap<String, String>> =
+            self.storage.get_serialized(&table_namespace, id)?;
+
+        for unique in &table.unique_constraints {
+            let namespace = Namespace::index(&database.name, &table.name, &unique.name);
+            if let Some(key) = Self::unique_index_key(unique, row)? {
+                match self.storage.get(&namespace, &key)? {
+                    Some(owner) if owner != id.as_bytes() => {
+                        return Err(DBError::constraint_violation(
+                            &unique.name,
+                            format!("duplicate value for ({})", unique.columns.join(", ")),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for unique in &table.unique_constraints {
+            let namespace = Namespace::index(&database.name, &table.name, &unique.name);
+            if let Some(previous_row) = &previous_row {
+                if let Some(key) = Self::unique_index_key(unique, previous_row)? {
+                    self.storage.delete(&namespace, &key)?;
+                }
+            }
+            if let Some(key) = Self::unique_index_key(unique, row)? {
+                self.storage.put(&namespace, &key, id.as_bytes().to_vec())?;
+            }
+        }
+        Ok(())
+    }
+
+    // None when any of the columns is NULL, as NULLs never conflict with each other
+    fn unique_index_key(
+        unique: &UniqueConstraint,
+        row: &HashMap<String, String>,
+    ) -> Result<Option<String>, DBError> {
+        let values: Option<Vec<&String>> = (&unique.columns)
+            .into_iter()
+            .map(|column_name| row.get(column_name))
+            .collect();
+        match values {
+            Some(values) => Self::index_key(&values).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn index_key(values: &Vec<&String>) -> Result<String, DBError> {
+        serde_json::to_string(values)
+            .map_err(|err| DBError::Internal(format!("failed to serialize. err: {:?}", err)))
+    }
+}
+
+// rows of a SELECT, pulled from the root operator of its plan one by one as they are iterated
+pub struct Rows<'a> {
+    operator: Box<dyn Operator + 'a>,
+    metadata: Arc<ResultMetadata>,
+}
+
+impl<'a> Rows<'a> {
+    pub(crate) fn new(operator: Box<dyn Operator + 'a>, select_plan: &SelectPlan) -> Self {
+        Self {
+            operator,
+            metadata: Arc::new(ResultMetadata::new(select_plan.result_metadata())),
+        }
+    }
+
+    pub fn metadata(&self) -> &ResultMetadata {
+        &self.metadata
+    }
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Result<Record, DBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.operator
+            .next()
+            .map(|row| row.map(|row| row.into_record().with_metadata(&self.metadata)))
+            .transpose()
+    }
 }
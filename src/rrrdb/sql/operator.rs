@@ -0,0 +1,769 @@
+use std::{
+    cell::Cell,
+    cmp::Ordering,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+    vec::IntoIter,
+};
+
+use crate::rrrdb::{
+    parser::Value,
+    schema::{ColumnType, Table},
+    storage::Cursor,
+    DBError, FieldValue, Record,
+};
+
+use super::planner::{
+    self, AggregateFunction, AggregatePlan, ColumnRef, JsonExtractPlan, ProjectionPlan, SortKey,
+};
+
+// values named by their table and column, in the order of the operator's output
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub(crate) struct Row {
+    fields: Vec<(ColumnRef, FieldValue)>,
+}
+
+impl Row {
+    pub fn new(fields: Vec<(ColumnRef, FieldValue)>) -> Self {
+        Self { fields }
+    }
+
+    // NULL for a column the row doesn't have
+    pub fn get(&self, table_name: &str, column_name: &str) -> &FieldValue {
+        self.fields
+            .iter()
+            .find(|(column, _)| {
+                column.table_name == table_name && column.column_name == column_name
+            })
+            .map(|(_, value)| value)
+            .unwrap_or(&FieldValue::Null)
+    }
+
+    fn get_ref(&self, column: &ColumnRef) -> &FieldValue {
+        self.get(&column.table_name, &column.column_name)
+    }
+
+    pub fn into_record(self) -> Record {
+        Record::new(self.fields.into_iter().map(|(_, value)| value).collect())
+    }
+}
+
+// a physical operator of the Volcano model, pulling rows from its inputs one at a time
+pub(crate) trait Operator {
+    // None when there are no more rows
+    fn next(&mut self) -> Result<Option<Row>, DBError>;
+}
+
+// every row of a table, decoded in the order of its columns
+pub(crate) struct Scan {
+    table: Table,
+    records: Cursor,
+}
+
+impl Scan {
+    pub fn new(table: Table, records: Cursor) -> Self {
+        Self { table, records }
+    }
+
+    pub fn decode(table: &Table, key: &str, value_bytes: Box<[u8]>) -> Result<Row, DBError> {
+        let json = String::from_utf8(value_bytes.into_vec())
+            .map_err(|err| err.to_string())
+            .and_then(|j| {
+                serde_json::from_str::<serde_json::Value>(&j).map_err(|err| err.to_string())
+            })
+            .map_err(|err| {
+                DBError::Corruption(format!(
+                    "unexpected formatted row for key({:?}). err = {}",
+                    key, err
+                ))
+            })?;
+        let mut values = Self::parse_single_row(table, json)?;
+        // NULL is stored as an absent column
+        let fields = (&table.columns)
+            .into_iter()
+            .map(|column| {
+                let value = values.remove(&column.name).unwrap_or(FieldValue::Null);
+                (ColumnRef::new(&table.name, &column.name), value)
+            })
+            .collect();
+        Ok(Row::new(fields))
+    }
+
+    fn parse_single_row(
+        table: &Table,
+        json: serde_json::Value,
+    ) -> Result<HashMap<String, FieldValue>, DBError> {
+        let corrupted = |detail: String| {
+            DBError::Corruption(format!(
+                "unexpected row in table {}. {}",
+                table.name, detail
+            ))
+        };
+        let row = json
+            .as_object()
+            .ok_or_else(|| corrupted(format!("not an object: {}", json)))?;
+        let mut map = HashMap::new();
+        for (column_name, column_value) in row {
+            let s = column_value
+                .as_str()
+                .ok_or_else(|| corrupted(format!("not a string: {}", column_value)))?
+                .to_owned();
+            let column = table
+                .column(column_name)
+                .ok_or_else(|| corrupted(format!("unknown column {}", column_name)))?;
+            let value = match column.column_type {
+                ColumnType::Integer => FieldValue::Int(
+                    s.parse::<i64>()
+                        .map_err(|_| corrupted(format!("not an integer: {}", s)))?,
+                ),
+                ColumnType::Varchar => FieldValue::Text(s),
+                ColumnType::Json => FieldValue::Json(s),
+            };
+            map.insert(column_name.to_owned(), value);
+        }
+        Ok(map)
+    }
+}
+
+impl Operator for Scan {
+    fn next(&mut self) -> Result<Option<Row>, DBError> {
+        match self.records.next() {
+            Some((key, value)) => Self::decode(&self.table, &key, value).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// rows found through the primary key or a unique index, which are looked up beforehand
+pub(crate) struct IndexScan {
+    rows: IntoIter<Row>,
+}
+
+impl IndexScan {
+    pub fn new(rows: Vec<Row>) -> Self {
+        Self {
+            rows: rows.into_iter(),
+        }
+    }
+}
+
+impl Operator for IndexScan {
+    fn next(&mut self) -> Result<Option<Row>, DBError> {
+        Ok(self.rows.next())
+    }
+}
+
+// rows satisfying every filter
+pub(crate) struct Filter {
+    input: Box<dyn Operator>,
+    filters: Vec<planner::Filter>,
+}
+
+impl Filter {
+    pub fn new(input: Box<dyn Operator>, filters: Vec<planner::Filter>) -> Self {
+        Self { input, filters }
+    }
+
+    fn is_satisfied(filter: &planner::Filter, row: &Row) -> bool {
+        let value = row.get(&filter.table_name, &filter.column_name);
+        let extracted = filter
+            .json_extract
+            .as_ref()
+            .map(|json_extract| extract_json(value, json_extract));
+        let value = extracted.as_ref().unwrap_or(value);
+        match (value, &filter.expected_value) {
+            // comparison with NULL is never true
+            (FieldValue::Null, _) => false,
+            // a number which isn't an integer never equals
+            (FieldValue::Int(i), Value::Number(n)) => n.parse::<i64>().map_or(false, |n| &n == i),
+            (FieldValue::Text(t), Value::QuotedString(s)) => t == s,
+            // values extracted from a JSON document are compared by their text
+            (FieldValue::Text(t), expected) if filter.json_extract.is_some() => {
+                t == &expected.to_string()
+            }
+            (FieldValue::Json(doc), expected) if filter.json_extract.is_some() => {
+                match serde_json::from_str::<serde_json::Value>(doc) {
+                    Ok(json) => json_to_text(&json) == expected.to_string(),
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Operator for Filter {
+    fn next(&mut self) -> Result<Option<Row>, DBError> {
+        while let Some(row) = self.input.next()? {
+            if (&self.filters)
+                .into_iter()
+                .all(|filter| Self::is_satisfied(filter, &row))
+            {
+                return Ok(Some(row));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// the columns, and the values extracted from JSON columns, which are selected
+pub(crate) struct Project {
+    input: Box<dyn Operator>,
+    projections: Vec<ProjectionPlan>,
+}
+
+impl Project {
+    pub fn new(input: Box<dyn Operator>, projections: Vec<ProjectionPlan>) -> Self {
+        Self { input, projections }
+    }
+}
+
+impl Operator for Project {
+    fn next(&mut self) -> Result<Option<Row>, DBError> {
+        let row = match self.input.next()? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let fields = (&self.projections)
+            .into_iter()
+            .map(|projection| {
+                let value = row.get(&projection.table.name, &projection.column.name);
+                let value = match &projection.json_extract {
+                    Some(json_extract) => extract_json(value, json_extract),
+                    None => value.to_owned(),
+                };
+                let name = projection.field_metadata().field_name;
+                (ColumnRef::new(&projection.table.name, &name), value)
+            })
+            .collect();
+        Ok(Some(Row::new(fields)))
+    }
+}
+
+// reads every row of the input on the first call
+pub(crate) struct Sort {
+    input: Box<dyn Operator>,
+    keys: Vec<SortKey>,
+    sorted: Option<IntoIter<Row>>,
+}
+
+impl Sort {
+    pub fn new(input: Box<dyn Operator>, keys: Vec<SortKey>) -> Self {
+        Self {
+            input,
+            keys,
+            sorted: None,
+        }
+    }
+
+    fn compare(keys: &Vec<SortKey>, a: &Row, b: &Row) -> Ordering {
+        for key in keys {
+            let ordering = compare_values(a.get_ref(&key.column), b.get_ref(&key.column));
+            let ordering = if key.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl Operator for Sort {
+    fn next(&mut self) -> Result<Option<Row>, DBError> {
+        if self.sorted.is_none() {
+            let mut rows = drain(self.input.as_mut())?;
+            let keys = &self.keys;
+            // stable, so that rows with equal keys keep the order of the input
+            rows.sort_by(|a, b| Self::compare(keys, a, b));
+            self.sorted = Some(rows.into_iter());
+        }
+        Ok(self.sorted.as_mut().and_then(|rows| rows.next()))
+    }
+}
+
+// stops pulling rows from the input once the limit is reached
+pub(crate) struct Limit {
+    input: Box<dyn Operator>,
+    limit: Option<usize>,
+    offset: usize,
+    returned: usize,
+}
+
+impl Limit {
+    pub fn new(input: Box<dyn Operator>, limit: Option<usize>, offset: usize) -> Self {
+        Self {
+            input,
+            limit,
+            offset,
+            returned: 0,
+        }
+    }
+}
+
+impl Operator for Limit {
+    fn next(&mut self) -> Result<Option<Row>, DBError> {
+        if self.limit.map_or(false, |limit| self.returned >= limit) {
+            return Ok(None);
+        }
+        while self.offset > 0 {
+            if self.input.next()?.is_none() {
+                return Ok(None);
+            }
+            self.offset -= 1;
+        }
+        let row = self.input.next()?;
+        if row.is_some() {
+            self.returned += 1;
+        }
+        Ok(row)
+    }
+}
+
+// a row per group, with the group's columns followed by the aggregates.
+// without GROUP BY, a single row is returned even for no input
+pub(crate) struct Aggregate {
+    input: Box<dyn Operator>,
+    group_by: Vec<ColumnRef>,
+    aggregates: Vec<AggregatePlan>,
+    output: Option<IntoIter<Row>>,
+}
+
+impl Aggregate {
+    pub fn new(
+        input: Box<dyn Operator>,
+        group_by: Vec<ColumnRef>,
+        aggregates: Vec<AggregatePlan>,
+    ) -> Self {
+        Self {
+            input,
+            group_by,
+            aggregates,
+            output: None,
+        }
+    }
+
+    fn aggregate(&mut self) -> Result<Vec<Row>, DBError> {
+        // groups in the order they first appear
+        let mut groups: Vec<(Vec<FieldValue>, Vec<FieldValue>)> = vec![];
+        let mut indexes: HashMap<Vec<FieldValue>, usize> = HashMap::new();
+        if self.group_by.is_empty() {
+            groups.push((vec![], self.initial_states()));
+            indexes.insert(vec![], 0);
+        }
+        while let Some(row) = self.input.next()? {
+            let group: Vec<FieldValue> = (&self.group_by)
+                .into_iter()
+                .map(|column| row.get_ref(column).to_owned())
+                .collect();
+            let index = match indexes.get(&group) {
+                Some(index) => *index,
+                None => {
+                    groups.push((group.clone(), self.initial_states()));
+                    indexes.insert(group, groups.len() - 1);
+                    groups.len() - 1
+                }
+            };
+            let states = &mut groups[index].1;
+            for (aggregate, state) in self.aggregates.iter().zip(states.iter_mut()) {
+                let value = match &aggregate.column {
+                    Some(column) => row.get_ref(column).to_owned(),
+                    // COUNT(*)
+                    None => FieldValue::Int(1),
+                };
+                *state = Self::accumulate(aggregate, state, value)?;
+            }
+        }
+        let rows = groups
+            .into_iter()
+            .map(|(group, states)| {
+                let group = self.group_by.iter().cloned().zip(group);
+                let aggregates = (&self.aggregates)
+                    .into_iter()
+                    .map(|aggregate| ColumnRef::new("", &aggregate.name))
+                    .zip(states);
+                Row::new(group.chain(aggregates).collect())
+            })
+            .collect();
+        Ok(rows)
+    }
+
+    fn initial_states(&self) -> Vec<FieldValue> {
+        (&self.aggregates)
+            .into_iter()
+            .map(|aggregate| match aggregate.function {
+                AggregateFunction::Count => FieldValue::Int(0),
+                _ => FieldValue::Null,
+            })
+            .collect()
+    }
+
+    // NULLs are ignored, as in SQL
+    fn accumulate(
+        aggregate: &AggregatePlan,
+        state: &FieldValue,
+        value: FieldValue,
+    ) -> Result<FieldValue, DBError> {
+        if value == FieldValue::Null {
+            return Ok(state.to_owned());
+        }
+        let accumulated = match (&aggregate.function, state) {
+            (AggregateFunction::Count, FieldValue::Int(count)) => FieldValue::Int(count + 1),
+            (_, FieldValue::Null) => value,
+            (AggregateFunction::Sum, FieldValue::Int(sum)) => match value {
+                FieldValue::Int(i) => FieldValue::Int(sum + i),
+                other => {
+                    return Err(DBError::InvalidStatement(format!(
+                        "{} expects integers, but got {:?}",
+                        aggregate.name, other
+                    )))
+                }
+            },
+            (AggregateFunction::Min, _) if compare_values(&value, state) == Ordering::Less => value,
+            (AggregateFunction::Max, _) if compare_values(&value, state) == Ordering::Greater => {
+                value
+            }
+            (_, state) => state.to_owned(),
+        };
+        Ok(accumulated)
+    }
+}
+
+impl Operator for Aggregate {
+    fn next(&mut self) -> Result<Option<Row>, DBError> {
+        if self.output.is_none() {
+            self.output = Some(self.aggregate()?.into_iter());
+        }
+        Ok(self.output.as_mut().and_then(|rows| rows.next()))
+    }
+}
+
+// nested loop join on the equality of a column of each side.
+// the right side is read once, on the first call
+pub(crate) struct Join {
+    left: Box<dyn Operator>,
+    right: Box<dyn Operator>,
+    on: (ColumnRef, ColumnRef),
+    right_rows: Option<Vec<Row>>,
+    // the left row being joined, and the index of the right row to try next
+    current: Option<(Row, usize)>,
+}
+
+impl Join {
+    pub fn new(
+        left: Box<dyn Operator>,
+        right: Box<dyn Operator>,
+        on: (ColumnRef, ColumnRef),
+    ) -> Self {
+        Self {
+            left,
+            right,
+            on,
+            right_rows: None,
+            current: None,
+        }
+    }
+}
+
+impl Operator for Join {
+    fn next(&mut self) -> Result<Option<Row>, DBError> {
+        if self.right_rows.is_none() {
+            self.right_rows = Some(drain(self.right.as_mut())?);
+        }
+        let right_rows = self.right_rows.as_ref().map_or(&[][..], |rows| &rows[..]);
+        loop {
+            let (left_row, index) = match self.current.as_mut() {
+                Some(current) => current,
+                None => match self.left.next()? {
+                    Some(row) => self.current.get_or_insert((row, 0)),
+                    None => return Ok(None),
+                },
+            };
+            let left_value = left_row.get_ref(&self.on.0);
+            while let Some(right_row) = right_rows.get(*index) {
+                *index += 1;
+                let right_value = right_row.get_ref(&self.on.1);
+                // NULL never equals
+                if left_value != &FieldValue::Null && left_value == right_value {
+                    let mut fields = left_row.fields.clone();
+                    fields.extend(right_row.fields.iter().cloned());
+                    return Ok(Some(Row::new(fields)));
+                }
+            }
+            self.current = None;
+        }
+    }
+}
+
+// rows returned by an operator and the time spent in it, including its inputs
+#[derive(Debug, Default)]
+pub(crate) struct OperatorStats {
+    pub(crate) rows: Cell<usize>,
+    pub(crate) elapsed: Cell<Duration>,
+}
+
+impl OperatorStats {
+    pub fn add_elapsed(&self, elapsed: Duration) {
+        self.elapsed.set(self.elapsed.get() + elapsed);
+    }
+}
+
+// measures another operator for EXPLAIN ANALYZE
+pub(crate) struct Instrument {
+    input: Box<dyn Operator>,
+    stats: Rc<OperatorStats>,
+}
+
+impl Instrument {
+    pub fn new(input: Box<dyn Operator>, stats: Rc<OperatorStats>) -> Self {
+        Self { input, stats }
+    }
+}
+
+impl Operator for Instrument {
+    fn next(&mut self) -> Result<Option<Row>, DBError> {
+        let start = Instant::now();
+        let row = self.input.next();
+        self.stats.add_elapsed(start.elapsed());
+        if let Ok(Some(_)) = row {
+            self.stats.rows.set(self.stats.rows.get() + 1);
+        }
+        row
+    }
+}
+
+pub(crate) fn drain(operator: &mut dyn Operator) -> Result<Vec<Row>, DBError> {
+    let mut rows = vec![];
+    while let Some(row) = operator.next()? {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+// NULLs come first, and values of different types are ordered by their type
+fn compare_values(a: &FieldValue, b: &FieldValue) -> Ordering {
+    let rank = |value: &FieldValue| match value {
+        FieldValue::Null => 0,
+        FieldValue::Int(_) => 1,
+        FieldValue::Text(_) => 2,
+        FieldValue::Json(_) => 3,
+        FieldValue::Bytes(_) => 4,
+    };
+    match (a, b) {
+        (FieldValue::Int(a), FieldValue::Int(b)) => a.cmp(b),
+        (FieldValue::Text(a), FieldValue::Text(b)) | (FieldValue::Json(a), FieldValue::Json(b)) => {
+            a.cmp(b)
+        }
+        (FieldValue::Bytes(a), FieldValue::Bytes(b)) => a.cmp(b),
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
+
+// evaluates `->`/`->>`, which yields NULL for a missing path or a non-JSON value
+fn extract_json(value: &FieldValue, json_extract: &JsonExtractPlan) -> FieldValue {
+    let document = match value {
+        FieldValue::Json(doc) => match serde_json::from_str::<serde_json::Value>(doc) {
+            Ok(document) => document,
+            Err(_) => return FieldValue::Null,
+        },
+        _ => return FieldValue::Null,
+    };
+    match json_extract.path.extract(&document) {
+        None => FieldValue::Null,
+        Some(serde_json::Value::Null) if json_extract.as_text => FieldValue::Null,
+        Some(found) if json_extract.as_text => FieldValue::Text(json_to_text(found)),
+        Some(found) => FieldValue::Json(found.to_string()),
+    }
+}
+
+fn json_to_text(json: &serde_json::Value) -> String {
+    match json {
+        serde_json::Value::String(s) => s.to_owned(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    struct Values(VecDeque<Row>);
+
+    impl Operator for Values {
+        fn next(&mut self) -> Result<Option<Row>, DBError> {
+            Ok(self.0.pop_front())
+        }
+    }
+
+    fn users() -> Box<dyn Operator> {
+        let row = |id: i64, name: &str, team: Option<i64>| {
+            Row::new(vec![
+                (ColumnRef::new("users", "id"), FieldValue::Int(id)),
+                (
+                    ColumnRef::new("users", "name"),
+                    FieldValue::Text(name.to_string()),
+                ),
+                (ColumnRef::new("users", "team_id"), team.into()),
+            ])
+        };
+        Box::new(Values(
+            vec![
+                row(1, "Alice", Some(10)),
+                row(2, "Bob", Some(20)),
+                row(3, "Carol", Some(10)),
+                row(4, "Dave", None),
+            ]
+            .into(),
+        ))
+    }
+
+    fn collect(mut operator: impl Operator) -> Vec<Record> {
+        drain(&mut operator)
+            .unwrap()
+            .into_iter()
+            .map(|row| row.into_record())
+            .collect()
+    }
+
+    fn ids(operator: impl Operator) -> Vec<FieldValue> {
+        collect(operator)
+            .into_iter()
+            .map(|record| record.get(0).unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn sort_and_limit() {
+        let team_id = ColumnRef::new("users", "team_id");
+        let id = ColumnRef::new("users", "id");
+        let sort = Sort::new(
+            users(),
+            vec![SortKey::new(team_id, true), SortKey::new(id.clone(), false)],
+        );
+        assert_eq!(
+            ids(sort),
+            vec![
+                FieldValue::Int(2),
+                FieldValue::Int(1),
+                FieldValue::Int(3),
+                FieldValue::Int(4)
+            ]
+        );
+        let sort = Sort::new(users(), vec![SortKey::new(id, true)]);
+        let limit = Limit::new(Box::new(sort), Some(2), 1);
+        assert_eq!(ids(limit), vec![FieldValue::Int(3), FieldValue::Int(2)]);
+        assert_eq!(ids(Limit::new(users(), None, 3)), vec![FieldValue::Int(4)]);
+        assert_eq!(ids(Limit::new(users(), Some(0), 0)), vec![]);
+    }
+
+    #[test]
+    fn aggregate() {
+        let team_id = ColumnRef::new("users", "team_id");
+        let aggregates = vec![
+            AggregatePlan::new(AggregateFunction::Count, None, "count(*)"),
+            AggregatePlan::new(
+                AggregateFunction::Max,
+                Some(ColumnRef::new("users", "name")),
+                "max(name)",
+            ),
+            AggregatePlan::new(
+                AggregateFunction::Sum,
+                Some(ColumnRef::new("users", "id")),
+                "sum(id)",
+            ),
+        ];
+        let aggregate = Aggregate::new(users(), vec![team_id], aggregates.clone());
+        assert_eq!(
+            collect(aggregate),
+            vec![
+                Record::new(vec![
+                    FieldValue::Int(10),
+                    FieldValue::Int(2),
+                    FieldValue::Text("Carol".to_string()),
+                    FieldValue::Int(4),
+                ]),
+                Record::new(vec![
+                    FieldValue::Int(20),
+                    FieldValue::Int(1),
+                    FieldValue::Text("Bob".to_string()),
+                    FieldValue::Int(2),
+                ]),
+                Record::new(vec![
+                    FieldValue::Null,
+                    FieldValue::Int(1),
+                    FieldValue::Text("Dave".to_string()),
+                    FieldValue::Int(4),
+                ]),
+            ]
+        );
+
+        // a row even for no input
+        let empty = Limit::new(users(), Some(0), 0);
+        let aggregate = Aggregate::new(Box::new(empty), vec![], aggregates);
+        assert_eq!(
+            collect(aggregate),
+            vec![Record::new(vec![
+                FieldValue::Int(0),
+                FieldValue::Null,
+                FieldValue::Null
+            ])]
+        );
+    }
+
+    #[test]
+    fn join_and_filter() {
+        let teams = Box::new(Values(
+            vec![
+                Row::new(vec![
+                    (ColumnRef::new("teams", "id"), FieldValue::Int(10)),
+                    (
+                        ColumnRef::new("teams", "name"),
+                        FieldValue::Text("red".to_string()),
+                    ),
+                ]),
+                Row::new(vec![
+                    (ColumnRef::new("teams", "id"), FieldValue::Int(20)),
+                    (
+                        ColumnRef::new("teams", "name"),
+                        FieldValue::Text("blue".to_string()),
+                    ),
+                ]),
+            ]
+            .into(),
+        ));
+        let join = Join::new(
+            users(),
+            teams,
+            (
+                ColumnRef::new("users", "team_id"),
+                ColumnRef::new("teams", "id"),
+            ),
+        );
+        let mut filter = Filter::new(
+            Box::new(join),
+            vec![planner::Filter::new(
+                "teams".to_string(),
+                "name".to_string(),
+                None,
+                Value::QuotedString("red".to_string()),
+            )],
+        );
+        let rows = drain(&mut filter).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0].get("users", "name"),
+            &FieldValue::Text("Alice".to_string())
+        );
+        assert_eq!(
+            rows[1].get("users", "name"),
+            &FieldValue::Text("Carol".to_string())
+        );
+        assert_eq!(rows[1].get("teams", "id"), &FieldValue::Int(10));
+    }
+}
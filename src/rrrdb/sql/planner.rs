@@ -1,8 +1,14 @@
-use std::todo;
+use std::{collections::HashMap, convert::TryFrom};
 
-use crate::rrrdb::{parser::*, schema::store::SchemaStore, FieldMetadata};
+use crate::rrrdb::{
+    parser::*,
+    schema::{statistics::TableStatistics, store::SchemaStore},
+    DBError, FieldMetadata,
+};
 use crate::rrrdb::{schema::*, storage::Storage};
 
+use super::evaluator::Evaluator;
+
 // SQL -> KVS requests
 pub(crate) struct Planner<'a> {
     database: Option<Database>,
@@ -16,28 +22,472 @@ pub(crate) enum Plan {
     InsertPlan(InsertPlan),
     CreateDatabasePlan(CreateDatabasePlan),
     CreateTablePlan(CreateTablePlan),
+    CreateSequencePlan(CreateSequencePlan),
+    TransactionPlan(TransactionStatement),
+    ExplainPlan(ExplainPlan),
+    AnalyzePlan(AnalyzePlan),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct SelectPlan {
     pub(crate) database: Database,
-    pub(crate) plans: Vec<SelectTablePlan>,
-    pub(crate) projections: Vec<ProjectionPlan>,
-    pub(crate) filters: Vec<Filter>,
+    pub(crate) root: PlanNode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct AnalyzePlan {
+    pub(crate) database_name: String,
+    pub(crate) tables: Vec<Table>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ExplainPlan {
+    pub(crate) plan: SelectPlan,
+    pub(crate) analyze: bool,
+}
+
+// a tree of physical operators, which the executor runs from the root
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum PlanNode {
+    Scan {
+        table: Table,
+    },
+    // rows whose indexed column equals the value
+    IndexScan {
+        table: Table,
+        index: IndexPlan,
+        value: Value,
+    },
+    Filter {
+        input: Box<PlanNode>,
+        filters: Vec<Filter>,
+    },
+    Project {
+        input: Box<PlanNode>,
+        projections: Vec<ProjectionPlan>,
+    },
+    Sort {
+        input: Box<PlanNode>,
+        keys: Vec<SortKey>,
+    },
+    Limit {
+        input: Box<PlanNode>,
+        limit: Option<usize>,
+        offset: usize,
+    },
+    Aggregate {
+        input: Box<PlanNode>,
+        group_by: Vec<ColumnRef>,
+        aggregates: Vec<AggregatePlan>,
+    },
+    // rows of both sides where the left column equals the right one
+    Join {
+        left: Box<PlanNode>,
+        right: Box<PlanNode>,
+        on: (ColumnRef, ColumnRef),
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum IndexPlan {
+    PrimaryKey,
+    Unique(UniqueConstraint),
+}
+
+impl IndexPlan {
+    pub fn column_name(&self) -> &str {
+        match self {
+            IndexPlan::PrimaryKey => Column::ID,
+            // only single-column indexes are planned
+            IndexPlan::Unique(unique) => &unique.columns[0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ColumnRef {
+    pub(crate) table_name: String,
+    pub(crate) column_name: String,
+}
+
+impl ColumnRef {
+    pub fn new(table_name: &str, column_name: &str) -> Self {
+        Self {
+            table_name: table_name.to_string(),
+            column_name: column_name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct SortKey {
+    pub(crate) column: ColumnRef,
+    pub(crate) descending: bool,
+}
+
+impl SortKey {
+    pub fn new(column: ColumnRef, descending: bool) -> Self {
+        Self { column, descending }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum AggregateFunction {
+    Count,
+    Sum,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct AggregatePlan {
+    pub(crate) function: AggregateFunction,
+    // None for COUNT(*)
+    pub(crate) column: Option<ColumnRef>,
+    pub(crate) name: String,
+}
+
+impl AggregatePlan {
+    pub fn new(function: AggregateFunction, column: Option<ColumnRef>, name: &str) -> Self {
+        Self {
+            function,
+            column,
+            name: name.to_string(),
+        }
+    }
+}
+
+// the rows a node is estimated to return, and the cost of returning them.
+// the cost is in rows read from a table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Estimate {
+    pub(crate) rows: f64,
+    pub(crate) cost: f64,
+}
+
+// estimates plans from the statistics collected by ANALYZE,
+// or from the row counts estimated by RocksDB for tables never analyzed
+pub(crate) struct CostModel {
+    tables: HashMap<String, (Table, f64, Option<TableStatistics>)>,
+}
+
+impl CostModel {
+    // a lookup through an index costs as much as reading a row
+    const INDEX_LOOKUP_COST: f64 = 1.0;
+    // processing a row read already
+    const ROW_COST: f64 = 0.01;
+    // of a condition nothing is known about
+    const DEFAULT_SELECTIVITY: f64 = 0.1;
+
+    pub fn load(
+        schema_store: &SchemaStore,
+        database_name: &str,
+        tables: Vec<&Table>,
+    ) -> Result<Self, DBError> {
+        let mut estimates = HashMap::new();
+        for table in tables {
+            let statistics = schema_store.find_statistics(database_name, &table.name)?;
+            let rows = match &statistics {
+                Some(statistics) => statistics.row_count,
+                None => schema_store.estimate_row_count(database_name, &table.name)?,
+            };
+            estimates.insert(table.name.clone(), (table.clone(), rows as f64, statistics));
+        }
+        Ok(Self { tables: estimates })
+    }
+
+    pub fn estimate(&self, node: &PlanNode) -> Estimate {
+        match node {
+            PlanNode::Scan { table } => {
+                let rows = self.table_rows(&table.name);
+                Estimate { rows, cost: rows }
+            }
+            PlanNode::IndexScan {
+                table,
+                index,
+                value,
+            } => {
+                let lookups = match index {
+                    IndexPlan::PrimaryKey => 1.0,
+                    // the index, then the table
+                    IndexPlan::Unique(_) => 2.0,
+                };
+                Estimate {
+                    rows: self.equal_rows(&table.name, index.column_name(), value),
+                    cost: lookups * Self::INDEX_LOOKUP_COST,
+                }
+            }
+            PlanNode::Filter { input, filters } => {
+                let input = self.estimate(input);
+                let selectivity: f64 = filters
+                    .into_iter()
+                    .map(|filter| self.selectivity(filter))
+                    .product();
+                Estimate {
+                    rows: input.rows * selectivity,
+                    cost: input.cost + input.rows * Self::ROW_COST,
+                }
+            }
+            PlanNode::Project { input, .. } => {
+                let input = self.estimate(input);
+                Estimate {
+                    rows: input.rows,
+                    cost: input.cost + input.rows * Self::ROW_COST,
+                }
+            }
+            PlanNode::Sort { input, .. } => {
+                let input = self.estimate(input);
+                Estimate {
+                    rows: input.rows,
+                    cost: input.cost + input.rows * input.rows.max(2.0).log2() * Self::ROW_COST,
+                }
+            }
+            PlanNode::Limit {
+                input,
+                limit,
+                offset,
+            } => {
+                let input = self.estimate(input);
+                let rows = (input.rows - *offset as f64).max(0.0);
+                Estimate {
+                    rows: limit.map_or(rows, |limit| rows.min(limit as f64)),
+                    cost: input.cost,
+                }
+            }
+            PlanNode::Aggregate {
+                input, group_by, ..
+            } => {
+                let input = self.estimate(input);
+                let groups = if group_by.is_empty() {
+                    1.0
+                } else {
+                    group_by
+                        .into_iter()
+                        .map(|column| self.distinct_count(column).unwrap_or(input.rows))
+                        .product::<f64>()
+                        .min(input.rows)
+                };
+                Estimate {
+                    rows: groups,
+                    cost: input.cost + input.rows * Self::ROW_COST,
+                }
+            }
+            PlanNode::Join { left, right, on } => {
+                let (left, right) = (self.estimate(left), self.estimate(right));
+                let selectivity = match (self.distinct_count(&on.0), self.distinct_count(&on.1)) {
+                    (None, None) => Self::DEFAULT_SELECTIVITY,
+                    (l, r) => 1.0 / l.unwrap_or(0.0).max(r.unwrap_or(0.0)).max(1.0),
+                };
+                Estimate {
+                    rows: left.rows * right.rows * selectivity,
+                    cost: left.cost + right.cost + left.rows * right.rows * Self::ROW_COST,
+                }
+            }
+        }
+    }
+
+    fn table_rows(&self, table_name: &str) -> f64 {
+        self.tables
+            .get(table_name)
+            .map_or(0.0, |(_, rows, _)| *rows)
+    }
+
+    fn distinct_count(&self, column: &ColumnRef) -> Option<f64> {
+        self.tables
+            .get(&column.table_name)
+            .and_then(|(_, _, statistics)| statistics.as_ref())
+            .and_then(|statistics| statistics.column(&column.column_name))
+            .map(|column| column.distinct_count as f64)
+    }
+
+    // the rows of the table holding the value in the column
+    fn equal_rows(&self, table_name: &str, column_name: &str, value: &Value) -> f64 {
+        let (table, rows, statistics) = match self.tables.get(table_name) {
+            Some(estimate) => estimate,
+            None => return 0.0,
+        };
+        let unique = column_name == Column::ID
+            || (&table.unique_constraints)
+                .into_iter()
+                .any(|unique| unique.columns == vec![column_name.to_string()]);
+        let column = statistics
+            .as_ref()
+            .and_then(|statistics| statistics.column(column_name));
+        let found = match (column, value) {
+            (_, Value::Null) => 0.0,
+            // a value known only on execution
+            (Some(column), Value::Placeholder(_)) => {
+                (rows - column.null_count as f64) / (column.distinct_count as f64).max(1.0)
+            }
+            (Some(column), value) => column.equal_rows(&value.to_string()),
+            (None, _) => rows * Self::DEFAULT_SELECTIVITY,
+        };
+        if unique {
+            found.min(1.0).min(*rows)
+        } else {
+            found
+        }
+    }
+
+    fn selectivity(&self, filter: &Filter) -> f64 {
+        if filter.json_extract.is_some() {
+            return Self::DEFAULT_SELECTIVITY;
+        }
+        let rows = self.table_rows(&filter.table_name);
+        if rows == 0.0 {
+            return Self::DEFAULT_SELECTIVITY;
+        }
+        self.equal_rows(
+            &filter.table_name,
+            &filter.column_name,
+            &filter.expected_value,
+        ) / rows
+    }
+}
+
+// a line of EXPLAIN, without the node's inputs
+impl std::fmt::Display for PlanNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |items: Vec<String>| items.join(", ");
+        match self {
+            PlanNode::Scan { table } => write!(f, "Scan on {}", table.name),
+            PlanNode::IndexScan {
+                table,
+                index,
+                value,
+            } => {
+                let index_name = match index {
+                    IndexPlan::PrimaryKey => "primary key",
+                    IndexPlan::Unique(unique) => &unique.name,
+                };
+                write!(
+                    f,
+                    "Index Scan using {} on {} ({} = {})",
+                    index_name,
+                    table.name,
+                    index.column_name(),
+                    literal(value)
+                )
+            }
+            PlanNode::Filter { filters, .. } => {
+                let filters: Vec<String> = filters.into_iter().map(|f| f.to_string()).collect();
+                write!(f, "Filter ({})", filters.join(" AND "))
+            }
+            PlanNode::Project { projections, .. } => write!(
+                f,
+                "Project ({})",
+                join(
+                    projections
+                        .into_iter()
+                        .map(|p| p.field_metadata().field_name)
+                        .collect()
+                )
+            ),
+            PlanNode::Sort { keys, .. } => write!(
+                f,
+                "Sort ({})",
+                join(
+                    keys.into_iter()
+                        .map(|key| match key.descending {
+                            true => format!("{} DESC", key.column),
+                            false => key.column.to_string(),
+                        })
+                        .collect()
+                )
+            ),
+            PlanNode::Limit { limit, offset, .. } => match limit {
+                Some(limit) => write!(f, "Limit ({} offset {})", limit, offset),
+                None => write!(f, "Limit (all offset {})", offset),
+            },
+            PlanNode::Aggregate {
+                group_by,
+                aggregates,
+                ..
+            } => {
+                let aggregates = join(aggregates.into_iter().map(|a| a.name.clone()).collect());
+                if group_by.is_empty() {
+                    write!(f, "Aggregate ({})", aggregates)
+                } else {
+                    let group_by = join(group_by.into_iter().map(|c| c.to_string()).collect());
+                    write!(f, "Aggregate ({}) group by ({})", aggregates, group_by)
+                }
+            }
+            PlanNode::Join { on, .. } => write!(f, "Nested Loop Join ({} = {})", on.0, on.1),
+        }
+    }
+}
+
+impl std::fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the output of an aggregate belongs to no table
+        if self.table_name.is_empty() {
+            return write!(f, "{}", self.column_name);
+        }
+        write!(f, "{}.{}", self.table_name, self.column_name)
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json_extract = self
+            .json_extract
+            .as_ref()
+            .map_or(String::new(), |json_extract| json_extract.to_string());
+        write!(
+            f,
+            "{}.{}{} = {}",
+            self.table_name,
+            self.column_name,
+            json_extract,
+            literal(&self.expected_value)
+        )
+    }
+}
+
+// a value as written in SQL
+fn literal(value: &Value) -> String {
+    match value {
+        Value::QuotedString(s) => format!("'{}'", s.replace('\'', "''")),
+        other => other.to_string(),
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct ProjectionPlan {
     pub(crate) table: Table,
     pub(crate) column: Column,
+    pub(crate) json_extract: Option<JsonExtractPlan>,
     // expression: Expression,
 }
 
+impl ProjectionPlan {
+    pub fn field_metadata(&self) -> FieldMetadata {
+        match &self.json_extract {
+            Some(json_extract) => {
+                let name = format!("{}{}", self.column.name, json_extract);
+                let field_type = if json_extract.as_text {
+                    ColumnType::Varchar
+                } else {
+                    ColumnType::Json
+                };
+                FieldMetadata::new(&name, &field_type.to_string())
+            }
+            None => FieldMetadata::new(&self.column.name, &self.column.column_type.to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub(crate) struct SelectTablePlan {
-    pub(crate) table: Table,
-    pub(crate) select_columns: Vec<Column>,
-    pub(crate) filter: Option<Filter>,
+pub(crate) struct JsonExtractPlan {
+    pub(crate) path: JsonPath,
+    pub(crate) as_text: bool,
+}
+
+impl std::fmt::Display for JsonExtractPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operator = if self.as_text { "->>" } else { "->" };
+        write!(f, "{}{}", operator, self.path.to_sql())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -50,6 +500,16 @@ pub(crate) struct CreateTablePlan {
     pub(crate) database_name: String,
     pub(crate) table_name: String,
     pub(crate) column_definitions: Vec<ColumnDefinition>,
+    pub(crate) unique_constraints: Vec<UniqueConstraint>,
+    pub(crate) foreign_keys: Vec<ForeignKeyConstraint>,
+    // backing the SERIAL and AUTO_INCREMENT columns
+    pub(crate) sequences: Vec<Sequence>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CreateSequencePlan {
+    pub(crate) database_name: String,
+    pub(crate) sequence: Sequence,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -57,6 +517,8 @@ pub(crate) struct InsertPlan {
     pub(crate) database: Database,
     pub(crate) table: Table,
     pub(crate) values: Vec<RecordValue>,
+    // values drawn from sequences, which are returned to the caller
+    pub(crate) generated_keys: Vec<RecordValue>,
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct RecordValue {
@@ -66,189 +528,410 @@ pub(crate) struct RecordValue {
 
 impl SelectPlan {
     pub fn result_metadata(&self) -> Vec<FieldMetadata> {
-        let projections = &self.projections;
-        let mut field_metadatas = (&self.plans).into_iter().fold(
-            vec![],
-            |mut metadatas,
-             SelectTablePlan {
-                 table,
-                 select_columns,
-                 filter,
-             }| {
-                select_columns
-                    .into_iter()
-                    .filter_map(|column| {
-                        projections.into_iter().enumerate().find_map(|(idx, p)| {
-                            if p.table.name == table.name && p.column.name == column.name {
-                                let metadata = FieldMetadata::new(
-                                    &column.name,
-                                    &column.column_type.to_string(),
-                                );
-                                Some((idx, metadata))
-                            } else {
-                                None
-                            }
-                        })
-                    })
-                    .for_each(|found| {
-                        metadatas.push(found);
-                    });
-                metadatas
-            },
-        );
-        field_metadatas.sort_by(|(idx_0, _), (idx_1, _)| idx_0.cmp(idx_1));
-        field_metadatas
+        self.root.result_metadata()
+    }
+}
+
+impl PlanNode {
+    pub fn result_metadata(&self) -> Vec<FieldMetadata> {
+        match self {
+            PlanNode::Scan { table } | PlanNode::IndexScan { table, .. } => (&table.columns)
+                .into_iter()
+                .map(|c| FieldMetadata::new(&c.name, &c.column_type.to_string()))
+                .collect(),
+            PlanNode::Filter { input, .. }
+            | PlanNode::Sort { input, .. }
+            | PlanNode::Limit { input, .. } => input.result_metadata(),
+            PlanNode::Project { projections, .. } => projections
+                .into_iter()
+                .map(|projection| projection.field_metadata())
+                .collect(),
+            PlanNode::Aggregate {
+                input,
+                group_by,
+                aggregates,
+            } => {
+                let fields = input.result_metadata();
+                let field = |column: &ColumnRef| {
+                    (&fields)
+                        .into_iter()
+                        .find(|field| field.field_name == column.column_name)
+                        .cloned()
+                };
+                let groups = group_by.into_iter().filter_map(|column| field(column));
+                let aggregates = aggregates.into_iter().map(|aggregate| {
+                    let field_type = match (&aggregate.function, &aggregate.column) {
+                        (AggregateFunction::Min, Some(column))
+                        | (AggregateFunction::Max, Some(column)) => field(column)
+                            .and_then(|field| field.field_type().ok())
+                            .unwrap_or(ColumnType::Integer),
+                        _ => ColumnType::Integer,
+                    };
+                    FieldMetadata::new(&aggregate.name, &field_type.to_string())
+                });
+                groups.chain(aggregates).collect()
+            }
+            PlanNode::Join { left, right, .. } => {
+                let mut fields = left.result_metadata();
+                fields.extend(right.result_metadata());
+                fields
+            }
+        }
+    }
+
+    // the tables read by the scans in the tree
+    pub fn tables(&self) -> Vec<&Table> {
+        self.explain()
             .into_iter()
-            .map(|(_, metadata)| metadata)
+            .filter_map(|(_, node)| match node {
+                PlanNode::Scan { table } | PlanNode::IndexScan { table, .. } => Some(table),
+                _ => None,
+            })
             .collect()
     }
+
+    // the nodes from the root, with their depth in the tree
+    pub fn explain(&self) -> Vec<(usize, &PlanNode)> {
+        let mut nodes = vec![];
+        self.walk(0, &mut nodes);
+        nodes
+    }
+
+    fn walk<'a>(&'a self, depth: usize, nodes: &mut Vec<(usize, &'a PlanNode)>) {
+        nodes.push((depth, self));
+        match self {
+            PlanNode::Scan { .. } | PlanNode::IndexScan { .. } => {}
+            PlanNode::Filter { input, .. }
+            | PlanNode::Project { input, .. }
+            | PlanNode::Sort { input, .. }
+            | PlanNode::Limit { input, .. }
+            | PlanNode::Aggregate { input, .. } => input.walk(depth + 1, nodes),
+            PlanNode::Join { left, right, .. } => {
+                left.walk(depth + 1, nodes);
+                right.walk(depth + 1, nodes);
+            }
+        }
+    }
+
+    // the values compared with rows, which are the ones placeholders are bound to
+    pub fn values_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            PlanNode::Scan { .. } => vec![],
+            PlanNode::IndexScan { value, .. } => vec![value],
+            PlanNode::Filter { input, filters } => {
+                let mut values = input.values_mut();
+                values.extend(filters.iter_mut().map(|filter| &mut filter.expected_value));
+                values
+            }
+            PlanNode::Project { input, .. }
+            | PlanNode::Sort { input, .. }
+            | PlanNode::Limit { input, .. }
+            | PlanNode::Aggregate { input, .. } => input.values_mut(),
+            PlanNode::Join { left, right, .. } => {
+                let mut values = left.values_mut();
+                values.extend(right.values_mut());
+                values
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct Filter {
     pub table_name: String,
     pub column_name: String,
+    pub json_extract: Option<JsonExtractPlan>,
     pub expected_value: Value,
 }
 impl Filter {
-    pub fn new(table_name: String, column_name: String, expected_value: Value) -> Self {
+    pub fn new(
+        table_name: String,
+        column_name: String,
+        json_extract: Option<JsonExtractPlan>,
+        expected_value: Value,
+    ) -> Self {
         Self {
             table_name,
             column_name,
+            json_extract,
             expected_value,
         }
     }
 }
 
 impl<'a> Planner<'a> {
-    pub fn new(database_name: &str, underlying: &'a mut Storage, sql: Statement) -> Self {
+    pub fn new(
+        database_name: &str,
+        underlying: &'a mut Storage,
+        sql: Statement,
+    ) -> Result<Self, DBError> {
         let schema_store = SchemaStore::new(underlying);
-        let database = match schema_store.find_schema(database_name) {
-            Ok(database) => database,
-            Err(err) => panic!("Unexpected error failed {:?}", err),
-        };
-
-        Self {
+        let database = schema_store.find_schema(database_name)?;
+        Ok(Self {
             database,
             schema_store,
             sql,
-        }
+        })
     }
 
-    pub fn plan(&mut self) -> Plan {
+    pub fn plan(&mut self) -> Result<Plan, DBError> {
         match &self.sql {
-            Statement::Select(query) => self.build_select_query_plan(query.clone()),
+            Statement::Select(query) => self
+                .build_select_query_plan(query.clone())
+                .map(Plan::SelectPlan),
             Statement::Insert(insert) => self.build_insert_plan(insert.clone()),
             Statement::CreateDatabase(create_database) => {
-                self.build_create_database_plan(create_database.clone())
+                Ok(self.build_create_database_plan(create_database.clone()))
             }
             Statement::CreateTable(create_table) => {
                 self.build_create_table_plan(create_table.clone())
             }
+            Statement::CreateSequence(create_sequence) => {
+                Ok(self.build_create_sequence_plan(create_sequence.clone()))
+            }
+            Statement::Transaction(transaction) => Ok(Plan::TransactionPlan(transaction.clone())),
+            Statement::Analyze { table_name } => self.build_analyze_plan(table_name.clone()),
+            Statement::Explain { statement, analyze } => {
+                let analyze = *analyze;
+                match statement.as_ref().clone() {
+                    Statement::Select(query) => Ok(Plan::ExplainPlan(ExplainPlan {
+                        plan: self.build_select_query_plan(query)?,
+                        analyze,
+                    })),
+                    other => Err(DBError::Unsupported(format!("EXPLAIN of {:?}", other))),
+                }
+            }
+            // handled by the session
+            Statement::Session(statement) => Err(DBError::Internal(format!(
+                "{:?} can't be planned",
+                statement
+            ))),
         }
     }
 
-    fn build_select_query_plan(&mut self, query: Query) -> Plan {
-        let database = self.database.clone().unwrap();
-        let mut tables: Vec<Table> = (&query.froms)
-            .into_iter()
-            .flat_map(|table_name| database.table(&table_name))
-            .collect();
-        let mut select_plan = SelectPlan {
-            database: database.clone(),
-            plans: vec![],
-            projections: vec![],
-            filters: vec![],
+    fn build_select_query_plan(&mut self, query: Query) -> Result<SelectPlan, DBError> {
+        // support only one table, so there is no join order to choose yet
+        let table_name = match &query.froms[..] {
+            [table_name] => table_name,
+            [] => return Err(DBError::Unsupported("SELECT without FROM".to_string())),
+            _ => {
+                return Err(DBError::Unsupported(
+                    "SELECT from multiple tables".to_string(),
+                ))
+            }
         };
-        (&query.projections)
-            .into_iter()
-            .for_each(|projection| match projection {
-                Projection::Expression(expr) => match expr {
-                    Expression::Ident(ident) => {
-                        let (table, column) = (&tables)
-                            .into_iter()
-                            .find_map(|t| t.column(ident).map(|c| (t, c)))
-                            .expect(&format!("Unknown identifier: {}", ident));
-                        let projection_plan = ProjectionPlan {
-                            table: table.clone(),
-                            column: column.clone(),
-                        };
-                        let select_table_plan = SelectTablePlan {
-                            table: table.clone(),
-                            select_columns: vec![column],
-                            filter: None,
-                        };
-                        select_plan.projections.push(projection_plan);
-                        select_plan.plans.push(select_table_plan);
-                    }
-                    Expression::Value(value) => {
-                        // not supported yet
-                        todo!()
-                    }
-                    Expression::BinOperator { lhs, rhs, op } => {
-                        // not supported yet
-                        todo!()
-                    }
-                },
+        let database = self
+            .database
+            .clone()
+            .ok_or_else(|| DBError::UnknownTable(table_name.to_string()).at(table_name.location))?;
+        let table = database
+            .table(table_name)
+            .ok_or_else(|| DBError::UnknownTable(table_name.to_string()).at(table_name.location))?;
+        let tables = vec![table];
+        let mut projections = vec![];
+        let mut filters = vec![];
+        for projection in &query.projections {
+            match projection {
+                Projection::Expression(expr) => {
+                    let (ident, json_extract) = match expr {
+                        Expression::Ident(ident) => (ident, None),
+                        Expression::JsonExtract {
+                            target,
+                            path,
+                            as_text,
+                        } => match target.as_ref() {
+                            Expression::Ident(ident) => (
+                                ident,
+                                Some(JsonExtractPlan {
+                                    path: path.clone(),
+                                    as_text: *as_text,
+                                }),
+                            ),
+                            // parser accepts only an identifier as a target
+                            _ => return Err(Self::unsupported("SELECT", expr)),
+                        },
+                        Expression::Value(_)
+                        | Expression::BinOperator { .. }
+                        | Expression::Function { .. } => {
+                            return Err(Self::unsupported("SELECT", expr))
+                        }
+                    };
+                    let (table, column) = Self::find_column(&tables, ident)?;
+                    projections.push(ProjectionPlan {
+                        table: table.clone(),
+                        column,
+                        json_extract,
+                    });
+                }
                 Projection::Wildcard => {
-                    assert!(query.froms.len() == 1);
-                    let table = (&tables)
-                        .into_iter()
-                        .next()
-                        .expect(&format!("table not found for {}", query.froms[0]));
+                    let table = &tables[0];
                     let projection_plans = (&table.columns).into_iter().map(|c| ProjectionPlan {
                         table: table.clone(),
                         column: c.clone(),
+                        json_extract: None,
                     });
-                    let columns = table.columns.clone();
-                    let select_table_plan = SelectTablePlan {
-                        table: table.clone(),
-                        select_columns: columns,
-                        filter: None,
-                    };
-                    select_plan.projections = projection_plans.collect();
-                    select_plan.plans.push(select_table_plan);
+                    projections = projection_plans.collect();
                 }
-            });
+            }
+        }
         if let Some(expr) = &query.predicate.expression {
             match expr {
-                Expression::BinOperator { lhs, rhs, op } => {
+                // filters compare for equality only, and so do index lookups
+                Expression::BinOperator { op, .. } if *op != BinaryOperator::Eq => {
+                    return Err(Self::unsupported("WHERE", expr))
+                }
+                Expression::BinOperator { lhs, rhs, .. } => {
                     match (lhs.as_ref(), rhs.as_ref()) {
                         (Expression::Ident(ident), Expression::Value(value)) => {
-                            select_plan.filters.push(self.build_filter(
+                            filters.push(self.build_filter(
                                 &tables,
-                                ident.to_owned(),
+                                ident.clone(),
+                                None,
                                 value.to_owned(),
-                            ));
+                            )?);
                         }
                         (Expression::Value(value), Expression::Ident(ident)) => {
-                            select_plan.filters.push(self.build_filter(
+                            filters.push(self.build_filter(
                                 &tables,
-                                ident.to_owned(),
+                                ident.clone(),
+                                None,
                                 value.to_owned(),
-                            ));
+                            )?);
                         }
-                        _ => todo!("not supported yet expression: {:?}", expr),
+                        (
+                            Expression::JsonExtract {
+                                target,
+                                path,
+                                as_text,
+                            },
+                            Expression::Value(value),
+                        )
+                        | (
+                            Expression::Value(value),
+                            Expression::JsonExtract {
+                                target,
+                                path,
+                                as_text,
+                            },
+                        ) => match target.as_ref() {
+                            Expression::Ident(ident) => {
+                                let json_extract = JsonExtractPlan {
+                                    path: path.to_owned(),
+                                    as_text: *as_text,
+                                };
+                                filters.push(self.build_filter(
+                                    &tables,
+                                    ident.clone(),
+                                    Some(json_extract),
+                                    value.to_owned(),
+                                )?);
+                            }
+                            _ => return Err(Self::unsupported("WHERE", expr)),
+                        },
+                        _ => return Err(Self::unsupported("WHERE", expr)),
                     };
                 }
-                _ => {
-                    // meaningless
-                }
+                // anything else would be dropped, returning every row
+                _ => return Err(Self::unsupported("WHERE", expr)),
             }
         };
-        Plan::SelectPlan(select_plan)
+        let cost_model = CostModel::load(&self.schema_store, &database.name, vec![&tables[0]])?;
+        let root = PlanNode::Project {
+            input: Box::new(Self::access_path(&cost_model, &tables[0], filters)),
+            projections,
+        };
+        Ok(SelectPlan { database, root })
     }
 
-    fn build_filter(&self, tables: &Vec<Table>, ident: String, value: Value) -> Filter {
-        let x = tables
+    // the cheapest of scanning the table and looking up the primary key or a single-column
+    // unique index for an equality filter on it.
+    // the filters are still applied to the rows found, so that values compare the same way
+    fn access_path(cost_model: &CostModel, table: &Table, filters: Vec<Filter>) -> PlanNode {
+        let indexes = (&table.unique_constraints)
             .into_iter()
-            .find_map(|t| t.column(&ident).map(|c| (t, c)));
-        let (table, left_column) = x.expect(&format!(
-            "Unknown identifier: {}, value: {:?}",
-            ident, value
-        ));
+            .filter(|unique| unique.columns.len() == 1)
+            .map(|unique| IndexPlan::Unique(unique.clone()));
+        let filtered = |input: PlanNode| PlanNode::Filter {
+            input: Box::new(input),
+            filters: filters.clone(),
+        };
+        let index_scans: Vec<PlanNode> = std::iter::once(IndexPlan::PrimaryKey)
+            .chain(indexes)
+            .filter_map(|index| {
+                let filter = (&filters).into_iter().find(|filter| {
+                    filter.table_name == table.name
+                        && filter.column_name == index.column_name()
+                        && filter.json_extract.is_none()
+                })?;
+                Some(filtered(PlanNode::IndexScan {
+                    table: table.clone(),
+                    value: filter.expected_value.clone(),
+                    index,
+                }))
+            })
+            .collect();
+        let mut best = filtered(PlanNode::Scan {
+            table: table.clone(),
+        });
+        let mut best_cost = cost_model.estimate(&best).cost;
+        // on a tie, an index wins over a scan, and the primary key over other indexes
+        for candidate in index_scans.into_iter().rev() {
+            let cost = cost_model.estimate(&candidate).cost;
+            if cost <= best_cost {
+                best = candidate;
+                best_cost = cost;
+            }
+        }
+        best
+    }
+
+    fn build_analyze_plan(&self, table_name: Option<String>) -> Result<Plan, DBError> {
+        let tables = match (&self.database, table_name) {
+            (Some(database), None) => database.tables.clone(),
+            (None, None) => vec![],
+            (database, Some(table_name)) => vec![database
+                .as_ref()
+                .and_then(|database| database.table(&table_name))
+                .ok_or(DBError::UnknownTable(table_name))?],
+        };
+        let database_name = self
+            .database
+            .as_ref()
+            .map_or(String::new(), |database| database.name.clone());
+        Ok(Plan::AnalyzePlan(AnalyzePlan {
+            database_name,
+            tables,
+        }))
+    }
+
+    fn build_filter(
+        &self,
+        tables: &Vec<Table>,
+        ident: Spanned<String>,
+        json_extract: Option<JsonExtractPlan>,
+        value: Value,
+    ) -> Result<Filter, DBError> {
+        let (table, _) = Self::find_column(tables, &ident)?;
+        Ok(Filter::new(
+            table.name.to_owned(),
+            ident.node,
+            json_extract,
+            value,
+        ))
+    }
+
+    fn find_column<'t>(
+        tables: &'t Vec<Table>,
+        ident: &Spanned<String>,
+    ) -> Result<(&'t Table, Column), DBError> {
+        tables
+            .into_iter()
+            .find_map(|t| t.column(ident).map(|c| (t, c)))
+            .ok_or_else(|| DBError::UnknownColumn(ident.to_string()).at(ident.location))
+    }
 
-        Filter::new(table.name.to_owned(), ident, value)
+    fn unsupported(clause: &str, expr: &Expression) -> DBError {
+        DBError::Unsupported(format!("expression in {}: {:?}", clause, expr))
     }
 
     fn build_create_database_plan(&mut self, create_database: CreateDatabase) -> Plan {
@@ -257,39 +940,374 @@ impl<'a> Planner<'a> {
         })
     }
 
-    fn build_create_table_plan(&mut self, create_table: CreateTable) -> Plan {
-        Plan::CreateTablePlan(CreateTablePlan {
+    fn build_create_table_plan(&mut self, create_table: CreateTable) -> Result<Plan, DBError> {
+        let table_name = create_table.table_name.clone();
+        // rejected before any column family is touched
+        if let Some(table) = self
+            .database
+            .as_ref()
+            .and_then(|database| database.table(&table_name))
+        {
+            return Err(DBError::AlreadyExists(format!("table {}", table.name)));
+        }
+        let column_uniques = (&create_table.column_definitions)
+            .into_iter()
+            .filter(|definition| definition.constraints.contains(&ColumnConstraint::Unique))
+            .map(|definition| {
+                UniqueConstraint::new(&table_name, None, vec![definition.name.clone()])
+            });
+        let table_uniques = (&create_table.constraints)
+            .into_iter()
+            .filter_map(|constraint| match constraint {
+                TableConstraint::Unique { name, columns } => Some(UniqueConstraint::new(
+                    &table_name,
+                    name.clone(),
+                    columns.clone(),
+                )),
+                TableConstraint::ForeignKey { .. } => None,
+            });
+        let unique_constraints: Vec<UniqueConstraint> =
+            column_uniques.chain(table_uniques).collect();
+        for (i, unique) in unique_constraints.iter().enumerate() {
+            if let Some(column_name) = unique.columns.iter().find(|column_name| {
+                !(&create_table.column_definitions)
+                    .into_iter()
+                    .any(|definition| &&definition.name == column_name)
+            }) {
+                return Err(DBError::InvalidDefinition(format!(
+                    "column {} in UNIQUE constraint {} not found in table {}",
+                    column_name, unique.name, table_name
+                )));
+            }
+            if unique_constraints[..i]
+                .iter()
+                .any(|u| u.name == unique.name)
+            {
+                return Err(DBError::InvalidDefinition(format!(
+                    "constraint {} is defined more than once",
+                    unique.name
+                )));
+            }
+        }
+        let foreign_keys = self.build_foreign_keys(&create_table, &unique_constraints)?;
+        for definition in &create_table.column_definitions {
+            ColumnType::try_from(definition.column_type.clone())?;
+        }
+        let sequences = (&create_table.column_definitions)
+            .into_iter()
+            .filter(|definition| definition.is_auto_increment())
+            .map(|definition| {
+                if ColumnType::try_from(definition.column_type.clone())? != ColumnType::Integer {
+                    return Err(DBError::InvalidDefinition(format!(
+                        "AUTO_INCREMENT column {} must be an integer, but got {}",
+                        definition.name, definition.column_type
+                    )));
+                }
+                Ok(Sequence::for_column(&table_name, &definition.name))
+            })
+            .collect::<Result<Vec<Sequence>, DBError>>()?;
+        Ok(Plan::CreateTablePlan(CreateTablePlan {
             database_name: create_table.database_name,
-            table_name: create_table.table_name,
+            table_name,
             column_definitions: create_table.column_definitions,
+            unique_constraints,
+            foreign_keys,
+            sequences,
+        }))
+    }
+
+    fn build_create_sequence_plan(&self, create_sequence: CreateSequence) -> Plan {
+        Plan::CreateSequencePlan(CreateSequencePlan {
+            database_name: create_sequence.database_name,
+            sequence: Sequence::new(
+                create_sequence.name,
+                create_sequence.start,
+                create_sequence.increment,
+            ),
         })
     }
-    fn build_insert_plan(&mut self, insert: Insert) -> Plan {
-        let database = self.database.clone().unwrap();
-        let table = database
-            .table(&insert.table_name)
-            .expect(&format!("table {} not found", insert.table_name));
-        let values = table
-            .columns
-            .iter()
-            .enumerate()
-            .map(|(i, column)| {
-                let column = column.to_owned();
-                let value = insert
-                    .values
-                    .get(i)
-                    .expect(&format!(
-                        "value is missing for column {} in given INSERT INTO statement",
-                        &column.name
-                    ))
-                    .to_owned();
-                RecordValue { column, value }
-            })
-            .collect();
-        Plan::InsertPlan(InsertPlan {
+
+    fn build_foreign_keys(
+        &self,
+        create_table: &CreateTable,
+        unique_constraints: &Vec<UniqueConstraint>,
+    ) -> Result<Vec<ForeignKeyConstraint>, DBError> {
+        let table_name = &create_table.table_name;
+        let column_references =
+            (&create_table.column_definitions)
+                .into_iter()
+                .flat_map(|definition| {
+                    (&definition.constraints)
+                        .into_iter()
+                        .filter_map(move |constraint| match constraint {
+                            ColumnConstraint::References(reference) => {
+                                Some((None, definition.name.clone(), reference))
+                            }
+                            _ => None,
+                        })
+                });
+        let table_references = (&create_table.constraints)
+            .into_iter()
+            .filter_map(|constraint| match constraint {
+                TableConstraint::ForeignKey {
+                    name,
+                    column,
+                    reference,
+                } => Some((name.clone(), column.clone(), reference)),
+                _ => None,
+            });
+
+        let mut foreign_keys: Vec<ForeignKeyConstraint> = vec![];
+        for (name, column_name, reference) in column_references.chain(table_references) {
+            let foreign_key = ForeignKeyConstraint::new(
+                table_name,
+                name,
+                column_name,
+                reference.table_name.clone(),
+                reference.column_name.clone(),
+                reference.on_delete.clone(),
+            );
+            let definition = (&create_table.column_definitions)
+                .into_iter()
+                .find(|definition| definition.name == foreign_key.column)
+                .ok_or(DBError::InvalidDefinition(format!(
+                    "column {} in FOREIGN KEY constraint {} not found in table {}",
+                    foreign_key.column, foreign_key.name, table_name
+                )))?;
+            if foreign_key.on_delete == ReferentialAction::SetNull
+                && definition.constraints.contains(&ColumnConstraint::NotNull)
+            {
+                return Err(DBError::InvalidDefinition(format!(
+                    "ON DELETE SET NULL of constraint {} conflicts with NOT NULL column {}",
+                    foreign_key.name, foreign_key.column
+                )));
+            }
+
+            // a table may reference itself, so look into the definition being created first
+            let (referenced_columns, referenced_uniques) =
+                if &foreign_key.referenced_table == table_name {
+                    let columns: Vec<String> = (&create_table.column_definitions)
+                        .into_iter()
+                        .map(|definition| definition.name.clone())
+                        .collect();
+                    (columns, unique_constraints.clone())
+                } else {
+                    let table = self
+                        .database
+                        .as_ref()
+                        .and_then(|database| database.table(&foreign_key.referenced_table))
+                        .ok_or(DBError::InvalidDefinition(format!(
+                            "table {} referenced by constraint {} not found",
+                            foreign_key.referenced_table, foreign_key.name
+                        )))?;
+                    let columns: Vec<String> = (&table.columns)
+                        .into_iter()
+                        .map(|c| c.name.clone())
+                        .collect();
+                    (columns, table.unique_constraints)
+                };
+            if !referenced_columns.contains(&foreign_key.referenced_column) {
+                return Err(DBError::InvalidDefinition(format!(
+                    "column {} referenced by constraint {} not found in table {}",
+                    foreign_key.referenced_column, foreign_key.name, foreign_key.referenced_table
+                )));
+            }
+            if foreign_key.referenced_column != Column::ID
+                && !referenced_uniques
+                    .iter()
+                    .any(|unique| unique.columns == vec![foreign_key.referenced_column.clone()])
+            {
+                return Err(DBError::InvalidDefinition(format!(
+                    "column {} referenced by constraint {} is neither the primary key nor UNIQUE in table {}",
+                    foreign_key.referenced_column, foreign_key.name, foreign_key.referenced_table
+                )));
+            }
+            if foreign_keys.iter().any(|fk| fk.name == foreign_key.name)
+                || unique_constraints
+                    .iter()
+                    .any(|u| u.name == foreign_key.name)
+            {
+                return Err(DBError::InvalidDefinition(format!(
+                    "constraint {} is defined more than once",
+                    foreign_key.name
+                )));
+            }
+            foreign_keys.push(foreign_key);
+        }
+        Ok(foreign_keys)
+    }
+    fn build_insert_plan(&mut self, insert: Insert) -> Result<Plan, DBError> {
+        let database = self.database.clone().ok_or_else(|| {
+            DBError::UnknownTable(insert.table_name.to_string()).at(insert.table_name.location)
+        })?;
+        let table = database.table(&insert.table_name).ok_or_else(|| {
+            DBError::UnknownTable(insert.table_name.to_string()).at(insert.table_name.location)
+        })?;
+        let given_values = Self::pair_insert_values(&table, &insert)?;
+        let mut values = Vec::with_capacity(table.columns.len());
+        let mut generated_keys = vec![];
+        for column in &table.columns {
+            let column = column.to_owned();
+            // a column omitted from the column list takes its default, or NULL
+            let given = given_values.get(&column.name);
+            let (value, generated) = match given {
+                Some(expr) => self
+                    .evaluate_insert_value(&database, expr)
+                    .map_err(|e| e.at(expr.location))?,
+                None => (column.default.clone().unwrap_or(Value::Null), false),
+            };
+            // and an AUTO_INCREMENT column takes the next value of its sequence instead of NULL
+            let (value, generated) = match (&column.sequence, value) {
+                (Some(sequence), Value::Null) => {
+                    let next = self.schema_store.next_value(&database.name, sequence)?;
+                    (Value::Number(next.to_string()), true)
+                }
+                (_, value) => (value, generated),
+            };
+            let value = Self::coerce_value(&column, &value)
+                .map_err(|e| e.at(given.and_then(|expr| expr.location)))?;
+            if column.not_null && value == Value::Null {
+                return Err(DBError::constraint_violation(
+                    &column.not_null_constraint_name(&table.name),
+                    format!("column {} must not be null", column.name),
+                ));
+            }
+            let record_value = RecordValue { column, value };
+            if generated {
+                generated_keys.push(record_value.clone());
+            }
+            values.push(record_value);
+        }
+        Self::check_constraints(&table, &values)?;
+        Ok(Plan::InsertPlan(InsertPlan {
             database,
             table,
             values,
-        })
+            generated_keys,
+        }))
+    }
+
+    // returns the value and whether it was drawn from a sequence
+    fn evaluate_insert_value(
+        &mut self,
+        database: &Database,
+        expr: &Expression,
+    ) -> Result<(Value, bool), DBError> {
+        match expr {
+            Expression::Value(value) => Ok((value.to_owned(), false)),
+            Expression::Function { name, args } if name.to_lowercase() == "nextval" => {
+                match &args[..] {
+                    [Expression::Value(Value::QuotedString(sequence))] => {
+                        let next = self.schema_store.next_value(&database.name, sequence)?;
+                        Ok((Value::Number(next.to_string()), true))
+                    }
+                    _ => Err(DBError::InvalidStatement(format!(
+                        "nextval expects a sequence name, but got {:?}",
+                        args
+                    ))),
+                }
+            }
+            Expression::Function { name, .. } => Err(DBError::UnknownFunction(name.to_owned())),
+            _ => Err(DBError::Unsupported(format!(
+                "expression in VALUES: {:?}",
+                expr
+            ))),
+        }
+    }
+
+    fn pair_insert_values(
+        table: &Table,
+        insert: &Insert,
+    ) -> Result<HashMap<String, Spanned<Expression>>, DBError> {
+        let column_names: Vec<Spanned<String>> = match &insert.columns {
+            Some(columns) => {
+                for (i, column_name) in columns.iter().enumerate() {
+                    if table.column(column_name).is_none() {
+                        return Err(DBError::UnknownColumn(format!(
+                            "{}.{}",
+                            table.name, column_name
+                        ))
+                        .at(column_name.location));
+                    }
+                    if columns[..i].contains(column_name) {
+                        return Err(DBError::InvalidStatement(format!(
+                            "column {} specified more than once",
+                            column_name
+                        ))
+                        .at(column_name.location));
+                    }
+                }
+                columns.clone()
+            }
+            None => (&table.columns)
+                .into_iter()
+                .map(|c| Spanned::from(c.name.clone()))
+                .collect(),
+        };
+        if let Some(extra) = insert.values.get(column_names.len()) {
+            return Err(DBError::InvalidStatement(format!(
+                "too many values for table {}. expected: {}, given: {}",
+                table.name,
+                column_names.len(),
+                insert.values.len()
+            ))
+            .at(extra.location));
+        }
+        if let Some(column_name) = column_names.get(insert.values.len()) {
+            return Err(DBError::InvalidStatement(format!(
+                "value is missing for column {} in given INSERT INTO statement",
+                column_name
+            )));
+        }
+        Ok(column_names
+            .into_iter()
+            .map(|column_name| column_name.node)
+            .zip(insert.values.iter().cloned())
+            .collect())
+    }
+
+    fn check_constraints(table: &Table, values: &Vec<RecordValue>) -> Result<(), DBError> {
+        let row: HashMap<String, Value> = values
+            .iter()
+            .map(|v| (v.column.name.clone(), v.value.clone()))
+            .collect();
+        let evaluator = Evaluator::new(&row);
+        for column in &table.columns {
+            if let Some(check) = &column.check {
+                if !evaluator.is_satisfied(check)? {
+                    return Err(DBError::constraint_violation(
+                        &column.check_constraint_name(&table.name),
+                        format!("row violates CHECK on column {}", column.name),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // check a given value against the column type, converting it into the stored representation
+    fn coerce_value(column: &Column, value: &Value) -> Result<Value, DBError> {
+        let mismatch = || DBError::type_mismatch(column, value);
+        match (&column.column_type, value) {
+            (ColumnType::Integer, Value::Number(n))
+            | (ColumnType::Integer, Value::QuotedString(n)) => n
+                .parse::<i64>()
+                .map(|i| Value::Number(i.to_string()))
+                .map_err(|_| mismatch()),
+            (ColumnType::Varchar, Value::QuotedString(_)) => Ok(value.to_owned()),
+            (ColumnType::Varchar, Value::Number(_)) | (ColumnType::Varchar, Value::Boolean(_)) => {
+                Ok(Value::QuotedString(value.to_string()))
+            }
+            // JSON documents are validated here and stored in their compact form
+            (ColumnType::Json, Value::QuotedString(_))
+            | (ColumnType::Json, Value::Number(_))
+            | (ColumnType::Json, Value::Boolean(_)) => {
+                serde_json::from_str::<serde_json::Value>(&value.to_string())
+                    .map(|json| Value::QuotedString(json.to_string()))
+                    .map_err(|_| mismatch())
+            }
+            (_, Value::Null) => Ok(Value::Null),
+            _ => Err(mismatch()),
+        }
     }
 }
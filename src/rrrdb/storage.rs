@@ -1,27 +1,371 @@
-use rocksdb::{ColumnFamily, DBIterator};
+use std::{
+    cell::{RefCell, UnsafeCell},
+    cmp::Ordering,
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    iter::Peekable,
+    mem::ManuallyDrop,
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use rocksdb::{
+    merge_operator::MergeOperands, ColumnFamily, ColumnFamilyDescriptor, DBIterator, Snapshot,
+    WriteBatch,
+};
 use serde::{de::DeserializeOwned, Serialize};
 
+use super::schema::{
+    store::{SchemaCache, SchemaStore},
+    Database,
+};
 use super::DBError;
 
 pub type DBResult<T> = Result<T, DBError>;
 
+// the RocksDB instance shared by every session, which read and write it concurrently.
+// rocksdb needs `&mut` only to create or drop a column family, which waits until no statement
+// has it pinned, and keeps sessions which hold no pin from taking one until it's done.
+pub struct SharedStorage {
+    rocksdb: UnsafeCell<rocksdb::DB>,
+    // locked while a column family is created or dropped
+    pins: Mutex<Pins>,
+    unpinned: Condvar,
+    // numbers commits, checking conflicts and applying writes at once
+    commit_log: Mutex<CommitLog>,
+    // CREATE statements run one at a time, so that no schema change gets lost
+    schema_lock: Mutex<()>,
+    schema_cache: SchemaCache,
+    recovery: Mutex<Vec<Recovery>>,
+}
+
+// what opening did to the column families, after a crash in CREATE TABLE or to upgrade them
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Recovery {
+    // of a table or an index no schema knows about
+    DroppedOrphan(String),
+    // of a table or an index in a schema
+    CreatedMissing(String),
+    // the rows of a table in a column family named `{database}_{table}` as before names were
+    // length-prefixed, which is dropped afterwards
+    Migrated { from: String, to: String },
+    // a schema saved as `{database}_schema` before keys were length-prefixed
+    MigratedSchema { from: String, to: String },
+    // as a schema can't be read
+    Skipped(String),
+}
+
+impl std::fmt::Display for Recovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recovery::DroppedOrphan(cf_name) => {
+                write!(f, "dropped orphan ColumnFamily({})", cf_name)
+            }
+            Recovery::CreatedMissing(cf_name) => {
+                write!(f, "created missing ColumnFamily({})", cf_name)
+            }
+            Recovery::Migrated { from, to } => {
+                write!(f, "moved ColumnFamily({}) into ColumnFamily({})", from, to)
+            }
+            Recovery::MigratedSchema { from, to } => {
+                write!(f, "moved schema {} to {}", from, to)
+            }
+            Recovery::Skipped(message) => {
+                write!(f, "left ColumnFamilies as they are: {}", message)
+            }
+        }
+    }
+}
+
+// `rocksdb` is reached only through `Pin::db`, `Storage::db` and `exclusive`, so sharing it is
+// sound:
+// - `&rocksdb::DB` is handed out only by a live pin, and never outlives it. `Pin::db` borrows
+//   the pin, and `Storage::db` borrows the session holding one, which takes `&mut self` to release
+//   it. snapshots and iterators borrowing it for longer are kept together with their pin
+// - `exclusive` hands out the only `&mut rocksdb::DB` with the pins locked and none alive, and no
+//   pin is taken until it unlocks them, so it never aliases a shared reference
+// - `rocksdb::DB` is Send and Sync itself, so shared references may be used across threads
+unsafe impl Sync for SharedStorage {}
+
+// pins are counted apart by how long they live. those of a statement are released when it ends,
+// and are waited for. those of a transaction's snapshot, or of rows streamed after their statement
+// ended, are released whenever the user gets to it, which may be never while waiting
+#[derive(Default)]
+struct Pins {
+    statements: usize,
+    lasting: usize,
+    // callers of `exclusive` waiting for the statements to end, which new statements wait for
+    // so that a steady stream of them can't keep a column family from being created
+    pending_exclusive: usize,
+}
+
+// keeps `rocksdb` from being changed, so references and snapshots of it stay valid
+struct Pin {
+    shared: Arc<SharedStorage>,
+    // changed with the pins locked
+    lasting: AtomicBool,
+}
+
+impl Pin {
+    fn db(&self) -> &rocksdb::DB {
+        unsafe { &*self.shared.rocksdb.get() }
+    }
+
+    // counted as lasting from now on, as it's kept after the statement ends
+    fn outlive_statement(&self) {
+        let mut pins = self.shared.lock_pins();
+        if !self.lasting.swap(true, AtomicOrdering::Relaxed) {
+            pins.statements -= 1;
+            pins.lasting += 1;
+            if pins.statements == 0 {
+                self.shared.unpinned.notify_all();
+            }
+        }
+    }
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        let mut pins = self.shared.lock_pins();
+        if self.lasting.load(AtomicOrdering::Relaxed) {
+            pins.lasting -= 1;
+        } else {
+            pins.statements -= 1;
+        }
+        if pins.statements == 0 {
+            self.shared.unpinned.notify_all();
+        }
+    }
+}
+
+// commits are numbered from 1, and the last one writing each key is remembered for as long as
+// writes based on an earlier commit may conflict with it
+#[derive(Default)]
+struct CommitLog {
+    sequence: u64,
+    last_commits: HashMap<(String, Vec<u8>), u64>,
+    // the commits snapshots and writes in progress are based on, with the number of each
+    bases: BTreeMap<u64, usize>,
+}
+
+impl CommitLog {
+    fn conflicts(&self, key: &(String, Vec<u8>), base: u64) -> bool {
+        self.last_commits
+            .get(key)
+            .map_or(false, |sequence| *sequence > base)
+    }
+
+    fn record<'a>(&mut self, keys: impl Iterator<Item = &'a (String, Vec<u8>)>) {
+        self.sequence += 1;
+        let sequence = self.sequence;
+        self.last_commits
+            .extend(keys.map(|key| (key.clone(), sequence)));
+        // nothing is based on a commit before the oldest base, so older ones never conflict
+        let oldest = self.bases.keys().next().copied();
+        self.last_commits
+            .retain(|_, sequence| oldest.map_or(false, |oldest| *sequence > oldest));
+    }
+}
+
+// a commit registered as a base while this is alive.
+// registered with the commit log locked, so that no later commit is forgotten in between
+struct Base {
+    sequence: u64,
+    shared: Arc<SharedStorage>,
+}
+
+impl Base {
+    fn new(shared: &Arc<SharedStorage>, commit_log: &mut CommitLog, sequence: u64) -> Self {
+        *commit_log.bases.entry(sequence).or_insert(0) += 1;
+        Self {
+            sequence,
+            shared: shared.clone(),
+        }
+    }
+}
+
+// locks the commit log, which must not be locked by the dropping thread
+impl Drop for Base {
+    fn drop(&mut self) {
+        let mut commit_log = self.shared.lock_commit_log();
+        if let Entry::Occupied(mut entry) = commit_log.bases.entry(self.sequence) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+// a session on SharedStorage, with the writes and the snapshot of its statement and transaction
 pub struct Storage {
-    rocksdb: rocksdb::DB,
+    transaction: Option<Transaction>,
+    statement: Option<WriteSet>,
+    // shared with the cursors reading it
+    statement_snapshot: Option<Arc<PinnedSnapshot>>,
+    // taken on the first access to `rocksdb`, and released when the statement ends
+    pin: RefCell<Option<Pin>>,
+    // for transactions to begin
+    isolation_level: IsolationLevel,
+    shared: Arc<SharedStorage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IsolationLevel {
+    // every statement reads the data committed before it starts
+    ReadCommitted,
+    // every statement reads the data committed before the transaction starts,
+    // and COMMIT fails when another one has changed the rows written by the transaction
+    Snapshot,
+}
+
+struct Transaction {
+    write_set: WriteSet,
+    isolation_level: IsolationLevel,
+    // taken at BEGIN under snapshot isolation
+    snapshot: Option<Arc<PinnedSnapshot>>,
+}
+
+// a snapshot with the pin keeping it valid, shared by a statement or a transaction and the cursors
+// reading it
+struct PinnedSnapshot {
+    // borrows `rocksdb` through the pin, and is dropped by `drop` before it
+    snapshot: ManuallyDrop<Snapshot<'static>>,
+    // the last commit the snapshot sees
+    base: Base,
+    pin: Pin,
+}
+
+impl PinnedSnapshot {
+    fn new(shared: &Arc<SharedStorage>, pin: Pin) -> Self {
+        // no commit is applied in between, so that the snapshot sees exactly its base
+        let mut commit_log = shared.lock_commit_log();
+        // the snapshot can't outlive the pin, as the pin is a field of the same struct
+        // which is dropped only after `drop` drops the snapshot
+        let snapshot =
+            unsafe { std::mem::transmute::<Snapshot<'_>, Snapshot<'static>>(pin.db().snapshot()) };
+        let sequence = commit_log.sequence;
+        let base = Base::new(shared, &mut commit_log, sequence);
+        drop(commit_log);
+        Self {
+            snapshot: ManuallyDrop::new(snapshot),
+            base,
+            pin,
+        }
+    }
+}
+
+impl Drop for PinnedSnapshot {
+    fn drop(&mut self) {
+        // the fields, and so the pin, are dropped after this
+        unsafe { ManuallyDrop::drop(&mut self.snapshot) }
+    }
+}
+
+// writes buffered until they are applied with a single WriteBatch, indexed so that reads see them.
+// RocksDB 0.15 has neither TransactionDB nor WriteBatchWithIndex, so this plays the latter.
+#[derive(Default)]
+struct WriteSet {
+    writes: BTreeMap<(String, Vec<u8>), Write>,
+    // the earliest base of the writes
+    base: Option<Base>,
+}
+
+struct Write {
+    // None for a deletion
+    value: Option<Vec<u8>>,
+    // the last commit seen by the write. applying fails if someone else has written the key since
+    base: u64,
+}
+
+impl WriteSet {
+    fn get(&self, cf_name: &str, key: &str) -> Option<&Option<Vec<u8>>> {
+        self.writes
+            .get(&(cf_name.to_string(), key.as_bytes().to_vec()))
+            .map(|write| &write.value)
+    }
+
+    fn writes_in(&self, cf_name: &str) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        self.writes
+            .range((cf_name.to_string(), vec![])..)
+            .take_while(|((cf, _), _)| cf == cf_name)
+            .map(|((_, key), write)| (key.clone(), write.value.clone()))
+            .collect()
+    }
+
+    // later writes win, but the earliest base is kept for the conflict check
+    fn extend(&mut self, other: WriteSet) {
+        let WriteSet { writes, base } = other;
+        if self.base.is_none() {
+            self.base = base;
+        }
+        for (key, write) in writes {
+            match self.writes.entry(key) {
+                Entry::Occupied(mut entry) => entry.get_mut().value = write.value,
+                Entry::Vacant(entry) => {
+                    entry.insert(write);
+                }
+            }
+        }
+    }
 }
 
 pub struct RecordIterator<'a> {
-    db_iterator: DBIterator<'a>,
+    db_iterator: Peekable<DBIterator<'a>>,
+    // uncommitted writes of the current statement and transaction, which shadow the stored records
+    writes: Peekable<std::vec::IntoIter<(Vec<u8>, Option<Vec<u8>>)>>,
 }
 impl<'a> Iterator for RecordIterator<'a> {
     type Item = (String, Box<[u8]>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let n = self.db_iterator.next();
-        n.map(|(key, value)| {
+        loop {
+            let order = match (self.db_iterator.peek(), self.writes.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((key, _)), Some((written_key, _))) => key.as_ref().cmp(written_key),
+            };
+            let (key, value) = match order {
+                Ordering::Less => self.db_iterator.next()?,
+                Ordering::Equal | Ordering::Greater => {
+                    if order == Ordering::Equal {
+                        self.db_iterator.next();
+                    }
+                    match self.writes.next()? {
+                        (key, Some(value)) => (key.into_boxed_slice(), value.into_boxed_slice()),
+                        // deleted in the transaction
+                        (_, None) => continue,
+                    }
+                }
+            };
             let string_key = String::from_utf8(key.to_vec())
                 .expect(&format!("Invalid key was found. key: {:?}", key));
-            (string_key, value)
-        })
+            return Some((string_key, value));
+        }
+    }
+}
+
+// a RecordIterator sharing the snapshot it reads, so that it outlives the statement
+pub(crate) struct Cursor {
+    // borrows the snapshot, and is dropped by `drop` before it
+    iterator: ManuallyDrop<RecordIterator<'static>>,
+    snapshot: Arc<PinnedSnapshot>,
+}
+impl Iterator for Cursor {
+    type Item = (String, Box<[u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iterator.next()
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        // the fields, and so the snapshot, are dropped after this
+        unsafe { ManuallyDrop::drop(&mut self.iterator) }
     }
 }
 
@@ -29,7 +373,15 @@ impl<'a> Iterator for RecordIterator<'a> {
 pub enum Namespace {
     Metadata,
     Database(String),
-    Table { database_name: String, name: String },
+    Table {
+        database_name: String,
+        name: String,
+    },
+    Index {
+        database_name: String,
+        table_name: String,
+        name: String,
+    },
 }
 
 impl Namespace {
@@ -42,6 +394,15 @@ impl Namespace {
             name: name.to_string(),
         }
     }
+    pub(crate) fn index(database_name: &str, table_name: &str, name: &str) -> Namespace {
+        Namespace::Index {
+            database_name: database_name.to_string(),
+            table_name: table_name.to_string(),
+            name: name.to_string(),
+        }
+    }
+    // names are prefixed with their length, e.g. `7:test_db_5:users` for the table `users` of
+    // `test_db`, so that no two tables or indexes share a column family whatever their names are
     pub(crate) fn cf_name(&self) -> String {
         match self {
             Namespace::Metadata => String::from("metadata"),
@@ -49,13 +410,50 @@ impl Namespace {
             Namespace::Table {
                 database_name,
                 name,
-            } => format!("{}_{}", database_name, name),
+            } => Self::encode(&[database_name, name]),
+            Namespace::Index {
+                database_name,
+                table_name,
+                name,
+            } => Self::encode(&[database_name, table_name, name]),
+        }
+    }
+
+    pub(crate) fn encode(names: &[&str]) -> String {
+        names
+            .into_iter()
+            .map(|name| format!("{}:{}", name.len(), name))
+            .collect::<Vec<String>>()
+            .join("_")
+    }
+
+    // whether the column family is named like a table's or an index's
+    fn is_encoded(mut cf_name: &str) -> bool {
+        let mut names = 0;
+        loop {
+            let colon = match cf_name.find(':') {
+                Some(colon) if colon > 0 => colon,
+                _ => return false,
+            };
+            if !cf_name[..colon].bytes().all(|b| b.is_ascii_digit()) {
+                return false;
+            }
+            let len = match cf_name[..colon].parse::<usize>() {
+                Ok(len) => len,
+                Err(_) => return false,
+            };
+            names += 1;
+            match cf_name[colon + 1..].get(len..) {
+                Some("") => return names == 2 || names == 3,
+                Some(rest) if rest.starts_with('_') => cf_name = &rest[1..],
+                _ => return false,
+            }
         }
     }
 }
 
-impl Storage {
-    pub fn new(path: &str) -> Storage {
+impl SharedStorage {
+    pub fn open(path: &str) -> DBResult<Arc<SharedStorage>> {
         let mut options = rocksdb::Options::default();
         options.set_error_if_exists(false);
         options.create_if_missing(true);
@@ -65,58 +463,660 @@ impl Storage {
             .iter()
             .find(|cf| *cf == &Namespace::Metadata.cf_name())
             .is_none();
-        let rocksdb = rocksdb::DB::open_cf(&options, path, cfs).unwrap();
+        let descriptors = cfs
+            .iter()
+            .map(|cf| ColumnFamilyDescriptor::new(cf, Storage::column_family_options(cf)));
+        let rocksdb = rocksdb::DB::open_cf_descriptors(&options, path, descriptors)?;
 
-        let mut instance = Storage { rocksdb };
+        let shared = Arc::new(SharedStorage {
+            rocksdb: UnsafeCell::new(rocksdb),
+            pins: Mutex::new(Pins::default()),
+            unpinned: Condvar::new(),
+            commit_log: Mutex::new(CommitLog::default()),
+            schema_lock: Mutex::new(()),
+            schema_cache: SchemaCache::default(),
+            recovery: Mutex::new(vec![]),
+        });
+        let mut instance = Storage::session(&shared);
         if need_to_create_metadata_cf {
-            instance
-                .create_column_family(Namespace::Metadata.cf_name().as_ref())
-                .unwrap();
+            instance.create_column_family(Namespace::Metadata.cf_name().as_ref())?;
         }
+        let recovery = instance.reconcile(cfs)?;
+        *shared
+            .recovery
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = recovery;
 
-        instance
+        Ok(shared)
+    }
+
+    // what was done to the column families on opening
+    pub fn recovery(&self) -> Vec<Recovery> {
+        self.recovery
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // waits for pending callers of `exclusive` unless `wait` is false, as it must be for a session
+    // holding a pin already, which they wait for in turn
+    fn pin(self: &Arc<Self>, lasting: bool, wait: bool) -> Pin {
+        let mut pins = self.lock_pins();
+        while wait && pins.pending_exclusive > 0 {
+            pins = self
+                .unpinned
+                .wait(pins)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if lasting {
+            pins.lasting += 1;
+        } else {
+            pins.statements += 1;
+        }
+        Pin {
+            shared: self.clone(),
+            lasting: AtomicBool::new(lasting),
+        }
+    }
+
+    fn lock_pins(&self) -> MutexGuard<'_, Pins> {
+        self.pins.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // statements may take a while, e.g. to check a constraint, but never wait for anything
+    const EXCLUSIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    // waits for the statements pinning `rocksdb` to end. meanwhile, sessions which hold no pin
+    // wait to take one until `f` is done or this gives up, and those holding one go on.
+    // fails at once while lasting pins are held, as they may be held by the caller itself,
+    // e.g. by rows being streamed on the same thread, or for as long as a transaction goes on
+    fn exclusive<T>(&self, f: impl FnOnce(&mut rocksdb::DB) -> T) -> DBResult<T> {
+        let deadline = Instant::now() + Self::EXCLUSIVE_TIMEOUT;
+        let mut pins = self.lock_pins();
+        pins.pending_exclusive += 1;
+        let result = loop {
+            if pins.lasting > 0 {
+                break Err(DBError::ObjectInUse(
+                    "ColumnFamilies can't be changed while rows are being streamed \
+                     or a transaction reads a snapshot"
+                        .to_string(),
+                ));
+            }
+            if pins.statements == 0 {
+                break Ok(f(unsafe { &mut *self.rocksdb.get() }));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break Err(DBError::ObjectInUse(
+                    "ColumnFamilies can't be changed while other statements are running"
+                        .to_string(),
+                ));
+            }
+            pins = self
+                .unpinned
+                .wait_timeout(pins, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        };
+        pins.pending_exclusive -= 1;
+        self.unpinned.notify_all();
+        result
+    }
+
+    pub(crate) fn has_column_family(self: &Arc<Self>, cf_name: &str) -> bool {
+        self.pin(false, true).db().cf_handle(cf_name).is_some()
+    }
+
+    fn lock_commit_log(&self) -> MutexGuard<'_, CommitLog> {
+        self.commit_log
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn lock_schema(&self) -> MutexGuard<'_, ()> {
+        self.schema_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Storage {
+    pub fn new(path: &str) -> DBResult<Storage> {
+        Ok(Self::session(&SharedStorage::open(path)?))
+    }
+
+    pub fn session(shared: &Arc<SharedStorage>) -> Storage {
+        Storage {
+            transaction: None,
+            statement: None,
+            statement_snapshot: None,
+            pin: RefCell::new(None),
+            isolation_level: IsolationLevel::ReadCommitted,
+            shared: shared.clone(),
+        }
+    }
+
+    fn db(&self) -> &rocksdb::DB {
+        if self.pin.borrow().is_none() {
+            let pin = self.new_pin(false);
+            *self.pin.borrow_mut() = Some(pin);
+        }
+        // valid until the pin is released, which takes `&mut self`
+        unsafe { &*self.shared.rocksdb.get() }
+    }
+
+    // waits for a column family to be created or dropped only if the session holds no pin yet,
+    // as `exclusive` would wait for that one in turn
+    fn new_pin(&self, lasting: bool) -> Pin {
+        let holds_pin = self.pin.borrow().is_some()
+            || self.statement_snapshot.is_some()
+            || self
+                .transaction
+                .as_ref()
+                .map_or(false, |transaction| transaction.snapshot.is_some());
+        self.shared.pin(lasting, !holds_pin)
+    }
+
+    pub(crate) fn schema_cache(&self) -> &SchemaCache {
+        &self.shared.schema_cache
+    }
+
+    // CREATE TABLE creates column families before saving the schema, so a crash in between
+    // leaves column families no schema knows about. drop them, and create the missing ones.
+    // only column families named like a table's or an index's are dropped, and none at all
+    // unless every schema can be read, as they may hold data otherwise
+    fn reconcile(&mut self, cf_names: Vec<String>) -> DBResult<Vec<Recovery>> {
+        let mut schema_store = SchemaStore::new(self);
+        let schemas = schema_store
+            .migrate_legacy_schemas()
+            .and_then(|moved| Ok((moved, schema_store.find_all_schemas()?)));
+        let (moved, schemas) = match schemas {
+            Ok(schemas) => schemas,
+            Err(DBError::Corruption(message)) => return Ok(vec![Recovery::Skipped(message)]),
+            Err(err) => return Err(err),
+        };
+        let expected: Vec<String> = (&schemas)
+            .into_iter()
+            .flat_map(|database| Self::column_family_names(database))
+            .collect();
+        let mut cf_names = cf_names;
+        let mut recovery: Vec<Recovery> = moved
+            .into_iter()
+            .map(|(from, to)| Recovery::MigratedSchema { from, to })
+            .collect();
+        for cf_name in &cf_names {
+            if Namespace::is_encoded(cf_name) && !expected.contains(cf_name) {
+                self.drop_column_family(cf_name)?;
+                recovery.push(Recovery::DroppedOrphan(cf_name.to_string()));
+            }
+        }
+        // `a` + `b_c` and `a_b` + `c` shared `a_b_c`, so both tables take its rows as they read them
+        let legacy: Vec<(String, String)> = (&schemas)
+            .into_iter()
+            .flat_map(|database| {
+                (&database.tables).into_iter().map(move |table| {
+                    (
+                        format!("{}_{}", database.name, table.name),
+                        Namespace::table(&database.name, &table.name).cf_name(),
+                    )
+                })
+            })
+            .filter(|(from, _)| cf_names.contains(from) && !expected.contains(from))
+            .collect();
+        for (from, to) in &legacy {
+            if !cf_names.contains(to) {
+                self.create_column_family(to)?;
+                cf_names.push(to.to_string());
+            }
+            self.copy_column_family(from, to)?;
+            recovery.push(Recovery::Migrated {
+                from: from.to_string(),
+                to: to.to_string(),
+            });
+        }
+        for (from, _) in &legacy {
+            if self.has_column_family(from) {
+                self.drop_column_family(from)?;
+            }
+        }
+        for cf_name in &expected {
+            if !cf_names.contains(cf_name) {
+                self.create_column_family(cf_name)?;
+                recovery.push(Recovery::CreatedMissing(cf_name.to_string()));
+            }
+        }
+        Ok(recovery)
+    }
+
+    // with a single WriteBatch, so `to` is left either empty or complete by a crash. a complete
+    // one is left as it is, as `from` is dropped only after every copy
+    fn copy_column_family(&self, from: &str, to: &str) -> DBResult<()> {
+        let db = self.db();
+        let cf_handle = |cf_name: &str| {
+            db.cf_handle(cf_name).ok_or(DBError::Storage(format!(
+                "ColumnFamily({}) not found",
+                cf_name
+            )))
+        };
+        let (from, to) = (cf_handle(from)?, cf_handle(to)?);
+        if db
+            .iterator_cf(to, rocksdb::IteratorMode::Start)
+            .next()
+            .is_some()
+        {
+            return Ok(());
+        }
+        let mut batch = WriteBatch::default();
+        for (key, value) in db.iterator_cf(from, rocksdb::IteratorMode::Start) {
+            batch.put_cf(to, key, value);
+        }
+        db.write(batch)?;
+        Ok(())
+    }
+
+    fn column_family_names(database: &Database) -> Vec<String> {
+        (&database.tables)
+            .into_iter()
+            .flat_map(|table| {
+                let indexes = (&table.unique_constraints).into_iter().map(move |unique| {
+                    Namespace::index(&database.name, &table.name, &unique.name).cf_name()
+                });
+                std::iter::once(Namespace::table(&database.name, &table.name).cf_name())
+                    .chain(indexes)
+            })
+            .collect()
+    }
+
+    pub(crate) fn has_column_family(&self, cf_name: &str) -> bool {
+        self.db().cf_handle(cf_name).is_some()
+    }
+
+    // metadata holds sequence counters, which are incremented by merging
+    fn column_family_options(cf_name: &str) -> rocksdb::Options {
+        let mut options = rocksdb::Options::default();
+        if cf_name == Namespace::Metadata.cf_name() {
+            options.set_merge_operator_associative("rrrdb.counter", Self::add_counter);
+        }
+        options
+    }
+
+    // counters and operands are decimal strings, and a missing counter is 0
+    fn add_counter(
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &mut MergeOperands,
+    ) -> Option<Vec<u8>> {
+        let parse = |bytes: &[u8]| {
+            std::str::from_utf8(bytes)
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+        };
+        let mut counter = match existing {
+            Some(existing) => parse(existing)?,
+            None => 0,
+        };
+        for operand in operands {
+            counter += parse(operand)?;
+        }
+        Some(counter.to_string().into_bytes())
     }
 
     pub(crate) fn create_column_family(&mut self, cf_name: &str) -> DBResult<()> {
-        let options = rocksdb::Options::default();
-        self.rocksdb
-            .create_cf(cf_name, &options)
+        self.release_pin()?;
+        let options = Self::column_family_options(cf_name);
+        self.shared
+            .exclusive(|db| db.create_cf(cf_name, &options))?
             .map_err(|e| DBError::from(e))
     }
 
+    pub(crate) fn drop_column_family(&mut self, cf_name: &str) -> DBResult<()> {
+        self.release_pin()?;
+        self.shared
+            .exclusive(|db| db.drop_cf(cf_name))?
+            .map_err(|e| DBError::from(e))
+    }
+
+    // column families can't be changed while this session pins `rocksdb`,
+    // so the statement goes on reading the latest data
+    fn release_pin(&mut self) -> DBResult<()> {
+        if self
+            .transaction
+            .as_ref()
+            .map_or(false, |transaction| transaction.snapshot.is_some())
+        {
+            return Err(DBError::InvalidTransactionState(
+                "column families can't be changed inside a transaction".to_string(),
+            ));
+        }
+        self.end_statement();
+        Ok(())
+    }
+
+    // releases the pins of the statement. those of the rows still being streamed are kept
+    fn end_statement(&mut self) {
+        if let Some(snapshot) = self.statement_snapshot.take() {
+            if Arc::strong_count(&snapshot) > 1 {
+                snapshot.pin.outlive_statement();
+            }
+        }
+        *self.pin.get_mut() = None;
+    }
+
+    // the transaction's snapshot under snapshot isolation, or the statement's one
+    fn read_snapshot(&self) -> Option<&PinnedSnapshot> {
+        self.transaction
+            .as_ref()
+            .and_then(|transaction| transaction.snapshot.as_deref())
+            .or(self.statement_snapshot.as_deref())
+    }
+
     fn get_column_family(&self, namespace: &Namespace) -> DBResult<&ColumnFamily> {
         let cf_name = namespace.cf_name();
-        self.rocksdb
+        self.db()
             .cf_handle(&cf_name)
             .ok_or(DBError::namespace_not_found(namespace))
     }
 
-    // fn db<'a>(&'a self, namespace: &Namespace) -> &'a rocksdb::DB {
-    //     match self.cfs.get(&namespace.cf_name()) {
-    //         Some(db) => db,
-    //         None => {
-    //             panic!("There is no open Column Family for {:?}", namespace)
-    //         }
-    //     }
-    // }
-
-    // fn db<'a>(&'a mut self, namespace: &Namespace) -> &'a rocksdb::DB {
-    //     let key = namespace.cf_name();
-    //     self.cfs
-    //         .entry(key.clone())
-    //         .or_insert(self.factory.open_column_family(self.default_db, &key))
-    // }
+    // RocksDB's estimate of the keys in the namespace, without uncommitted writes
+    pub fn estimate_num_keys(&self, namespace: &Namespace) -> DBResult<Option<u64>> {
+        let cf = self.get_column_family(namespace)?;
+        self.db()
+            .property_int_value_cf(cf, "rocksdb.estimate-num-keys")
+            .map_err(|e| DBError::from(e))
+    }
 
     // pub fn iterate<'a>(&'a self, namespace: &Namespace) -> DBIterator<'a> {
     pub fn iterator<'a>(&'a self, namespace: &Namespace) -> DBResult<RecordIterator<'a>> {
-        self.get_column_family(namespace).map(|cf| RecordIterator {
-            db_iterator: self.rocksdb.iterator_cf(cf, rocksdb::IteratorMode::Start),
+        let cf = self.get_column_family(namespace)?;
+        let db_iterator = match self.read_snapshot() {
+            Some(pinned) => pinned
+                .snapshot
+                .iterator_cf(cf, rocksdb::IteratorMode::Start),
+            None => self.db().iterator_cf(cf, rocksdb::IteratorMode::Start),
+        };
+        Ok(RecordIterator {
+            db_iterator: db_iterator.peekable(),
+            writes: self.uncommitted_writes(namespace),
+        })
+    }
+
+    // reads the transaction's snapshot under snapshot isolation, or the statement's one
+    pub(crate) fn cursor(&self, namespace: &Namespace) -> DBResult<Cursor> {
+        let snapshot = match self
+            .transaction
+            .as_ref()
+            .and_then(|transaction| transaction.snapshot.as_ref())
+            .or(self.statement_snapshot.as_ref())
+        {
+            Some(snapshot) => snapshot.clone(),
+            None => Arc::new(PinnedSnapshot::new(&self.shared, self.new_pin(true))),
+        };
+        let cf = snapshot
+            .pin
+            .db()
+            .cf_handle(&namespace.cf_name())
+            .ok_or(DBError::namespace_not_found(namespace))?;
+        let db_iterator = snapshot
+            .snapshot
+            .iterator_cf(cf, rocksdb::IteratorMode::Start);
+        // the iterator borrows the snapshot and the column family for as long as the Cursor
+        // lives, so 'static is sound even though it's longer than both:
+        // - the snapshot lives in the Arc's allocation, which doesn't move when the Cursor does,
+        //   and is kept alive by `snapshot` until `drop` has dropped the iterator
+        // - the snapshot's pin keeps the column family from being dropped, as it keeps `rocksdb`
+        //   from being changed at all
+        // - the Cursor yields owned keys and values, so no borrow with the 'static lifetime
+        //   escapes it
+        let db_iterator =
+            unsafe { std::mem::transmute::<DBIterator<'_>, DBIterator<'static>>(db_iterator) };
+        Ok(Cursor {
+            iterator: ManuallyDrop::new(RecordIterator {
+                db_iterator: db_iterator.peekable(),
+                writes: self.uncommitted_writes(namespace),
+            }),
+            snapshot,
         })
     }
 
+    fn uncommitted_writes(
+        &self,
+        namespace: &Namespace,
+    ) -> Peekable<std::vec::IntoIter<(Vec<u8>, Option<Vec<u8>>)>> {
+        // the statement's writes are newer than the transaction's
+        let mut writes = BTreeMap::new();
+        let transaction = self.transaction.as_ref().map(|t| &t.write_set);
+        for write_set in transaction.into_iter().chain(self.statement.iter()) {
+            writes.extend(write_set.writes_in(&namespace.cf_name()));
+        }
+        writes
+            .into_iter()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .peekable()
+    }
+
     pub fn get(&self, namespace: &Namespace, key: &str) -> DBResult<Option<Vec<u8>>> {
-        self.get_column_family(namespace)
-            .and_then(|cf| self.rocksdb.get_cf(cf, key).map_err(|e| DBError::from(e)))
+        let transaction = self.transaction.as_ref().map(|t| &t.write_set);
+        if let Some(written) = self
+            .statement
+            .iter()
+            .chain(transaction.into_iter())
+            .find_map(|write_set| write_set.get(&namespace.cf_name(), key))
+        {
+            return Ok(written.clone());
+        }
+        self.get_committed(namespace, key)
+    }
+
+    // reads through the snapshot, ignoring uncommitted writes
+    fn get_committed(&self, namespace: &Namespace, key: &str) -> DBResult<Option<Vec<u8>>> {
+        let cf = self.get_column_family(namespace)?;
+        match self.read_snapshot() {
+            Some(pinned) => pinned.snapshot.get_cf(cf, key),
+            None => self.db().get_cf(cf, key),
+        }
+        .map_err(|e| DBError::from(e))
+    }
+
+    // reads the latest committed value, regardless of snapshots and uncommitted writes
+    pub fn get_latest(&self, namespace: &Namespace, key: &str) -> DBResult<Option<Vec<u8>>> {
+        let cf = self.get_column_family(namespace)?;
+        self.db().get_cf(cf, key).map_err(|e| DBError::from(e))
+    }
+
+    pub(crate) fn is_written(&self, namespace: &Namespace, key: &str) -> bool {
+        let transaction = self.transaction.as_ref().map(|t| &t.write_set);
+        self.statement
+            .iter()
+            .chain(transaction.into_iter())
+            .any(|write_set| write_set.get(&namespace.cf_name(), key).is_some())
+    }
+
+    // writes until commit_statement or rollback_statement are applied all or nothing,
+    // and reads see the data committed before the statement starts
+    pub fn begin_statement(&mut self) {
+        self.statement = Some(WriteSet::default());
+        let pin = self.new_pin(false);
+        self.statement_snapshot = Some(Arc::new(PinnedSnapshot::new(&self.shared, pin)));
+    }
+
+    // hands the writes over to the transaction in progress, or applies them
+    pub fn commit_statement(&mut self) -> DBResult<()> {
+        let result = match self.statement.take() {
+            None => Ok(()),
+            Some(statement) => match self.transaction.as_mut() {
+                Some(transaction) => {
+                    transaction.write_set.extend(statement);
+                    Ok(())
+                }
+                None => self.apply(statement),
+            },
+        };
+        self.end_statement();
+        result
+    }
+
+    pub fn rollback_statement(&mut self) {
+        self.statement = None;
+        self.end_statement();
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    pub fn begin(&mut self) -> DBResult<()> {
+        if self.in_transaction() {
+            return Err(DBError::InvalidTransactionState(
+                "there is already a transaction in progress".to_string(),
+            ));
+        }
+        self.transaction = Some(Transaction {
+            write_set: WriteSet::default(),
+            isolation_level: self.isolation_level,
+            snapshot: self.transaction_snapshot(self.isolation_level),
+        });
+        Ok(())
+    }
+
+    // holds its own pin, as it lives across statements
+    fn transaction_snapshot(&self, isolation_level: IsolationLevel) -> Option<Arc<PinnedSnapshot>> {
+        match isolation_level {
+            IsolationLevel::Snapshot => Some(Arc::new(PinnedSnapshot::new(
+                &self.shared,
+                self.new_pin(true),
+            ))),
+            IsolationLevel::ReadCommitted => None,
+        }
+    }
+
+    // applies to the transaction in progress, which must not have written anything yet,
+    // or to the transactions beginning afterwards
+    pub fn set_isolation_level(&mut self, isolation_level: IsolationLevel) -> DBResult<()> {
+        let snapshot = self.transaction_snapshot(isolation_level);
+        match self.transaction.as_mut() {
+            Some(transaction) => {
+                if !transaction.write_set.writes.is_empty() {
+                    return Err(DBError::InvalidTransactionState(
+                        "isolation level must be set before any write in the transaction"
+                            .to_string(),
+                    ));
+                }
+                transaction.isolation_level = isolation_level;
+                transaction.snapshot = snapshot;
+            }
+            None => self.isolation_level = isolation_level,
+        }
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> DBResult<()> {
+        let transaction = self
+            .transaction
+            .take()
+            .ok_or(DBError::InvalidTransactionState(
+                "there is no transaction in progress".to_string(),
+            ))?;
+        self.apply(transaction.write_set)
+    }
+
+    pub fn rollback(&mut self) -> DBResult<()> {
+        self.transaction
+            .take()
+            .map(|_| ())
+            .ok_or(DBError::InvalidTransactionState(
+                "there is no transaction in progress".to_string(),
+            ))
+    }
+
+    // applies the buffered writes atomically. the first committer wins: no one else may have
+    // committed a key since the commit the write is based on, or their write would be lost.
+    // commits are compared rather than values, so that a key changed back and forth conflicts too
+    fn apply(&mut self, write_set: WriteSet) -> DBResult<()> {
+        // unregistered after the commit log is unlocked, as it locks the log
+        let WriteSet {
+            writes,
+            base: _base,
+        } = write_set;
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let mut commit_log = self.shared.lock_commit_log();
+        let db = self.db();
+        let mut batch = WriteBatch::default();
+        for (cf_key, write) in &writes {
+            let (cf_name, key) = cf_key;
+            let cf = db.cf_handle(cf_name).ok_or(DBError::Storage(format!(
+                "ColumnFamily({}) not found",
+                cf_name
+            )))?;
+            if commit_log.conflicts(cf_key, write.base) {
+                return Err(DBError::SerializationFailure(format!(
+                    "concurrent update of {} in ColumnFamily({})",
+                    String::from_utf8_lossy(key),
+                    cf_name
+                )));
+            }
+            match &write.value {
+                Some(value) => batch.put_cf(cf, key, value),
+                None => batch.delete_cf(cf, key),
+            }
+        }
+        db.write(batch)?;
+        commit_log.record(writes.keys());
+        drop(commit_log);
+        for (cf_name, key) in writes.keys() {
+            self.invalidate_cache(cf_name, key);
+        }
+        Ok(())
+    }
+
+    fn invalidate_cache(&self, cf_name: &str, key: &[u8]) {
+        if cf_name == Namespace::Metadata.cf_name() {
+            self.shared
+                .schema_cache
+                .invalidate(&String::from_utf8_lossy(key));
+        }
+    }
+
+    // buffers the write while a statement or a transaction is in progress
+    fn write(&mut self, namespace: &Namespace, key: &str, value: Option<Vec<u8>>) -> DBResult<()> {
+        let cf_key = (namespace.cf_name(), key.as_bytes().to_vec());
+        if self.statement.is_none() && self.transaction.is_none() {
+            let cf = self.get_column_family(namespace)?;
+            let mut commit_log = self.shared.lock_commit_log();
+            match value {
+                Some(value) => self.db().put_cf(cf, key, value),
+                None => self.db().delete_cf(cf, key),
+            }?;
+            commit_log.record(std::iter::once(&cf_key));
+            drop(commit_log);
+            self.invalidate_cache(&cf_key.0, &cf_key.1);
+            return Ok(());
+        }
+        // based on the snapshot read, or on the latest commit without one
+        let snapshot_base = self.read_snapshot().map(|pinned| pinned.base.sequence);
+        let shared = &self.shared;
+        let transaction = self.transaction.as_mut().map(|t| &mut t.write_set);
+        if let Some(write_set) = self.statement.as_mut().or(transaction) {
+            let base = match (snapshot_base, &write_set.base) {
+                (Some(base), Some(_)) => base,
+                _ => {
+                    let mut commit_log = shared.lock_commit_log();
+                    let base = snapshot_base.unwrap_or(commit_log.sequence);
+                    if write_set.base.is_none() {
+                        write_set.base = Some(Base::new(shared, &mut commit_log, base));
+                    }
+                    base
+                }
+            };
+            write_set
+                .writes
+                .entry(cf_key)
+                .or_insert(Write { value: None, base })
+                .value = value;
+        }
+        Ok(())
     }
 
     pub fn get_serialized<T: DeserializeOwned>(
@@ -124,24 +1124,52 @@ impl Storage {
         namespace: &Namespace,
         key: &str,
     ) -> Result<Option<T>, DBError> {
-        self.get(namespace, key).and_then(|opt| match opt {
+        self.get(namespace, key).and_then(Self::deserialize)
+    }
+
+    pub fn get_latest_serialized<T: DeserializeOwned>(
+        &self,
+        namespace: &Namespace,
+        key: &str,
+    ) -> Result<Option<T>, DBError> {
+        self.get_latest(namespace, key).and_then(Self::deserialize)
+    }
+
+    fn deserialize<T: DeserializeOwned>(found: Option<Vec<u8>>) -> Result<Option<T>, DBError> {
+        match found {
             Some(found) => match String::from_utf8(found) {
                 Ok(s) => match serde_json::from_str::<T>(&s) {
                     Ok(t) => Ok(Some(t)),
-                    Err(err) => Err(DBError::from(format!("Failed to deserialize: {:?}", err))),
+                    Err(err) => Err(DBError::Corruption(format!(
+                        "Failed to deserialize: {:?}",
+                        err
+                    ))),
                 },
-                Err(err) => Err(DBError::from(format!("Failed to deserialize: {:?}", err))),
+                Err(err) => Err(DBError::Corruption(format!(
+                    "Failed to deserialize: {:?}",
+                    err
+                ))),
             },
             None => Ok(None),
-        })
+        }
     }
 
     pub fn put(&mut self, namespace: &Namespace, key: &str, value: Vec<u8>) -> DBResult<()> {
-        self.get_column_family(namespace).and_then(|cf| {
-            self.rocksdb
-                .put_cf(cf, key, value)
-                .map_err(|e| DBError::from(e))
-        })
+        self.write(namespace, key, Some(value))
+    }
+
+    pub fn delete(&mut self, namespace: &Namespace, key: &str) -> DBResult<()> {
+        self.write(namespace, key, None)
+    }
+
+    // adds 1 to the counter and returns the sum, never as part of a transaction, like sequences
+    // in other databases. merged and read under the commit lock, so that no other increment
+    // comes in between and two callers never get the same sum
+    pub fn increment(&mut self, namespace: &Namespace, key: &str) -> DBResult<Option<Vec<u8>>> {
+        let cf = self.get_column_family(namespace)?;
+        let _commit_log = self.shared.lock_commit_log();
+        self.db().merge_cf(cf, key, "1")?;
+        self.db().get_cf(cf, key).map_err(|e| DBError::from(e))
     }
 
     pub fn put_serialized<T: Serialize + std::fmt::Debug>(
@@ -152,7 +1180,7 @@ impl Storage {
     ) -> Result<(), DBError> {
         match serde_json::to_string(&value) {
             Ok(serialized) => self.put(namespace, &key, serialized.into_bytes()),
-            Err(err) => Err(DBError::from(format!(
+            Err(err) => Err(DBError::Internal(format!(
                 "Failed to serialize to String. T: {:?}, err: {:?}",
                 value, err
             ))),
@@ -166,6 +1194,7 @@ mod tests {
     use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
     use super::DBError;
+    use super::IsolationLevel;
     use super::Namespace;
     use super::Storage;
 
@@ -177,7 +1206,7 @@ mod tests {
         std::fs::remove_dir_all(path).unwrap();
         std::fs::create_dir(path).unwrap();
         let namespace = Namespace::Metadata;
-        let mut instance = Storage::new(path);
+        let mut instance = Storage::new(path).unwrap();
 
         let res1 = instance.get(&namespace, "key-1");
         println!("res1: {:?}", res1);
@@ -207,6 +1236,191 @@ mod tests {
         assert!(res5.unwrap().unwrap() == user);
     }
 
+    #[test]
+    fn test_statement() {
+        let path = "./tmp/statement";
+        if std::path::Path::new(path).exists() {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+        std::fs::create_dir_all(path).unwrap();
+        let namespace = Namespace::Metadata;
+        let mut instance = Storage::new(path).unwrap();
+        instance
+            .put(&namespace, "key-1", "value-1".to_string().into_bytes())
+            .unwrap();
+
+        // a failed statement leaves nothing
+        instance.begin_statement();
+        instance
+            .put(&namespace, "key-2", "value-2".to_string().into_bytes())
+            .unwrap();
+        instance.delete(&namespace, "key-1").unwrap();
+        assert!(instance.get(&namespace, "key-1").unwrap().is_none());
+        assert_eq!(
+            instance
+                .iterator(&namespace)
+                .unwrap()
+                .map(|(k, _)| k)
+                .collect::<Vec<String>>(),
+            vec!["key-2".to_string()]
+        );
+        instance.rollback_statement();
+        assert!(instance.get(&namespace, "key-1").unwrap().is_some());
+        assert!(instance.get(&namespace, "key-2").unwrap().is_none());
+
+        // a statement in a transaction is applied on COMMIT
+        instance.begin().unwrap();
+        instance.begin_statement();
+        instance
+            .put(&namespace, "key-2", "value-2".to_string().into_bytes())
+            .unwrap();
+        instance.commit_statement().unwrap();
+        instance.begin_statement();
+        instance.delete(&namespace, "key-2").unwrap();
+        instance.rollback_statement();
+        assert!(instance.get(&namespace, "key-2").unwrap().is_some());
+        instance.rollback().unwrap();
+        assert!(instance.get(&namespace, "key-2").unwrap().is_none());
+
+        instance.begin_statement();
+        instance
+            .put(&namespace, "key-2", "value-2".to_string().into_bytes())
+            .unwrap();
+        instance.commit_statement().unwrap();
+        assert_eq!(
+            instance.get(&namespace, "key-2").unwrap(),
+            Some("value-2".as_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn test_isolation_level() {
+        let path = "./tmp/isolation_level";
+        if std::path::Path::new(path).exists() {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+        std::fs::create_dir_all(path).unwrap();
+        let namespace = Namespace::Metadata;
+        let mut instance = Storage::new(path).unwrap();
+        let mut other = Storage::session(&instance.shared);
+        // a write committed by another session
+        let concurrent_put = |other: &mut Storage, value: &str| {
+            other
+                .put(&namespace, "key-1", value.to_string().into_bytes())
+                .unwrap();
+        };
+        let get = |instance: &Storage| {
+            String::from_utf8(instance.get(&namespace, "key-1").unwrap().unwrap()).unwrap()
+        };
+        concurrent_put(&mut other, "value-1");
+
+        // a statement doesn't see writes committed after it starts
+        instance.begin_statement();
+        concurrent_put(&mut other, "value-2");
+        assert_eq!(get(&instance), "value-1");
+        assert_eq!(instance.iterator(&namespace).unwrap().count(), 1);
+        instance.commit_statement().unwrap();
+        assert_eq!(get(&instance), "value-2");
+
+        // READ COMMITTED reads the latest data in every statement
+        instance.begin().unwrap();
+        concurrent_put(&mut other, "value-3");
+        instance.begin_statement();
+        assert_eq!(get(&instance), "value-3");
+        instance
+            .put(&namespace, "key-1", "value-4".to_string().into_bytes())
+            .unwrap();
+        instance.commit_statement().unwrap();
+        instance.commit().unwrap();
+        assert_eq!(get(&instance), "value-4");
+
+        // SNAPSHOT reads the data as of BEGIN, and the first committer wins
+        instance
+            .set_isolation_level(IsolationLevel::Snapshot)
+            .unwrap();
+        instance.begin().unwrap();
+        concurrent_put(&mut other, "value-5");
+        instance.begin_statement();
+        assert_eq!(get(&instance), "value-4");
+        instance
+            .put(&namespace, "key-1", "value-6".to_string().into_bytes())
+            .unwrap();
+        instance.commit_statement().unwrap();
+        assert!(instance.commit().is_err());
+        assert!(!instance.in_transaction());
+        assert_eq!(get(&instance), "value-5");
+
+        // even when the value has been changed back
+        instance.begin().unwrap();
+        concurrent_put(&mut other, "value-1");
+        concurrent_put(&mut other, "value-5");
+        instance
+            .put(&namespace, "key-1", "value-6".to_string().into_bytes())
+            .unwrap();
+        assert!(matches!(
+            instance.commit(),
+            Err(DBError::SerializationFailure(_))
+        ));
+        assert_eq!(get(&instance), "value-5");
+
+        instance.begin().unwrap();
+        instance
+            .put(&namespace, "key-1", "value-6".to_string().into_bytes())
+            .unwrap();
+        assert!(instance
+            .set_isolation_level(IsolationLevel::ReadCommitted)
+            .is_err());
+        instance.commit().unwrap();
+        assert_eq!(get(&instance), "value-6");
+    }
+
+    #[test]
+    fn test_exclusive() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::{thread, time::Duration};
+
+        let path = "./tmp/exclusive";
+        if std::path::Path::new(path).exists() {
+            std::fs::remove_dir_all(path).unwrap();
+        }
+        std::fs::create_dir_all(path).unwrap();
+        let namespace = Namespace::Metadata;
+        let mut instance = Storage::new(path).unwrap();
+        let shared = instance.shared.clone();
+        instance.begin_statement();
+
+        let creating = {
+            let shared = shared.clone();
+            thread::spawn(move || Storage::session(&shared).create_column_family("created"))
+        };
+        while shared.lock_pins().pending_exclusive == 0 {
+            thread::yield_now();
+        }
+        // a statement beginning meanwhile waits for the column family to be created
+        let begun = Arc::new(AtomicBool::new(false));
+        let beginning = {
+            let (shared, begun) = (shared.clone(), begun.clone());
+            thread::spawn(move || {
+                let mut other = Storage::session(&shared);
+                other.begin_statement();
+                begun.store(true, Ordering::SeqCst);
+                other.commit_statement().unwrap();
+            })
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert!(!begun.load(Ordering::SeqCst));
+
+        // while the one in progress goes on, as the creation waits for it
+        assert!(instance.get(&namespace, "key-1").unwrap().is_none());
+        assert_eq!(instance.iterator(&namespace).unwrap().count(), 0);
+        instance.commit_statement().unwrap();
+        creating.join().unwrap().unwrap();
+        beginning.join().unwrap();
+        assert!(begun.load(Ordering::SeqCst));
+        assert!(shared.has_column_family("created"));
+    }
+
     #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
     pub(crate) struct User {
         pub name: String,